@@ -0,0 +1,82 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Measures listing of the ids kept by a large `DiskStorage`. Run it with and
+//! without the `rayon` feature to compare serial and parallel parsing of the
+//! file names:
+//!
+//! ```sh
+//! cargo bench --bench scan --features server
+//! cargo bench --bench scan --features server,rayon
+//! ```
+//!
+//! Number of the stored geneses defaults to 100 000 and may be changed with
+//! `RGB_BENCH_ENTRIES` environment variable.
+
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
+
+use amplify::Wrapper;
+use bitcoin::hashes::Hash;
+use rgb::ContractId;
+use rgb_node::stashd::storage::{DiskStorage, DiskStorageConfig, Store};
+
+const ROUNDS: usize = 5;
+
+fn measure(name: &str, mut f: impl FnMut() -> usize) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut count = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        count = f();
+        best = best.min(start.elapsed());
+    }
+    println!(
+        "{:<16} {:>8} entries {:>10.1} ms",
+        name,
+        count,
+        best.as_secs_f64() * 1000.0
+    );
+}
+
+fn main() {
+    let entries = env::var("RGB_BENCH_ENTRIES")
+        .ok()
+        .and_then(|entries| entries.parse().ok())
+        .unwrap_or(100_000usize);
+    let data_dir = env::temp_dir().join(format!("rgb-node-bench-scan-{}", process::id()));
+    let _ = fs::remove_dir_all(&data_dir);
+
+    let config = DiskStorageConfig::new(data_dir.clone());
+    let storage = DiskStorage::new(config.clone()).expect("storage");
+    // Listings only parse the file names, so the files may stay empty
+    for no in 0..entries {
+        let id = ContractId::from_inner(Hash::hash(&(no as u64).to_be_bytes()));
+        fs::write(config.genesis_filename(&id), b"").expect("genesis file");
+    }
+
+    println!(
+        "Listing {} geneses, rayon feature is {}",
+        entries,
+        if cfg!(feature = "rayon") { "on" } else { "off" }
+    );
+    measure("contract_ids", || {
+        storage.contract_ids().expect("ids").len()
+    });
+    measure("contract_count", || {
+        storage.contract_count().expect("count")
+    });
+
+    drop(storage);
+    fs::remove_dir_all(data_dir).expect("cleanup");
+}
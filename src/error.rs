@@ -85,6 +85,8 @@ pub enum ServiceErrorDomain {
 
     Storage(String),
 
+    NotFound,
+
     Index(String),
 
     #[cfg(feature = "fungibles")]
@@ -127,6 +129,10 @@ pub enum ServiceErrorDomain {
     Internal(String),
 }
 
+impl From<strict_encoding::Error> for ServiceErrorDomain {
+    fn from(err: strict_encoding::Error) -> Self { ServiceErrorDomain::Storage(err.to_string()) }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Display)]
 #[display(Debug)]
 #[non_exhaustive]
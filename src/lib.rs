@@ -41,6 +41,10 @@ extern crate serde_crate as serde;
 #[cfg(feature = "serde")]
 #[macro_use]
 extern crate serde_with;
+#[cfg(feature = "rayon")]
+extern crate rayon_crate as rayon;
+#[cfg(feature = "tokio")]
+extern crate tokio_crate;
 
 #[cfg(all(feature = "fungibles", feature = "sql"))]
 #[macro_use]
@@ -0,0 +1,228 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeSet;
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::{Consignment, ContractId, Node, NodeId, Transition};
+
+use super::index::{self, Index, OrphanReport};
+use super::storage::Store;
+use crate::error::ServiceErrorDomain;
+
+/// Stash data composed of a [`Store`] keeping the objects and an [`Index`]
+/// resolving anchors of the stored nodes, which provides the queries and the
+/// updates involving both of them.
+///
+/// Anchors added and removed through the stash are indexed and unindexed
+/// together with their storage, so the index stays in sync with the store.
+/// Errors of both layers are converted into [`ServiceErrorDomain`].
+///
+/// Not to be confused with [`rgb::Stash`] trait, which is implemented by the
+/// stash daemon runtime.
+#[derive(Debug)]
+pub struct StashView<S: Store, I: Index> {
+    store: S,
+    index: I,
+}
+
+impl<S: Store, I: Index> StashView<S, I> {
+    pub fn new(store: S, index: I) -> Self { Self { store, index } }
+
+    #[inline]
+    pub fn store(&self) -> &S { &self.store }
+
+    #[inline]
+    pub fn index(&self) -> &I { &self.index }
+
+    /// Gives mutable access to the store. Anchors changed this way must be
+    /// changed in the index as well, or fixed with [`StashView::rebuild_index`].
+    #[inline]
+    pub fn store_mut(&mut self) -> &mut S { &mut self.store }
+
+    #[inline]
+    pub fn into_inner(self) -> (S, I) { (self.store, self.index) }
+
+    /// Loads anchor of the state transition or extension, resolving its id
+    /// with the index. Returns `None` if the index does not know the node;
+    /// an index entry pointing to an anchor which is absent from the store is
+    /// reported as [`ServiceErrorDomain::NotFound`].
+    pub fn anchor_for(
+        &self,
+        node_id: &NodeId,
+    ) -> Result<Option<Anchor<MerkleBlock>>, ServiceErrorDomain> {
+        let anchor_id = match self.index.anchor_id_by_transition_id(*node_id) {
+            Ok(anchor_id) => anchor_id,
+            Err(err) if I::is_not_found(&err) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        self.store
+            .anchor(&anchor_id)
+            .map(Some)
+            .map_err(S::Error::into)
+    }
+
+    /// Loads all state transitions of the contract; see
+    /// [`Store::transitions_by_contract`]
+    #[inline]
+    pub fn transitions_for(
+        &self,
+        contract_id: &ContractId,
+    ) -> Result<Vec<Transition>, ServiceErrorDomain> {
+        self.store.transitions_by_contract(contract_id, &self.index)
+    }
+
+    /// Checks which nodes of the consignment are absent from the store,
+    /// returning their ids; an empty set means that the whole consignment is
+    /// already known. Genesis is reported under its node id.
+    pub fn validate_presence(
+        &self,
+        consignment: &Consignment,
+    ) -> Result<BTreeSet<NodeId>, ServiceErrorDomain> {
+        let mut missing = BTreeSet::new();
+        let genesis = &consignment.genesis;
+        if !self
+            .store
+            .has_genesis(&genesis.contract_id())
+            .map_err(S::Error::into)?
+        {
+            missing.insert(genesis.node_id());
+        }
+
+        let transition_ids: Vec<NodeId> = consignment
+            .state_transitions
+            .iter()
+            .map(|(_, transition)| transition.node_id())
+            .collect();
+        let present = self
+            .store
+            .has_transitions(&transition_ids)
+            .map_err(S::Error::into)?;
+        missing.extend(
+            transition_ids
+                .into_iter()
+                .filter(|node_id| !present.contains(node_id)),
+        );
+
+        for extension in consignment.state_extensions.iter() {
+            let node_id = extension.node_id();
+            if !self.store.has_extension(&node_id).map_err(S::Error::into)? {
+                missing.insert(node_id);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Stores and indexes the anchor, returning whether it was already
+    /// stored. The anchor is indexed first, so an anchor conflicting with the
+    /// indexed ones is not stored.
+    pub fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, ServiceErrorDomain> {
+        self.index.index_anchor(anchor).map_err(I::Error::into)?;
+        self.store.add_anchor(anchor).map_err(S::Error::into)
+    }
+
+    /// Removes the anchor both from the store and the index, returning
+    /// whether it was stored
+    pub fn remove_anchor(&mut self, anchor_id: &AnchorId) -> Result<bool, ServiceErrorDomain> {
+        let anchor = match self.store.anchor(anchor_id) {
+            Ok(anchor) => anchor,
+            Err(err) if S::is_not_found(&err) => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        self.index.remove_anchor(&anchor).map_err(I::Error::into)?;
+        self.store.remove_anchor(anchor_id).map_err(S::Error::into)
+    }
+
+    /// Re-indexes all the stored anchors; see [`index::rebuild`]
+    #[inline]
+    pub fn rebuild_index(&mut self) -> Result<usize, ServiceErrorDomain> {
+        index::rebuild(&self.store, &mut self.index)
+    }
+
+    /// Cross-checks the store against the index; see [`index::find_orphans`]
+    #[inline]
+    pub fn find_orphans(&self) -> Result<OrphanReport, ServiceErrorDomain> {
+        index::find_orphans(&self.store, &self.index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rgb::{Extension, Genesis};
+
+    use super::*;
+    use crate::stashd::index::{BTreeIndex, BTreeIndexConfig};
+    use crate::stashd::storage::{sample_anchor, MemoryStorage};
+    use crate::util::TempPath;
+
+    fn stash_view(index_dir: &Path) -> StashView<MemoryStorage, BTreeIndex> {
+        let index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_owned())).unwrap();
+        StashView::new(MemoryStorage::new(), index)
+    }
+
+    #[test]
+    fn test_stash_anchors() {
+        let index_dir = TempPath::new("stash-anchors");
+        let mut stash = stash_view(&index_dir);
+        let node_id = Transition::default().node_id();
+        let anchor = sample_anchor(0, &[node_id]);
+        stash
+            .store_mut()
+            .add_transition(&Transition::default())
+            .unwrap();
+        assert_eq!(stash.anchor_for(&node_id).ok(), Some(None));
+
+        assert_eq!(stash.add_anchor(&anchor).ok(), Some(false));
+        assert_eq!(stash.anchor_for(&node_id).ok(), Some(Some(anchor.clone())));
+        assert!(stash.find_orphans().ok().unwrap().is_empty());
+
+        // Conflicting anchor is neither indexed nor stored
+        let conflicting = sample_anchor(1, &[node_id]);
+        assert!(stash.add_anchor(&conflicting).is_err());
+        assert!(!stash.store().has_anchor(&conflicting.anchor_id()).unwrap());
+
+        assert_eq!(stash.remove_anchor(&anchor.anchor_id()).ok(), Some(true));
+        assert_eq!(stash.remove_anchor(&anchor.anchor_id()).ok(), Some(false));
+        assert_eq!(stash.anchor_for(&node_id).ok(), Some(None));
+
+        stash.store_mut().add_anchor(&anchor).unwrap();
+        assert_eq!(stash.anchor_for(&node_id).ok(), Some(None));
+        assert_eq!(stash.rebuild_index().ok(), Some(1));
+        assert_eq!(stash.anchor_for(&node_id).ok(), Some(Some(anchor)));
+    }
+
+    #[test]
+    fn test_stash_presence() {
+        let index_dir = TempPath::new("stash-presence");
+        let mut stash = stash_view(&index_dir);
+        let genesis = Genesis::default();
+        let extension = Extension::default();
+        let consignment = Consignment::with(
+            genesis.clone(),
+            vec![],
+            vec![].into(),
+            vec![extension.clone()].into(),
+        );
+        assert_eq!(
+            stash.validate_presence(&consignment).ok(),
+            Some(bset! {genesis.node_id(), extension.node_id()})
+        );
+
+        stash.store_mut().add_genesis(&genesis).unwrap();
+        stash.store_mut().add_extension(&extension).unwrap();
+        assert_eq!(stash.validate_presence(&consignment).ok(), Some(bset! {}));
+    }
+}
@@ -11,10 +11,10 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use amplify::{IoError, Wrapper};
 use bitcoin::hashes::{sha256t, Hash};
@@ -26,7 +26,8 @@ use strict_encoding::{StrictDecode, StrictEncode};
 
 use super::Index;
 use crate::error::{BootstrapError, ServiceErrorDomain};
-use crate::util::file::{file, FileMode};
+use crate::util::file::{file, sync_dir, temp_filename, FileMode};
+use crate::util::MagicNumber;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default, StrictEncode, StrictDecode)]
@@ -68,6 +69,18 @@ pub enum BTreeIndexError {
 
     /// Anchor is not found, index is probably broken
     AnchorNotFound,
+
+    /// Index file has version {found} of the format, while only version
+    /// {supported} is supported; the index has to be rebuilt
+    IncompatibleIndexVersion { found: u16, supported: u16 },
+
+    /// Transition {node_id} is already indexed as committed by anchor
+    /// {existing}, but anchor {incoming} claims to commit to it as well
+    Conflict {
+        node_id: NodeId,
+        existing: AnchorId,
+        incoming: AnchorId,
+    },
 }
 
 impl From<BTreeIndexError> for ServiceErrorDomain {
@@ -86,6 +99,16 @@ pub struct BTreeIndexConfig {
 }
 
 impl BTreeIndexConfig {
+    /// Constructs configuration keeping strict-encoded index under
+    /// `index_dir`
+    #[inline]
+    pub fn new(index_dir: PathBuf) -> Self {
+        Self {
+            index_dir,
+            data_format: FileFormat::StrictEncode,
+        }
+    }
+
     #[inline]
     pub fn index_dir(&self) -> PathBuf { self.index_dir.clone() }
 
@@ -102,9 +125,21 @@ impl BTreeIndexConfig {
 pub struct BTreeIndex {
     config: BTreeIndexConfig,
     index: BTreeIndexData,
+    /// Reverse of `index.node_anchors`; not persisted and re-built on load
+    anchor_nodes: BTreeMap<AnchorId, BTreeSet<NodeId>>,
 }
 
 impl BTreeIndex {
+    /// Major version of the index file format; files with other major
+    /// versions are rejected with [`BTreeIndexError::IncompatibleIndexVersion`]
+    pub const FORMAT_MAJOR: u16 = 1;
+
+    /// Minor version of the index file format, increased when sections are
+    /// added
+    pub const FORMAT_MINOR: u16 = 0;
+
+    const SECTION_NODE_ANCHORS: u16 = 1;
+
     pub fn new(config: BTreeIndexConfig) -> Result<Self, BTreeIndexError> {
         debug!("Instantiating RGB index (file storage) ...");
 
@@ -120,6 +155,7 @@ impl BTreeIndex {
         let mut me = Self {
             config,
             index: empty!(),
+            anchor_nodes: empty!(),
         };
 
         if me.config.index_filename().exists() {
@@ -150,16 +186,44 @@ impl BTreeIndex {
                 f.read_to_string(&mut data)?;
                 toml::from_str(&data)?
             }
-            FileFormat::StrictEncode => StrictDecode::strict_decode(&mut f)?,
+            FileFormat::StrictEncode => {
+                let mut data = vec![];
+                f.read_to_end(&mut data)?;
+                Self::decode_versioned(&data)?
+            }
             _ => unimplemented!(),
         };
+        self.anchor_nodes = empty!();
+        for (node_id, anchor_id) in &self.index.node_anchors {
+            self.anchor_nodes
+                .entry(*anchor_id)
+                .or_default()
+                .insert(*node_id);
+        }
         Ok(())
     }
 
+    /// Saves index data, replacing the index file atomically: the data are
+    /// written to a temporary file first, which is then renamed into place.
     pub fn store(&self) -> Result<(), BTreeIndexError> {
         trace!("Saving index information ...");
-        let _ = fs::remove_file(&self.config.index_filename());
-        let mut f = file(&self.config.index_filename(), FileMode::Create)?;
+        let filename = self.config.index_filename();
+        let temp = temp_filename(&filename);
+        if let Err(err) = self
+            .write_data(&temp)
+            .and_then(|_| fs::rename(&temp, &filename).map_err(BTreeIndexError::from))
+        {
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+        if let Some(dir) = filename.parent() {
+            sync_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    fn write_data(&self, filename: &Path) -> Result<(), BTreeIndexError> {
+        let mut f = file(filename, FileMode::Create)?;
         match self.config.data_format {
             #[cfg(feature = "serde_yaml")]
             FileFormat::Yaml => serde_yaml::to_writer(&f, &self.index)?,
@@ -167,18 +231,80 @@ impl BTreeIndex {
             FileFormat::Json => serde_json::to_writer(&f, &self.index)?,
             #[cfg(feature = "toml")]
             FileFormat::Toml => f.write_all(&toml::to_vec(&self.index)?)?,
-            FileFormat::StrictEncode => {
-                self.index.strict_encode(&mut f)?;
-            }
+            FileFormat::StrictEncode => self.encode_versioned(&mut f)?,
             _ => unimplemented!(),
         }
+        f.sync_all()?;
+        Ok(())
+    }
+
+    /// Writes strict-encoded index data in the versioned format: the
+    /// [`MagicNumber::Index`] and the major and minor versions of the format
+    /// are followed by sections, each consisting of a `u16` tag, a `u32`
+    /// length and the section data. Readers skip sections with unknown
+    /// tags, so new sections may be added with a minor version bump, while
+    /// incompatible changes require a new major version.
+    fn encode_versioned(&self, mut w: impl Write) -> Result<(), BTreeIndexError> {
+        w.write_all(&MagicNumber::Index.to_u32().to_be_bytes())?;
+        Self::FORMAT_MAJOR.strict_encode(&mut w)?;
+        Self::FORMAT_MINOR.strict_encode(&mut w)?;
+        let data = self.index.node_anchors.strict_serialize()?;
+        Self::SECTION_NODE_ANCHORS.strict_encode(&mut w)?;
+        (data.len() as u32).strict_encode(&mut w)?;
+        w.write_all(&data)?;
         Ok(())
     }
+
+    /// Reads data written by [`BTreeIndex::encode_versioned`]; files written
+    /// before the format was versioned are read as well
+    fn decode_versioned(data: &[u8]) -> Result<BTreeIndexData, BTreeIndexError> {
+        if !data.starts_with(&MagicNumber::Index.to_u32().to_be_bytes()) {
+            return Ok(BTreeIndexData::strict_deserialize(data)?);
+        }
+        let mut r = &data[4..];
+        let major = u16::strict_decode(&mut r)?;
+        if major != Self::FORMAT_MAJOR {
+            return Err(BTreeIndexError::IncompatibleIndexVersion {
+                found: major,
+                supported: Self::FORMAT_MAJOR,
+            });
+        }
+        let _minor = u16::strict_decode(&mut r)?;
+        let mut index = BTreeIndexData::default();
+        while !r.is_empty() {
+            let tag = u16::strict_decode(&mut r)?;
+            let len = u32::strict_decode(&mut r)? as usize;
+            if r.len() < len {
+                return Err(strict_encoding::Error::Io(io::ErrorKind::UnexpectedEof.into()).into());
+            }
+            let (section, rest) = r.split_at(len);
+            match tag {
+                Self::SECTION_NODE_ANCHORS => {
+                    index.node_anchors = StrictDecode::strict_deserialize(section)?
+                }
+                // Written by a newer version of the node
+                tag => trace!("Skipping unknown index section {}", tag),
+            }
+            r = rest;
+        }
+        Ok(index)
+    }
+}
+
+/// Ids of all nodes committed to by the anchor
+fn anchor_node_ids(anchor: &Anchor<MerkleBlock>) -> Vec<NodeId> {
+    anchor
+        .lnpbp4_proof
+        .into_iter()
+        .map(|commitment| NodeId::from_inner(sha256t::Hash::from_inner(commitment.into_inner())))
+        .collect()
 }
 
 impl Index for BTreeIndex {
     type Error = BTreeIndexError;
 
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, BTreeIndexError::AnchorNotFound) }
+
     fn anchor_id_by_transition_id(&self, node_id: NodeId) -> Result<AnchorId, Self::Error> {
         self.index
             .node_anchors
@@ -187,16 +313,327 @@ impl Index for BTreeIndex {
             .ok_or(BTreeIndexError::AnchorNotFound)
     }
 
+    fn transition_ids_by_anchor_id(&self, anchor_id: AnchorId) -> Result<Vec<NodeId>, Self::Error> {
+        Ok(self
+            .anchor_nodes
+            .get(&anchor_id)
+            .map(|nodes| nodes.iter().copied().collect())
+            .unwrap_or_default())
+    }
+
     fn index_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        Ok(self.index_anchors(std::slice::from_ref(anchor))? > 0)
+    }
+
+    /// Checks all the anchors before changing anything, so a conflict with
+    /// the index or between the anchors of the batch leaves the index
+    /// untouched. The index file is saved once for the whole batch.
+    fn index_anchors(&mut self, anchors: &[Anchor<MerkleBlock>]) -> Result<usize, Self::Error> {
+        let mut pending = BTreeMap::<NodeId, AnchorId>::new();
+        let mut batch = Vec::with_capacity(anchors.len());
+        for anchor in anchors {
+            let incoming = anchor.anchor_id();
+            let node_ids = anchor_node_ids(anchor);
+            for node_id in &node_ids {
+                let existing = self
+                    .index
+                    .node_anchors
+                    .get(node_id)
+                    .or_else(|| pending.get(node_id));
+                match existing {
+                    Some(existing) if *existing != incoming => {
+                        return Err(BTreeIndexError::Conflict {
+                            node_id: *node_id,
+                            existing: *existing,
+                            incoming,
+                        })
+                    }
+                    _ => {}
+                }
+                pending.insert(*node_id, incoming);
+            }
+            batch.push((incoming, node_ids));
+        }
+
+        let mut count = 0usize;
+        for (incoming, node_ids) in batch {
+            let mut added = false;
+            for node_id in node_ids {
+                if self.index.node_anchors.insert(node_id, incoming).is_none() {
+                    added = true;
+                }
+                self.anchor_nodes
+                    .entry(incoming)
+                    .or_default()
+                    .insert(node_id);
+            }
+            if added {
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.store()?;
+        }
+        Ok(count)
+    }
+
+    fn remove_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
         let anchor_id = anchor.anchor_id();
+        let mut removed = false;
         for commitment in &anchor.lnpbp4_proof {
-            let scalar = commitment.into_inner();
-            self.index.node_anchors.insert(
-                NodeId::from_inner(sha256t::Hash::from_inner(scalar)),
-                anchor_id,
-            );
+            let node_id = NodeId::from_inner(sha256t::Hash::from_inner(commitment.into_inner()));
+            if self.index.node_anchors.get(&node_id) == Some(&anchor_id) {
+                self.index.node_anchors.remove(&node_id);
+                removed = true;
+            }
+        }
+        self.anchor_nodes.remove(&anchor_id);
+        if removed {
+            self.store()?;
         }
-        self.store()?;
-        Ok(true)
+        Ok(removed)
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        Ok(self.anchor_nodes.keys().copied().collect())
+    }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> { Ok(self.index.node_anchors.len()) }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.index = empty!();
+        self.anchor_nodes = empty!();
+        self.store()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::slice;
+
+    use super::*;
+    use crate::stashd::index::rebuild;
+    use crate::stashd::storage::{sample_anchor, MemoryStorage, Store};
+    use crate::util::TempPath;
+
+    fn node_id(tag: &[u8]) -> NodeId { NodeId::from_inner(sha256t::Hash::hash(tag)) }
+
+    #[test]
+    fn test_btree_introspection() {
+        let index_dir = TempPath::new("btree-stats");
+
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert!(index.is_empty().unwrap());
+        assert_eq!(index.anchor_ids().unwrap(), vec![]);
+
+        let anchor = sample_anchor(1, &[node_id(b"first"), node_id(b"second")]);
+        index.index_anchor(&anchor).unwrap();
+        assert!(!index.is_empty().unwrap());
+        assert_eq!(index.transition_count().unwrap(), 2);
+        assert_eq!(index.anchor_ids().unwrap(), vec![anchor.anchor_id()]);
+
+        let index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert_eq!(index.transition_count().unwrap(), 2);
+        assert_eq!(index.anchor_ids().unwrap(), vec![anchor.anchor_id()]);
+    }
+
+    #[test]
+    fn test_btree_rebuild() {
+        let index_dir = TempPath::new("btree-rebuild");
+
+        let first = sample_anchor(1, &[node_id(b"first"), node_id(b"second")]);
+        let second = sample_anchor(2, &[node_id(b"third")]);
+        let stale = sample_anchor(3, &[node_id(b"first")]);
+        let mut store = MemoryStorage::new();
+        store.add_anchor(&first).unwrap();
+        store.add_anchor(&second).unwrap();
+
+        // Stale mapping conflicting with the stored anchor must not prevent
+        // the recovery
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        index.index_anchor(&stale).unwrap();
+        assert!(rebuild(&store, &mut index).is_ok());
+        assert_eq!(
+            index.anchor_id_by_transition_id(node_id(b"first")).unwrap(),
+            first.anchor_id()
+        );
+        assert!(index
+            .transition_ids_by_anchor_id(stale.anchor_id())
+            .unwrap()
+            .is_empty());
+        assert_eq!(index.transition_count().unwrap(), 3);
+
+        drop(index);
+        fs::remove_dir_all(&index_dir).unwrap();
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert!(index.is_empty().unwrap());
+        assert_eq!(rebuild(&store, &mut index).ok(), Some(2));
+        assert_eq!(
+            index.anchor_id_by_transition_id(node_id(b"third")).unwrap(),
+            second.anchor_id()
+        );
+
+        // The rebuilt index is persisted
+        let index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert_eq!(index.transition_count().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_btree_conflict() {
+        let index_dir = TempPath::new("btree-conflict");
+
+        let first = sample_anchor(1, &[node_id(b"first"), node_id(b"second")]);
+        let conflicting = sample_anchor(2, &[node_id(b"third"), node_id(b"second")]);
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert!(index.index_anchor(&first).unwrap());
+        assert!(!index.index_anchor(&first).unwrap());
+        assert_eq!(index.transition_count().unwrap(), 2);
+
+        match index.index_anchor(&conflicting) {
+            Err(BTreeIndexError::Conflict {
+                node_id: conflict,
+                existing,
+                incoming,
+            }) => {
+                assert_eq!(conflict, node_id(b"second"));
+                assert_eq!(existing, first.anchor_id());
+                assert_eq!(incoming, conflicting.anchor_id());
+            }
+            res => panic!("conflict is not detected: {:?}", res),
+        }
+        assert!(index.anchor_id_by_transition_id(node_id(b"third")).is_err());
+        assert_eq!(index.anchor_ids().unwrap(), vec![first.anchor_id()]);
+
+        // Nothing is saved either
+        let index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert_eq!(index.transition_count().unwrap(), 2);
+        assert_eq!(
+            index
+                .anchor_id_by_transition_id(node_id(b"second"))
+                .unwrap(),
+            first.anchor_id()
+        );
+    }
+
+    #[test]
+    fn test_btree_index_anchors() {
+        let index_dir = TempPath::new("btree-batch");
+
+        let first = sample_anchor(1, &[node_id(b"first")]);
+        let second = sample_anchor(2, &[node_id(b"second")]);
+        let third = sample_anchor(3, &[node_id(b"third"), node_id(b"second")]);
+        let conflicting = sample_anchor(4, &[node_id(b"fourth"), node_id(b"first")]);
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert_eq!(index.index_anchors(slice::from_ref(&first)).unwrap(), 1);
+
+        // Conflict with the indexed anchor is found before anything changes
+        assert!(matches!(
+            index.index_anchors(&[second.clone(), conflicting]),
+            Err(BTreeIndexError::Conflict { .. })
+        ));
+        assert!(index
+            .anchor_id_by_transition_id(node_id(b"second"))
+            .is_err());
+
+        // So is a conflict between the anchors of the batch
+        assert!(matches!(
+            index.index_anchors(&[second.clone(), third]),
+            Err(BTreeIndexError::Conflict { .. })
+        ));
+        assert!(index
+            .anchor_id_by_transition_id(node_id(b"second"))
+            .is_err());
+        assert!(index.anchor_id_by_transition_id(node_id(b"third")).is_err());
+        assert_eq!(index.transition_count().unwrap(), 1);
+
+        assert_eq!(
+            index
+                .index_anchors(&[first.clone(), second.clone()])
+                .unwrap(),
+            1
+        );
+        assert_eq!(index.anchor_ids().unwrap().len(), 2);
+        assert_eq!(
+            index
+                .anchor_id_by_transition_id(node_id(b"second"))
+                .unwrap(),
+            second.anchor_id()
+        );
+    }
+
+    #[test]
+    fn test_btree_versioned_format() {
+        let index_dir = TempPath::new("btree-format");
+
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        index
+            .index_anchor(&sample_anchor(1, &[node_id(b"first"), node_id(b"second")]))
+            .unwrap();
+
+        let mut data = vec![];
+        index.encode_versioned(&mut data).unwrap();
+        assert_eq!(BTreeIndex::decode_versioned(&data).unwrap(), index.index);
+
+        // Files written before the format was versioned
+        let legacy = index.index.strict_serialize().unwrap();
+        assert_eq!(BTreeIndex::decode_versioned(&legacy).unwrap(), index.index);
+
+        // Sections added by future minor versions
+        let mut newer = data.clone();
+        99u16.strict_encode(&mut newer).unwrap();
+        3u32.strict_encode(&mut newer).unwrap();
+        newer.extend_from_slice(b"new");
+        assert_eq!(BTreeIndex::decode_versioned(&newer).unwrap(), index.index);
+
+        let mut foreign = data.clone();
+        foreign[4..6].copy_from_slice(&(BTreeIndex::FORMAT_MAJOR + 1).strict_serialize().unwrap());
+        assert!(matches!(
+            BTreeIndex::decode_versioned(&foreign),
+            Err(BTreeIndexError::IncompatibleIndexVersion { found, supported })
+                if found == BTreeIndex::FORMAT_MAJOR + 1 && supported == BTreeIndex::FORMAT_MAJOR
+        ));
+
+        // Index file written in the legacy format is upgraded on the next save
+        fs::write(index.config.index_filename(), &legacy).unwrap();
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert_eq!(index.transition_count().unwrap(), 2);
+        index
+            .index_anchor(&sample_anchor(2, &[node_id(b"third")]))
+            .unwrap();
+        let saved = fs::read(index.config.index_filename()).unwrap();
+        assert!(saved.starts_with(&MagicNumber::Index.to_u32().to_be_bytes()));
+    }
+
+    #[test]
+    fn test_btree_remove_anchor() {
+        let index_dir = TempPath::new("btree-remove");
+
+        let first = sample_anchor(1, &[node_id(b"first"), node_id(b"second")]);
+        let second = sample_anchor(2, &[node_id(b"third")]);
+        let unknown = sample_anchor(3, &[node_id(b"first"), node_id(b"third")]);
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        index
+            .index_anchors(&[first.clone(), second.clone()])
+            .unwrap();
+
+        // Mappings pointing to other anchors are kept
+        assert!(!index.remove_anchor(&unknown).unwrap());
+        assert_eq!(index.transition_count().unwrap(), 3);
+        assert_eq!(
+            index.anchor_id_by_transition_id(node_id(b"first")).unwrap(),
+            first.anchor_id()
+        );
+
+        assert!(index.remove_anchor(&first).unwrap());
+        assert!(!index.remove_anchor(&first).unwrap());
+        assert!(index.anchor_id_by_transition_id(node_id(b"first")).is_err());
+        assert!(index
+            .transition_ids_by_anchor_id(first.anchor_id())
+            .unwrap()
+            .is_empty());
+        assert_eq!(index.anchor_ids().unwrap(), vec![second.anchor_id()]);
+
+        let index = BTreeIndex::new(BTreeIndexConfig::new(index_dir.to_path_buf())).unwrap();
+        assert_eq!(index.transition_count().unwrap(), 1);
     }
 }
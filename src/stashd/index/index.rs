@@ -11,16 +11,195 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use bp::dbc::{Anchor, AnchorId};
 use commit_verify::lnpbp4::MerkleBlock;
-use rgb::NodeId;
+use rgb::{ContractId, NodeId};
 
 use crate::error::ServiceErrorDomain;
+use crate::stashd::storage::Store;
 
 pub trait Index {
     type Error: ::std::error::Error + Into<ServiceErrorDomain>;
 
+    /// Tells whether the error reports that the requested data are not known
+    /// to the index
+    fn is_not_found(_err: &Self::Error) -> bool
+    where Self: Sized {
+        false
+    }
+
     fn anchor_id_by_transition_id(&self, tsid: NodeId) -> Result<AnchorId, Self::Error>;
 
+    /// Returns id of the anchor committing to the state extension.
+    ///
+    /// LNPBP-4 commitments of an anchor do not tell transitions from
+    /// extensions, so [`Index::index_anchor`] records all of them in the same
+    /// way, and by default this is the same lookup as
+    /// [`Index::anchor_id_by_transition_id`].
+    fn anchor_id_by_extension_id(&self, node_id: NodeId) -> Result<AnchorId, Self::Error> {
+        self.anchor_id_by_transition_id(node_id)
+    }
+
+    /// Returns ids of all transitions committed to by the anchor; an anchor
+    /// without known transitions gives an empty list
+    fn transition_ids_by_anchor_id(&self, anchor_id: AnchorId) -> Result<Vec<NodeId>, Self::Error>;
+
+    /// Returns ids of all transitions of the contract, or `None` if the index
+    /// does not track which contract transitions belong to; in the latter
+    /// case [`Store::transitions_by_contract`] falls back to scanning the
+    /// store.
+    fn transition_ids_by_contract(
+        &self,
+        _contract_id: ContractId,
+    ) -> Result<Option<Vec<NodeId>>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Indexes all nodes (state transitions as well as state extensions)
+    /// committed to by the `anchor`, returning whether any new mapping was
+    /// added; re-indexing an already indexed anchor is a no-op returning
+    /// `false`.
+    ///
+    /// If some of the nodes are already indexed under a different
+    /// anchor, the index must fail with an error without changing anything,
+    /// since two anchors committing to the same node indicate a bug or
+    /// an attack.
     fn index_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error>;
+
+    /// Indexes all the `anchors` like [`Index::index_anchor`] does, returning
+    /// the number of anchors which added new mappings. Persistent indexes
+    /// should override the default implementation, which indexes anchors one
+    /// by one, to save their data only once for the whole batch.
+    ///
+    /// The default implementation stops on the first error, leaving the
+    /// preceding anchors indexed.
+    fn index_anchors(&mut self, anchors: &[Anchor<MerkleBlock>]) -> Result<usize, Self::Error> {
+        let mut count = 0usize;
+        for anchor in anchors {
+            if self.index_anchor(anchor)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Removes all transition id to anchor id mappings contributed by the
+    /// `anchor`, returning whether anything was removed.
+    ///
+    /// The index does not track the storage by itself: callers removing an
+    /// anchor from the [`crate::stashd::storage::Store`] must remove it from
+    /// the index as well (and vice versa), otherwise lookups will return ids
+    /// of the anchors which are no longer stored.
+    fn remove_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error>;
+
+    /// Removes all the mappings, leaving the index empty; used by [`rebuild`]
+    /// to drop stale data before re-indexing. Like [`Index::anchor_ids`], it
+    /// has no default implementation.
+    fn clear(&mut self) -> Result<(), Self::Error>;
+
+    /// Returns ids of all anchors known to the index, allowing to compare
+    /// them with [`Store::anchor_ids`].
+    ///
+    /// This method has no default implementation, since only the index knows
+    /// what it keeps; adding it is a breaking change for external
+    /// implementations of the trait.
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error>;
+
+    /// Returns number of the indexed transitions. Like [`Index::anchor_ids`],
+    /// it has to be provided by each implementation.
+    fn transition_count(&self) -> Result<usize, Self::Error>;
+
+    /// Tells whether the index knows no transitions, i.e. whether it has to
+    /// be built with [`rebuild`]
+    #[inline]
+    fn is_empty(&self) -> Result<bool, Self::Error> { Ok(self.transition_count()? == 0) }
+}
+
+/// Re-indexes all anchors kept by the `store`, returning number of the indexed
+/// anchors. Since the store is the authoritative source of the data, this
+/// allows to recover an index which was lost or went out of sync.
+///
+/// The index is cleared first, so stale mappings can't conflict with the
+/// stored anchors, and then all the anchors are indexed in a single
+/// [`Index::index_anchors`] batch.
+pub fn rebuild<S: Store, I: Index>(store: &S, index: &mut I) -> Result<usize, ServiceErrorDomain> {
+    let anchors = store
+        .anchor_ids()
+        .map_err(S::Error::into)?
+        .iter()
+        .map(|anchor_id| store.anchor(anchor_id))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(S::Error::into)?;
+    index.clear().map_err(I::Error::into)?;
+    index.index_anchors(&anchors).map_err(I::Error::into)?;
+    Ok(anchors.len())
+}
+
+/// Report produced by [`find_orphans`]
+#[derive(Clone, PartialEq, Eq, Debug, Default, Display)]
+#[display(Debug)]
+pub struct OrphanReport {
+    /// Stored transitions which anchor can't be resolved: either the index
+    /// does not know the transition, or the anchor it points to is absent
+    /// from the store
+    pub orphan_transitions: BTreeSet<NodeId>,
+
+    /// Stored anchors which, according to the index, commit to transitions
+    /// absent from the store, together with ids of the missing transitions
+    pub missing_transitions: BTreeMap<AnchorId, BTreeSet<NodeId>>,
+}
+
+impl OrphanReport {
+    /// Tells whether the store and the index are consistent
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.orphan_transitions.is_empty() && self.missing_transitions.is_empty()
+    }
+}
+
+/// Cross-checks transitions and anchors kept by the `store` using the
+/// `index`, reporting transitions without a resolvable anchor and anchors
+/// referencing missing transitions. Useful after an unclean shutdown to find
+/// out which data have to be re-downloaded.
+pub fn find_orphans<S: Store, I: Index>(
+    store: &S,
+    index: &I,
+) -> Result<OrphanReport, ServiceErrorDomain> {
+    let mut report = OrphanReport::default();
+    let anchor_ids: BTreeSet<AnchorId> = store
+        .anchor_ids()
+        .map_err(S::Error::into)?
+        .into_iter()
+        .collect();
+
+    for node_id in store.transition_ids().map_err(S::Error::into)? {
+        match index.anchor_id_by_transition_id(node_id) {
+            Ok(anchor_id) if anchor_ids.contains(&anchor_id) => {}
+            Ok(_) => {
+                report.orphan_transitions.insert(node_id);
+            }
+            Err(err) if I::is_not_found(&err) => {
+                report.orphan_transitions.insert(node_id);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    for anchor_id in anchor_ids {
+        let node_ids = index
+            .transition_ids_by_anchor_id(anchor_id)
+            .map_err(I::Error::into)?;
+        let present = store.has_transitions(&node_ids).map_err(S::Error::into)?;
+        let missing: BTreeSet<NodeId> = node_ids
+            .into_iter()
+            .filter(|node_id| !present.contains(node_id))
+            .collect();
+        if !missing.is_empty() {
+            report.missing_transitions.insert(anchor_id, missing);
+        }
+    }
+
+    Ok(report)
 }
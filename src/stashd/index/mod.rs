@@ -15,5 +15,5 @@ mod index;
 
 mod btree;
 
-pub(super) use btree::{BTreeIndex, BTreeIndexConfig, BTreeIndexError};
-pub(super) use index::Index;
+pub use btree::{BTreeIndex, BTreeIndexConfig, BTreeIndexError};
+pub use index::{find_orphans, rebuild, Index, OrphanReport};
@@ -11,12 +11,14 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod composite;
 mod config;
 mod runtime;
 mod stash;
 
-pub(self) mod index;
-pub(self) mod storage;
+pub mod index;
+pub mod storage;
 
+pub use composite::StashView;
 pub use config::{Config, Opts};
 pub use runtime::{main_with_config, Runtime};
@@ -76,9 +76,8 @@ impl Runtime {
 
     pub fn init(config: Config) -> Result<Self, BootstrapError> {
         #[cfg(not(store_hammersbald))] // Default store
-        let storage = DiskStorage::new(DiskStorageConfig {
-            data_dir: PathBuf::from(config.stash.clone()),
-        })?;
+        let storage =
+            DiskStorage::new(DiskStorageConfig::new(PathBuf::from(config.stash.clone())))?;
 
         let indexer = BTreeIndex::new(BTreeIndexConfig {
             index_dir: PathBuf::from(config.index.clone()),
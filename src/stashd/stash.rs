@@ -210,6 +210,7 @@ impl Stash for Runtime {
         // [PRIVACY] [SECURITY]:
         // Update all data with the previously known revealed information in the
         // stash
+        let mut anchors = Vec::with_capacity(consignment.state_transitions.len());
         for (anchor, transition) in consignment.state_transitions.iter() {
             let mut transition = transition.clone();
             transition
@@ -231,9 +232,10 @@ impl Stash for Runtime {
             }
             // Store the transition and the anchor data in the stash
             self.storage.add_anchor(&anchor)?;
-            self.indexer.index_anchor(&anchor)?;
             self.storage.add_transition(&transition)?;
+            anchors.push(anchor);
         }
+        self.indexer.index_anchors(&anchors)?;
 
         for extension in consignment.state_extensions.iter() {
             let mut extension = extension.clone();
@@ -269,6 +271,7 @@ impl Stash for Runtime {
                 .map_err(|_| Error::UnknownContract(contract_id))?;
         }
 
+        let mut anchors = Vec::with_capacity(disclosure.transitions().len());
         for anchor in disclosure.transitions().values().map(|(anchor, _)| anchor) {
             let mut anchor = anchor.clone();
             if let Ok(other_anchor) = self.storage.anchor(&anchor.anchor_id()) {
@@ -277,8 +280,9 @@ impl Stash for Runtime {
                     .expect("RGB commitment procedure is broken");
             }
             self.storage.add_anchor(&anchor)?;
-            self.indexer.index_anchor(&anchor)?;
+            anchors.push(anchor);
         }
+        self.indexer.index_anchors(&anchors)?;
 
         for transition in disclosure
             .transitions()
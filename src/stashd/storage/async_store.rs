@@ -0,0 +1,289 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::panic;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+use tokio_crate::task;
+
+use super::Store;
+use crate::error::ServiceErrorDomain;
+
+/// Asynchronous version of [`Store`] for use from async services
+#[async_trait]
+pub trait AsyncStore {
+    type Error: ::std::error::Error + Into<ServiceErrorDomain> + Send;
+
+    async fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error>;
+    async fn schema_count(&self) -> Result<usize, Self::Error>;
+    async fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error>;
+    async fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error>;
+    async fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error>;
+    async fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error>;
+    async fn clear_schemata(&mut self) -> Result<(), Self::Error>;
+
+    async fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error>;
+    async fn contract_count(&self) -> Result<usize, Self::Error>;
+    async fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error>;
+    async fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error>;
+    async fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error>;
+    async fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error>;
+    async fn clear_geneses(&mut self) -> Result<(), Self::Error>;
+
+    async fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error>;
+    async fn anchor_count(&self) -> Result<usize, Self::Error>;
+    async fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error>;
+    async fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error>;
+    async fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error>;
+    async fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error>;
+    async fn clear_anchors(&mut self) -> Result<(), Self::Error>;
+
+    async fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error>;
+    async fn transition_count(&self) -> Result<usize, Self::Error>;
+    async fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error>;
+    async fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error>;
+    async fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error>;
+    async fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error>;
+    async fn clear_transitions(&mut self) -> Result<(), Self::Error>;
+
+    async fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error>;
+    async fn extension_count(&self) -> Result<usize, Self::Error>;
+    async fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error>;
+    async fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error>;
+    async fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error>;
+    async fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error>;
+    async fn clear_extensions(&mut self) -> Result<(), Self::Error>;
+
+    async fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Adapts any [`Store`] to [`AsyncStore`] by running its calls on the tokio
+/// blocking threadpool, so the async runtime is never blocked by the store
+/// I/O. Calls are serialized with a mutex.
+#[derive(Debug)]
+pub struct BlockingStore<S: Store> {
+    store: Arc<Mutex<S>>,
+}
+
+impl<S: Store> Clone for BlockingStore<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<S: Store> BlockingStore<S>
+where
+    S: Send + 'static,
+    S::Error: Send + 'static,
+{
+    pub fn new(store: S) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut S) -> Result<T, S::Error> + Send + 'static,
+    ) -> Result<T, S::Error> {
+        let store = self.store.clone();
+        let res = task::spawn_blocking(move || {
+            let mut store = store.lock().unwrap_or_else(|err| err.into_inner());
+            f(&mut store)
+        })
+        .await;
+        match res {
+            Ok(res) => res,
+            // Store has panicked: propagate the panic to the caller
+            Err(err) => panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Store> AsyncStore for BlockingStore<S>
+where
+    S: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Error = S::Error;
+
+    async fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
+        self.run(|store| store.schema_ids()).await
+    }
+
+    async fn schema_count(&self) -> Result<usize, Self::Error> {
+        self.run(|store| store.schema_count()).await
+    }
+
+    async fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.schema(&id)).await
+    }
+
+    async fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.has_schema(&id)).await
+    }
+
+    async fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        let schema = schema.clone();
+        self.run(move |store| store.add_schema(&schema)).await
+    }
+
+    async fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.remove_schema(&id)).await
+    }
+
+    async fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.run(|store| store.clear_schemata()).await
+    }
+
+    async fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
+        self.run(|store| store.contract_ids()).await
+    }
+
+    async fn contract_count(&self) -> Result<usize, Self::Error> {
+        self.run(|store| store.contract_count()).await
+    }
+
+    async fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.genesis(&id)).await
+    }
+
+    async fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.has_genesis(&id)).await
+    }
+
+    async fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        let genesis = genesis.clone();
+        self.run(move |store| store.add_genesis(&genesis)).await
+    }
+
+    async fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.remove_genesis(&id)).await
+    }
+
+    async fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.run(|store| store.clear_geneses()).await
+    }
+
+    async fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        self.run(|store| store.anchor_ids()).await
+    }
+
+    async fn anchor_count(&self) -> Result<usize, Self::Error> {
+        self.run(|store| store.anchor_count()).await
+    }
+
+    async fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.anchor(&id)).await
+    }
+
+    async fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.has_anchor(&id)).await
+    }
+
+    async fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        let anchor = anchor.clone();
+        self.run(move |store| store.add_anchor(&anchor)).await
+    }
+
+    async fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.remove_anchor(&id)).await
+    }
+
+    async fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.run(|store| store.clear_anchors()).await
+    }
+
+    async fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.run(|store| store.transition_ids()).await
+    }
+
+    async fn transition_count(&self) -> Result<usize, Self::Error> {
+        self.run(|store| store.transition_count()).await
+    }
+
+    async fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.transition(&id)).await
+    }
+
+    async fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.has_transition(&id)).await
+    }
+
+    async fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        let transition = transition.clone();
+        self.run(move |store| store.add_transition(&transition))
+            .await
+    }
+
+    async fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.remove_transition(&id)).await
+    }
+
+    async fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.run(|store| store.clear_transitions()).await
+    }
+
+    async fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.run(|store| store.extension_ids()).await
+    }
+
+    async fn extension_count(&self) -> Result<usize, Self::Error> {
+        self.run(|store| store.extension_count()).await
+    }
+
+    async fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.extension(&id)).await
+    }
+
+    async fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.has_extension(&id)).await
+    }
+
+    async fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        let extension = extension.clone();
+        self.run(move |store| store.add_extension(&extension)).await
+    }
+
+    async fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        let id = *id;
+        self.run(move |store| store.remove_extension(&id)).await
+    }
+
+    async fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.run(|store| store.clear_extensions()).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> { self.run(|store| store.flush()).await }
+}
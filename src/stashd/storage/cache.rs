@@ -0,0 +1,443 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use lru::LruCache;
+use rgb::prelude::*;
+
+use super::{Store, Txn, TxnOp};
+
+/// Cache for a single type of the objects
+#[derive(Debug)]
+struct Cache<K: Hash + Eq, V> {
+    /// Recently read objects
+    present: LruCache<K, V>,
+    /// Ids recently reported as absent by the inner store
+    absent: LruCache<K, ()>,
+}
+
+impl<K: Hash + Eq, V> Cache<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            present: LruCache::new(capacity),
+            absent: LruCache::new(capacity),
+        }
+    }
+
+    fn invalidate(&mut self, id: &K) {
+        self.present.pop(id);
+        self.absent.pop(id);
+    }
+
+    fn clear(&mut self) {
+        self.present.clear();
+        self.absent.clear();
+    }
+}
+
+/// Wraps any [`Store`] keeping recently read objects in per-type LRU caches.
+///
+/// Besides the objects, the cache remembers ids which were recently found to
+/// be absent from the inner store (either by `has_*` methods or by object
+/// reads failing with [`Store::is_not_found`] errors), so repeated presence
+/// checks for missing objects do not hit the inner store. Both positive and
+/// negative caches are bounded by the same capacity.
+///
+/// Cached entries are invalidated on each addition or removal of the object
+/// done through the wrapper; modifications done to the inner store directly
+/// are not tracked.
+#[derive(Debug)]
+pub struct CachedStore<S: Store> {
+    inner: S,
+    schemata: Mutex<Cache<SchemaId, Schema>>,
+    geneses: Mutex<Cache<ContractId, Genesis>>,
+    anchors: Mutex<Cache<AnchorId, Anchor<MerkleBlock>>>,
+    transitions: Mutex<Cache<NodeId, Transition>>,
+    extensions: Mutex<Cache<NodeId, Extension>>,
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<S: Store> CachedStore<S> {
+    /// Wraps `inner` store, caching up to `capacity` objects of each type
+    /// (and the same number of absent ids)
+    pub fn new(inner: S, capacity: usize) -> Self {
+        Self {
+            inner,
+            schemata: Mutex::new(Cache::new(capacity)),
+            geneses: Mutex::new(Cache::new(capacity)),
+            anchors: Mutex::new(Cache::new(capacity)),
+            transitions: Mutex::new(Cache::new(capacity)),
+            extensions: Mutex::new(Cache::new(capacity)),
+            hits: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &S { &self.inner }
+
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    /// Number of requests served from the cache of present objects
+    #[inline]
+    pub fn hits(&self) -> u64 { self.hits.load(Ordering::Relaxed) }
+
+    /// Number of presence checks answered from the cache of absent ids
+    #[inline]
+    pub fn negative_hits(&self) -> u64 { self.negative_hits.load(Ordering::Relaxed) }
+
+    /// Number of requests which had to go to the inner store
+    #[inline]
+    pub fn misses(&self) -> u64 { self.misses.load(Ordering::Relaxed) }
+
+    fn lock<K: Hash + Eq, V>(cache: &Mutex<Cache<K, V>>) -> MutexGuard<'_, Cache<K, V>> {
+        // Cache can't be left in an inconsistent state by a panic, so it is
+        // safe to ignore poisoning
+        cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn get<K: Hash + Eq + Copy, V: Clone>(
+        &self,
+        cache: &Mutex<Cache<K, V>>,
+        id: &K,
+        load: impl FnOnce() -> Result<V, S::Error>,
+    ) -> Result<V, S::Error> {
+        if let Some(object) = Self::lock(cache).present.get(id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(object.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        match load() {
+            Ok(object) => {
+                let mut cache = Self::lock(cache);
+                cache.absent.pop(id);
+                cache.present.put(*id, object.clone());
+                Ok(object)
+            }
+            Err(err) => {
+                if S::is_not_found(&err) {
+                    Self::lock(cache).absent.put(*id, ());
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn has<K: Hash + Eq + Copy, V>(
+        &self,
+        cache: &Mutex<Cache<K, V>>,
+        id: &K,
+        check: impl FnOnce() -> Result<bool, S::Error>,
+    ) -> Result<bool, S::Error> {
+        {
+            let mut cache = Self::lock(cache);
+            if cache.present.contains(id) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(true);
+            }
+            if cache.absent.get(id).is_some() {
+                self.negative_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(false);
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let present = check()?;
+        if !present {
+            Self::lock(cache).absent.put(*id, ());
+        }
+        Ok(present)
+    }
+
+    /// Drops cached entries of the object mutated by the operation
+    fn invalidate_op(&self, op: &TxnOp) {
+        match op {
+            TxnOp::AddSchema(schema) => Self::lock(&self.schemata).invalidate(&schema.schema_id()),
+            TxnOp::RemoveSchema(id) => Self::lock(&self.schemata).invalidate(id),
+            TxnOp::AddGenesis(genesis) => {
+                Self::lock(&self.geneses).invalidate(&genesis.contract_id())
+            }
+            TxnOp::RemoveGenesis(id) => Self::lock(&self.geneses).invalidate(id),
+            TxnOp::AddAnchor(anchor) => Self::lock(&self.anchors).invalidate(&anchor.anchor_id()),
+            TxnOp::RemoveAnchor(id) => Self::lock(&self.anchors).invalidate(id),
+            TxnOp::AddTransition(transition) => {
+                Self::lock(&self.transitions).invalidate(&transition.node_id())
+            }
+            TxnOp::RemoveTransition(id) => Self::lock(&self.transitions).invalidate(id),
+            TxnOp::AddExtension(extension) => {
+                Self::lock(&self.extensions).invalidate(&extension.node_id())
+            }
+            TxnOp::RemoveExtension(id) => Self::lock(&self.extensions).invalidate(id),
+        }
+    }
+}
+
+impl<S: Store> Store for CachedStore<S> {
+    type Error = S::Error;
+
+    fn is_not_found(err: &Self::Error) -> bool { S::is_not_found(err) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> { self.inner.schema_ids() }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> { self.inner.schema_count() }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        self.get(&self.schemata, id, || self.inner.schema(id))
+    }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.has(&self.schemata, id, || self.inner.has_schema(id))
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        Self::lock(&self.schemata).invalidate(&schema.schema_id());
+        self.inner.add_schema(schema)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        Self::lock(&self.schemata).invalidate(id);
+        let removed = self.inner.remove_schema(id)?;
+        Self::lock(&self.schemata).absent.put(*id, ());
+        Ok(removed)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        Self::lock(&self.schemata).clear();
+        self.inner.clear_schemata()
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> { self.inner.contract_ids() }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> { self.inner.contract_count() }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        self.get(&self.geneses, id, || self.inner.genesis(id))
+    }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.has(&self.geneses, id, || self.inner.has_genesis(id))
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        Self::lock(&self.geneses).invalidate(&genesis.contract_id());
+        self.inner.add_genesis(genesis)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        Self::lock(&self.geneses).invalidate(id);
+        let removed = self.inner.remove_genesis(id)?;
+        Self::lock(&self.geneses).absent.put(*id, ());
+        Ok(removed)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        Self::lock(&self.geneses).clear();
+        self.inner.clear_geneses()
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> { self.inner.anchor_ids() }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> { self.inner.anchor_count() }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        self.get(&self.anchors, id, || self.inner.anchor(id))
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.has(&self.anchors, id, || self.inner.has_anchor(id))
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        Self::lock(&self.anchors).invalidate(&anchor.anchor_id());
+        self.inner.add_anchor(anchor)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        Self::lock(&self.anchors).invalidate(id);
+        let removed = self.inner.remove_anchor(id)?;
+        Self::lock(&self.anchors).absent.put(*id, ());
+        Ok(removed)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        Self::lock(&self.anchors).clear();
+        self.inner.clear_anchors()
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> { self.inner.transition_ids() }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> { self.inner.transition_count() }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        self.get(&self.transitions, id, || self.inner.transition(id))
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.has(&self.transitions, id, || self.inner.has_transition(id))
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        Self::lock(&self.transitions).invalidate(&transition.node_id());
+        self.inner.add_transition(transition)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        for transition in transitions {
+            Self::lock(&self.transitions).invalidate(&transition.node_id());
+        }
+        self.inner.add_transitions(transitions)
+    }
+
+    fn transitions_many(
+        &self,
+        ids: &[NodeId],
+    ) -> Result<BTreeMap<NodeId, Transition>, Self::Error> {
+        self.inner.transitions_many(ids)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        Self::lock(&self.transitions).invalidate(id);
+        let removed = self.inner.remove_transition(id)?;
+        Self::lock(&self.transitions).absent.put(*id, ());
+        Ok(removed)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        Self::lock(&self.transitions).clear();
+        self.inner.clear_transitions()
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> { self.inner.extension_ids() }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> { self.inner.extension_count() }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        self.get(&self.extensions, id, || self.inner.extension(id))
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.has(&self.extensions, id, || self.inner.has_extension(id))
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        Self::lock(&self.extensions).invalidate(&extension.node_id());
+        self.inner.add_extension(extension)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        Self::lock(&self.extensions).invalidate(id);
+        let removed = self.inner.remove_extension(id)?;
+        Self::lock(&self.extensions).absent.put(*id, ());
+        Ok(removed)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        Self::lock(&self.extensions).clear();
+        self.inner.clear_extensions()
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> { self.inner.flush() }
+
+    /// Invalidates cached entries of all the objects mutated by the
+    /// transaction, and applies it to the inner store, so the latter may
+    /// apply it atomically
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> {
+        for op in txn.ops() {
+            self.invalidate_op(op);
+        }
+        self.inner.apply_txn(txn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stashd::storage::{MemoryStorage, MemoryStorageError};
+
+    #[test]
+    fn test_cache_hits() {
+        let genesis = Genesis::default();
+        let contract_id = genesis.contract_id();
+        let mut store = CachedStore::new(MemoryStorage::new(), 16);
+        store.add_genesis(&genesis).unwrap();
+
+        assert_eq!(store.genesis(&contract_id).unwrap(), genesis);
+        assert_eq!((store.hits(), store.misses()), (0, 1));
+        assert_eq!(store.genesis(&contract_id).unwrap(), genesis);
+        assert!(store.has_genesis(&contract_id).unwrap());
+        assert_eq!((store.hits(), store.misses()), (2, 1));
+
+        assert!(store.remove_genesis(&contract_id).unwrap());
+        assert!(store.genesis(&contract_id).is_err());
+        assert!(!store.inner().has_genesis(&contract_id).unwrap());
+        assert!(!store.add_genesis(&genesis).unwrap());
+        assert_eq!(store.genesis(&contract_id).unwrap(), genesis);
+        assert_eq!((store.hits(), store.misses()), (2, 3));
+    }
+
+    #[test]
+    fn test_cache_negative_hits() {
+        let extension = Extension::default();
+        let node_id = extension.node_id();
+        let mut store = CachedStore::new(MemoryStorage::new(), 16);
+
+        assert!(!store.has_extension(&node_id).unwrap());
+        assert!(!store.has_extension(&node_id).unwrap());
+        assert_eq!((store.negative_hits(), store.misses()), (1, 1));
+        assert!(store.extension(&node_id).is_err());
+        assert!(!store.has_extension(&node_id).unwrap());
+        assert_eq!((store.negative_hits(), store.misses()), (2, 2));
+
+        assert!(!store.add_extension(&extension).unwrap());
+        assert!(store.has_extension(&node_id).unwrap());
+        assert_eq!((store.negative_hits(), store.misses()), (2, 3));
+        assert!(store.remove_extension(&node_id).unwrap());
+        assert!(!store.has_extension(&node_id).unwrap());
+        assert_eq!((store.negative_hits(), store.misses()), (3, 3));
+    }
+
+    #[test]
+    fn test_cache_txn_invalidation() {
+        let genesis = Genesis::default();
+        let contract_id = genesis.contract_id();
+        let transition = Transition::default();
+        let mut store = CachedStore::new(MemoryStorage::new(), 16);
+        store.add_genesis(&genesis).unwrap();
+        assert_eq!(store.genesis(&contract_id).ok(), Some(genesis.clone()));
+        assert!(!store.has_transition(&transition.node_id()).unwrap());
+
+        store
+            .transaction::<_, MemoryStorageError>(|txn| {
+                txn.remove_genesis(&contract_id);
+                txn.add_transition(&transition);
+                Ok(())
+            })
+            .unwrap();
+        assert!(!store.has_genesis(&contract_id).unwrap());
+        assert!(store.genesis(&contract_id).is_err());
+        assert!(store.has_transition(&transition.node_id()).unwrap());
+        assert_eq!(
+            store.transition(&transition.node_id()).ok(),
+            Some(transition)
+        );
+    }
+}
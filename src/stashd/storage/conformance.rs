@@ -0,0 +1,299 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Checks of the [`Store`] behaviour shared by the tests of all the storage
+//! backends, so the backends can't diverge. Exposed with `test-util` feature
+//! for the authors of third-party backends.
+
+use std::error::Error;
+use std::slice;
+
+use amplify::Wrapper;
+use bitcoin::hashes::Hash;
+use bitcoin::Txid;
+use bp::dbc::Anchor;
+use commit_verify::lnpbp4::{self, MerkleBlock, MerkleTree, MultiSource};
+use commit_verify::TryCommitVerify;
+use rgb::prelude::*;
+
+use super::{IdMismatch, Store};
+
+/// Checks addition, reading, enumeration and removal of a single object of
+/// each kind
+macro_rules! check_kind {
+    (
+        $store:ident,
+        $object:expr,
+        $id:expr,
+        $add:ident,
+        $has:ident,
+        $get:ident,
+        $ids:ident,
+        $count:ident,
+        $remove:ident
+    ) => {{
+        let object = $object;
+        let id = $id;
+        assert!(!$store.$has(&id).unwrap());
+        assert!(S::is_not_found(&$store.$get(&id).unwrap_err()));
+        assert!(!$store.$add(&object).unwrap());
+        assert!($store.$add(&object).unwrap());
+        assert!($store.$has(&id).unwrap());
+        assert_eq!($store.$get(&id).unwrap(), object);
+        assert_eq!($store.$ids().unwrap(), vec![id]);
+        assert_eq!($store.$count().unwrap(), 1);
+        assert!($store.$remove(&id).unwrap());
+        assert!(!$store.$remove(&id).unwrap());
+        assert!(!$store.$has(&id).unwrap());
+        assert!($store.$ids().unwrap().is_empty());
+    }};
+}
+
+/// Checks that the store implements semantics of the [`Store`] trait,
+/// panicking on the first violation.
+///
+/// Covers behaviour of empty stores, add/has/get/remove round-trips of all
+/// five object kinds, values returned by repeated additions and removals
+/// (`add_*` and `remove_*` report whether the object was present before),
+/// enumeration and counts after insertions, batch transition methods,
+/// replacements, additions of absent objects, computation of absent
+/// transitions (including the ones computed with a different id), visiting
+/// of the stored transitions, id sets, lookup of ids by prefix, lookup of
+/// contracts by schema, [`Store::flush`] and [`Store::clear`]. Reads of
+/// absent objects must fail with errors recognized by [`Store::is_not_found`].
+///
+/// `new_store` must return a new empty store on each call. It is `FnMut`
+/// rather than `Fn`, so persistent backends can count the calls to give each
+/// store its own data directory; any `Fn` closure is accepted as well.
+pub fn assert_store_conformance<S: Store>(mut new_store: impl FnMut() -> S)
+where S::Error: 'static {
+    check_empty(&new_store());
+    check_objects(&mut new_store());
+    check_clear(&mut new_store());
+}
+
+/// Constructs a valid anchor committing to the nodes, each under its own
+/// protocol id; anchors with different `txid` bytes have different ids.
+pub fn sample_anchor(txid: u8, node_ids: &[NodeId]) -> Anchor<MerkleBlock> {
+    let source = MultiSource {
+        min_depth: 3,
+        messages: node_ids
+            .iter()
+            .enumerate()
+            .map(|(no, node_id)| {
+                (
+                    lnpbp4::ProtocolId::from_inner([no as u8 + 1; 32]),
+                    lnpbp4::Message::from_inner(node_id.into_inner().into_inner()),
+                )
+            })
+            .collect(),
+    };
+    Anchor {
+        txid: Txid::from_inner([txid; 32]),
+        lnpbp4_proof: MerkleBlock::from(
+            MerkleTree::try_commit(&source).expect("sample anchor commitment"),
+        ),
+        dbc_proof: bp::dbc::Proof::Opret1st,
+    }
+}
+
+fn check_empty<S: Store>(store: &S) {
+    assert!(store.schema_ids().unwrap().is_empty());
+    assert!(store.contract_ids().unwrap().is_empty());
+    assert!(store.anchor_ids().unwrap().is_empty());
+    assert!(store.transition_ids().unwrap().is_empty());
+    assert!(store.extension_ids().unwrap().is_empty());
+    assert_eq!(store.schema_count().unwrap(), 0);
+    assert_eq!(store.contract_count().unwrap(), 0);
+    assert_eq!(store.anchor_count().unwrap(), 0);
+    assert_eq!(store.transition_count().unwrap(), 0);
+    assert_eq!(store.extension_count().unwrap(), 0);
+}
+
+fn check_objects<S: Store>(store: &mut S)
+where S::Error: 'static {
+    let schema = rgb20::schema::schema();
+    check_kind!(
+        store,
+        schema.clone(),
+        schema.schema_id(),
+        add_schema,
+        has_schema,
+        schema,
+        schema_ids,
+        schema_count,
+        remove_schema
+    );
+    check_kind!(
+        store,
+        Genesis::default(),
+        Genesis::default().contract_id(),
+        add_genesis,
+        has_genesis,
+        genesis,
+        contract_ids,
+        contract_count,
+        remove_genesis
+    );
+    check_kind!(
+        store,
+        Transition::default(),
+        Transition::default().node_id(),
+        add_transition,
+        has_transition,
+        transition,
+        transition_ids,
+        transition_count,
+        remove_transition
+    );
+    check_kind!(
+        store,
+        Extension::default(),
+        Extension::default().node_id(),
+        add_extension,
+        has_extension,
+        extension,
+        extension_ids,
+        extension_count,
+        remove_extension
+    );
+
+    let anchor = sample_anchor(0, &[Transition::default().node_id()]);
+    check_kind!(
+        store,
+        anchor.clone(),
+        anchor.anchor_id(),
+        add_anchor,
+        has_anchor,
+        anchor,
+        anchor_ids,
+        anchor_count,
+        remove_anchor
+    );
+
+    let transition = Transition::default();
+    let node_id = transition.node_id();
+    let missing = NodeId::from_inner(Hash::hash(b"missing"));
+    assert_eq!(
+        store.add_transitions(slice::from_ref(&transition)).unwrap(),
+        1
+    );
+    assert_eq!(
+        store.add_transitions(slice::from_ref(&transition)).unwrap(),
+        0
+    );
+    assert_eq!(
+        store.has_transitions(&[node_id, missing]).unwrap(),
+        bset! {node_id}
+    );
+    assert_eq!(
+        store.transitions_many(&[node_id, missing]).unwrap(),
+        bmap! {node_id => transition.clone()}
+    );
+    assert!(store.replace_transition(&transition, &transition).unwrap());
+    assert!(!store
+        .replace_extension(&Extension::default(), &Extension::default())
+        .unwrap());
+    assert_eq!(
+        store.add_transition_if_absent(&transition).unwrap(),
+        Some(transition.clone())
+    );
+    assert_eq!(
+        store
+            .add_extension_if_absent(&Extension::default())
+            .unwrap(),
+        None
+    );
+    assert!(store
+        .remove_extension(&Extension::default().node_id())
+        .unwrap());
+    assert_eq!(
+        store
+            .transition_or_insert_with::<Box<dyn Error>, _>(&node_id, || unreachable!())
+            .unwrap(),
+        transition
+    );
+    assert!(store.remove_transition(&node_id).unwrap());
+    let err = store
+        .transition_or_insert_with::<Box<dyn Error>, _>(&missing, || Ok(transition.clone()))
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<IdMismatch>(),
+        Some(&IdMismatch {
+            expected: missing,
+            found: node_id
+        })
+    );
+    assert!(!store.has_transition(&missing).unwrap());
+    assert!(!store.has_transition(&node_id).unwrap());
+    assert_eq!(
+        store
+            .transition_or_insert_with::<Box<dyn Error>, _>(&node_id, || Ok(transition.clone()))
+            .unwrap(),
+        transition
+    );
+    assert!(store.has_transition(&node_id).unwrap());
+
+    let mut visited = vec![];
+    store
+        .for_each_transition::<S::Error, _>(|id, transition| {
+            visited.push((id, transition));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(visited, vec![(node_id, transition)]);
+}
+
+fn check_clear<S: Store>(store: &mut S) {
+    store.add_schema(&rgb20::schema::schema()).unwrap();
+    store.add_genesis(&Genesis::default()).unwrap();
+    store.add_transition(&Transition::default()).unwrap();
+    store.add_extension(&Extension::default()).unwrap();
+    assert_eq!(
+        store.contract_id_set().unwrap(),
+        bset! {Genesis::default().contract_id()}
+    );
+    assert_eq!(
+        store.transition_id_set().unwrap(),
+        bset! {Transition::default().node_id()}
+    );
+    assert_eq!(
+        store.extension_id_set().unwrap(),
+        bset! {Extension::default().node_id()}
+    );
+    assert!(store.anchor_id_set().unwrap().is_empty());
+    let contract_id = Genesis::default().contract_id();
+    let bech32 = contract_id.to_bech32().to_string();
+    assert_eq!(store.contract_ids_with_prefix(&bech32[..8]).unwrap(), vec![
+        contract_id
+    ]);
+    assert!(store.contract_ids_with_prefix("rgb0").unwrap().is_empty());
+    let schema_id = rgb20::schema::schema().schema_id();
+    let bech32 = schema_id.to_bech32().to_string();
+    assert_eq!(store.schema_ids_with_prefix(&bech32).unwrap(), vec![
+        schema_id
+    ]);
+    assert_eq!(
+        store
+            .contract_ids_by_schema(&Genesis::default().schema_id())
+            .unwrap(),
+        vec![Genesis::default().contract_id()]
+    );
+    assert!(store
+        .contract_ids_by_schema(&rgb20::schema::schema().schema_id())
+        .unwrap()
+        .is_empty());
+    store.flush().unwrap();
+    store.clear().unwrap();
+    check_empty(store);
+}
@@ -0,0 +1,467 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use bp::dbc::AnchorId;
+use rgb::prelude::*;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{DiskStorage, DiskStorageError};
+use crate::error::ServiceErrorDomain;
+use crate::stashd::index::Index;
+use crate::stashd::storage::{ObjectKind, Store};
+use crate::util::MagicNumber;
+
+/// Number of items of a single kind processed by
+/// [`DiskStorage::import_archive`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct ImportCount {
+    /// Items which were not present in the storage before the import
+    pub added: usize,
+    /// Items which were already present in the storage
+    pub present: usize,
+}
+
+/// Archive item skipped by [`DiskStorage::import_archive`] since it can't be
+/// decoded
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct ImportFailure {
+    /// Archive section the item belongs to
+    pub section: ObjectKind,
+    /// Position of the item inside its section
+    pub index: u64,
+    pub error: String,
+}
+
+/// Statistics returned by [`DiskStorage::import_archive`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct ImportStats {
+    pub schemata: ImportCount,
+    pub geneses: ImportCount,
+    pub anchors: ImportCount,
+    pub transitions: ImportCount,
+    pub extensions: ImportCount,
+    /// Items which were skipped because of decoding errors
+    pub failures: Vec<ImportFailure>,
+}
+
+impl DiskStorage {
+    /// Exports schema as a pretty-printed JSON for diagnostic purposes
+    #[cfg(feature = "serde")]
+    pub fn export_schema_json(&self, id: &SchemaId) -> Result<String, DiskStorageError> {
+        Self::export_json(id, &self.schema(id)?)
+    }
+
+    /// Exports genesis as a pretty-printed JSON for diagnostic purposes
+    #[cfg(feature = "serde")]
+    pub fn export_genesis_json(&self, id: &ContractId) -> Result<String, DiskStorageError> {
+        Self::export_json(id, &self.genesis(id)?)
+    }
+
+    /// Exports anchor as a pretty-printed JSON for diagnostic purposes
+    #[cfg(feature = "serde")]
+    pub fn export_anchor_json(&self, id: &AnchorId) -> Result<String, DiskStorageError> {
+        Self::export_json(id, &self.anchor(id)?)
+    }
+
+    /// Exports state transition as a pretty-printed JSON for diagnostic
+    /// purposes
+    #[cfg(feature = "serde")]
+    pub fn export_transition_json(&self, id: &NodeId) -> Result<String, DiskStorageError> {
+        Self::export_json(id, &self.transition(id)?)
+    }
+
+    /// Exports state extension as a pretty-printed JSON for diagnostic
+    /// purposes
+    #[cfg(feature = "serde")]
+    pub fn export_extension_json(&self, id: &NodeId) -> Result<String, DiskStorageError> {
+        Self::export_json(id, &self.extension(id)?)
+    }
+
+    /// Serializes the object into JSON, adding `id` field to it. The output is
+    /// not guaranteed to be convertible back into the object.
+    #[cfg(feature = "serde")]
+    fn export_json(id: &impl Display, object: &impl Serialize) -> Result<String, DiskStorageError> {
+        let mut value = serde_json::to_value(object)?;
+        if let serde_json::Value::Object(ref mut map) = value {
+            map.insert(s!("id"), serde_json::Value::String(id.to_string()));
+        }
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    /// Streams all stored data into a single archive.
+    ///
+    /// The archive starts with [`MagicNumber::Stash`] followed by sections
+    /// for schemata, geneses, anchors, transitions and extensions, in this
+    /// order. Each section has a header consisting of the magic number of
+    /// the object type and the number of the items in the section; each
+    /// item is written as its strict-encoded length-prefixed data.
+    pub fn export_archive<W: Write>(&self, mut w: W) -> Result<(), DiskStorageError> {
+        w.write_all(&MagicNumber::Stash.to_u32().to_be_bytes())?;
+        self.export_section(
+            &mut w,
+            MagicNumber::Schema,
+            self.schema_ids()?,
+            Self::schema,
+        )?;
+        self.export_section(
+            &mut w,
+            MagicNumber::Genesis,
+            self.contract_ids()?,
+            Self::genesis,
+        )?;
+        self.export_section(
+            &mut w,
+            MagicNumber::Anchor,
+            self.anchor_ids()?,
+            Self::anchor,
+        )?;
+        self.export_section(
+            &mut w,
+            MagicNumber::Transition,
+            self.transition_ids()?,
+            Self::transition,
+        )?;
+        self.export_section(
+            &mut w,
+            MagicNumber::Extension,
+            self.extension_ids()?,
+            Self::extension,
+        )?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Streams data of a single contract into an archive of the same format
+    /// as produced by [`DiskStorage::export_archive`], so it can be added to
+    /// another storage with [`DiskStorage::import_archive`].
+    ///
+    /// The archive contains schema and genesis of the contract, its state
+    /// transitions (selected as by [`Store::transitions_by_contract`]) and
+    /// state extensions, and the anchors the `index` knows for these
+    /// transitions and extensions. An anchor shared by several nodes is
+    /// written only once; nodes unknown to the index are exported without
+    /// anchors.
+    pub fn export_contract<I: Index, W: Write>(
+        &self,
+        contract_id: &ContractId,
+        index: &I,
+        mut w: W,
+    ) -> Result<(), ServiceErrorDomain> {
+        let (genesis, schema) = self.schema_for_genesis(contract_id)?;
+        let transitions = self.transitions_by_contract(contract_id, index)?;
+        let mut extensions = vec![];
+        self.for_each_extension::<DiskStorageError, _>(|_, extension| {
+            if extension.contract_id() == Some(*contract_id) {
+                extensions.push(extension);
+            }
+            Ok(())
+        })?;
+
+        let mut anchor_ids = BTreeSet::new();
+        let anchored = transitions
+            .iter()
+            .map(|transition| index.anchor_id_by_transition_id(transition.node_id()))
+            .chain(
+                extensions
+                    .iter()
+                    .map(|extension| index.anchor_id_by_extension_id(extension.node_id())),
+            );
+        for anchor_id in anchored {
+            match anchor_id {
+                Ok(anchor_id) => {
+                    anchor_ids.insert(anchor_id);
+                }
+                Err(err) if I::is_not_found(&err) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        w.write_all(&MagicNumber::Stash.to_u32().to_be_bytes())?;
+        Self::write_section(&mut w, MagicNumber::Schema, &[schema])?;
+        Self::write_section(&mut w, MagicNumber::Genesis, &[genesis])?;
+        // Anchors are loaded one by one as they are written
+        self.export_section(
+            &mut w,
+            MagicNumber::Anchor,
+            anchor_ids.into_iter().collect(),
+            Self::anchor,
+        )?;
+        Self::write_section(&mut w, MagicNumber::Transition, &transitions)?;
+        Self::write_section(&mut w, MagicNumber::Extension, &extensions)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Adds all data from an archive produced by
+    /// [`DiskStorage::export_archive`] to the storage.
+    ///
+    /// Items which can't be decoded are skipped and reported in
+    /// [`ImportStats::failures`], so a slightly damaged archive still gets
+    /// imported as much as possible. Fails with
+    /// [`DiskStorageError::BrokenArchive`] if the archive structure is
+    /// invalid; truncated archives fail with an I/O error. Items preceding
+    /// the failure remain added.
+    pub fn import_archive<R: Read>(&mut self, mut r: R) -> Result<ImportStats, DiskStorageError> {
+        self.check_writable()?;
+        Self::read_magic(&mut r, MagicNumber::Stash)?;
+        let mut failures = vec![];
+        Ok(ImportStats {
+            schemata: self.import_section(
+                &mut r,
+                ObjectKind::Schema,
+                MagicNumber::Schema,
+                Self::add_schema,
+                &mut failures,
+            )?,
+            geneses: self.import_section(
+                &mut r,
+                ObjectKind::Genesis,
+                MagicNumber::Genesis,
+                Self::add_genesis,
+                &mut failures,
+            )?,
+            anchors: self.import_section(
+                &mut r,
+                ObjectKind::Anchor,
+                MagicNumber::Anchor,
+                Self::add_anchor,
+                &mut failures,
+            )?,
+            transitions: self.import_section(
+                &mut r,
+                ObjectKind::Transition,
+                MagicNumber::Transition,
+                Self::add_transition,
+                &mut failures,
+            )?,
+            extensions: self.import_section(
+                &mut r,
+                ObjectKind::Extension,
+                MagicNumber::Extension,
+                Self::add_extension,
+                &mut failures,
+            )?,
+            failures,
+        })
+    }
+
+    fn export_section<T: StrictEncode, I>(
+        &self,
+        w: &mut impl Write,
+        magic: MagicNumber,
+        ids: Vec<I>,
+        load: impl Fn(&Self, &I) -> Result<T, DiskStorageError>,
+    ) -> Result<(), DiskStorageError> {
+        Self::write_section_header(w, magic, ids.len())?;
+        for id in ids {
+            Self::write_item(w, &load(self, &id)?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes section of the already loaded `items`
+    fn write_section<T: StrictEncode>(
+        w: &mut impl Write,
+        magic: MagicNumber,
+        items: &[T],
+    ) -> Result<(), DiskStorageError> {
+        Self::write_section_header(w, magic, items.len())?;
+        for item in items {
+            Self::write_item(w, item)?;
+        }
+        Ok(())
+    }
+
+    fn write_section_header(
+        w: &mut impl Write,
+        magic: MagicNumber,
+        len: usize,
+    ) -> Result<(), DiskStorageError> {
+        w.write_all(&magic.to_u32().to_be_bytes())?;
+        (len as u64).strict_encode(&mut *w)?;
+        Ok(())
+    }
+
+    fn write_item(w: &mut impl Write, item: &impl StrictEncode) -> Result<(), DiskStorageError> {
+        let data = item.strict_serialize()?;
+        (data.len() as u32).strict_encode(&mut *w)?;
+        w.write_all(&data)?;
+        Ok(())
+    }
+
+    fn import_section<T: StrictDecode>(
+        &mut self,
+        r: &mut impl Read,
+        kind: ObjectKind,
+        magic: MagicNumber,
+        add: impl Fn(&mut Self, &T) -> Result<bool, DiskStorageError>,
+        failures: &mut Vec<ImportFailure>,
+    ) -> Result<ImportCount, DiskStorageError> {
+        Self::read_magic(r, magic)?;
+        let mut count = ImportCount::default();
+        for index in 0..u64::strict_decode(&mut *r)? {
+            let len = u32::strict_decode(&mut *r)? as usize;
+            let mut data = Vec::with_capacity(len);
+            r.take(len as u64).read_to_end(&mut data)?;
+            if data.len() != len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            // Items are length-prefixed, so a broken item does not affect
+            // reading of the following ones
+            let item = match T::strict_deserialize(&data) {
+                Ok(item) => item,
+                Err(err) => {
+                    warn!(
+                        "Skipping broken {} #{} in the archive: {}",
+                        kind, index, err
+                    );
+                    failures.push(ImportFailure {
+                        section: kind,
+                        index,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if add(self, &item)? {
+                count.present += 1;
+            } else {
+                count.added += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn read_magic(r: &mut impl Read, expected: MagicNumber) -> Result<(), DiskStorageError> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if u32::from_be_bytes(magic) != expected.to_u32() {
+            return Err(DiskStorageError::BrokenArchive);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::dbc::Anchor;
+    use commit_verify::lnpbp4::MerkleBlock;
+    use rgb::prelude::*;
+
+    use super::*;
+    use crate::stashd::storage::DiskStorageConfig;
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_disk_archive_roundtrip() {
+        use crate::stashd::storage::sample_anchor;
+
+        let data_dir = TempPath::new("disk-archive");
+
+        let transition = Transition::default();
+        let anchor = sample_anchor(0, &[transition.node_id()]);
+        let mut storage =
+            DiskStorage::new(DiskStorageConfig::new(data_dir.join("source"))).unwrap();
+        storage.add_schema(&rgb20::schema::schema()).unwrap();
+        storage.add_genesis(&Genesis::default()).unwrap();
+        storage.add_anchor(&anchor).unwrap();
+        storage.add_transition(&transition).unwrap();
+        storage.add_extension(&Extension::default()).unwrap();
+        let mut archive = vec![];
+        storage.export_archive(&mut archive).unwrap();
+
+        let mut copy = DiskStorage::new(DiskStorageConfig::new(data_dir.join("copy"))).unwrap();
+        let added = ImportCount {
+            added: 1,
+            present: 0,
+        };
+        assert_eq!(copy.import_archive(&archive[..]).unwrap(), ImportStats {
+            schemata: added,
+            geneses: added,
+            anchors: added,
+            transitions: added,
+            extensions: added,
+            failures: vec![],
+        });
+        assert_eq!(copy.schema_ids().unwrap(), storage.schema_ids().unwrap());
+        assert_eq!(
+            copy.contract_ids().unwrap(),
+            storage.contract_ids().unwrap()
+        );
+        assert_eq!(copy.anchor(&anchor.anchor_id()).unwrap(), anchor);
+        assert_eq!(copy.transition(&transition.node_id()).unwrap(), transition);
+        assert_eq!(
+            copy.extension_ids().unwrap(),
+            storage.extension_ids().unwrap()
+        );
+        let stats = copy.import_archive(&archive[..]).unwrap();
+        assert_eq!(stats.transitions, ImportCount {
+            added: 0,
+            present: 1
+        });
+
+        let mut truncated = DiskStorage::new(DiskStorageConfig::new(data_dir.join("cut"))).unwrap();
+        assert!(matches!(
+            truncated.import_archive(&archive[..archive.len() - 3]),
+            Err(DiskStorageError::Io { .. }) | Err(DiskStorageError::Encoding(_))
+        ));
+        assert!(matches!(
+            truncated.import_archive(&archive[..2]),
+            Err(DiskStorageError::Io { .. })
+        ));
+        assert!(matches!(
+            truncated.import_archive(&archive[4..]),
+            Err(DiskStorageError::BrokenArchive)
+        ));
+        assert!(truncated.extension_ids().unwrap().is_empty());
+        assert!(truncated.add_extension(&Extension::default()).is_ok());
+
+        drop((storage, copy, truncated));
+    }
+
+    #[test]
+    fn test_disk_archive_failures() {
+        let data_dir = TempPath::new("disk-failures");
+
+        let transition = Transition::default();
+        let mut archive = MagicNumber::Stash.to_u32().to_be_bytes().to_vec();
+        DiskStorage::write_section::<Schema>(&mut archive, MagicNumber::Schema, &[]).unwrap();
+        DiskStorage::write_section(&mut archive, MagicNumber::Genesis, &[Genesis::default()])
+            .unwrap();
+        DiskStorage::write_section::<Anchor<MerkleBlock>>(&mut archive, MagicNumber::Anchor, &[])
+            .unwrap();
+        DiskStorage::write_section_header(&mut archive, MagicNumber::Transition, 2).unwrap();
+        // Length-prefixed item which is not a transition
+        archive.extend(&[3, 0, 0, 0, 0xFF, 0xFF, 0xFF]);
+        DiskStorage::write_item(&mut archive, &transition).unwrap();
+        DiskStorage::write_section::<Extension>(&mut archive, MagicNumber::Extension, &[]).unwrap();
+
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        let stats = storage.import_archive(&archive[..]).unwrap();
+        assert_eq!(stats.geneses.added, 1);
+        assert_eq!(stats.transitions.added, 1);
+        assert_eq!(stats.failures.len(), 1);
+        assert_eq!(stats.failures[0].section, ObjectKind::Transition);
+        assert_eq!(stats.failures[0].index, 0);
+        assert!(storage.has_transition(&transition.node_id()).unwrap());
+    }
+}
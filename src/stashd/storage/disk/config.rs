@@ -0,0 +1,976 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, iter};
+
+use bp::dbc::AnchorId;
+use rgb::prelude::*;
+
+use super::scan::{dir_filenames, object_file_name, read_filenames, shard_dirs};
+use super::{is_reserved_name, DiskStorageError};
+use crate::constants::{RGB_DATA_DIR, RGB_NETWORK, STASHD_STASH};
+use crate::stashd::storage::{
+    ConsignmentId, DefaultPathMapper, FileSystem, ObjectKind, PathMapper, RetryPolicy,
+    StdFileSystem,
+};
+
+/// Object kept in a file of the storage, as identified by
+/// [`DiskStorageConfig::classify`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub enum StoredObjectRef {
+    Schema(SchemaId),
+    Genesis(ContractId),
+    Anchor(AnchorId),
+    Transition(NodeId),
+    Extension(NodeId),
+    Consignment(ConsignmentId),
+}
+
+impl StoredObjectRef {
+    /// Kind of the object; `None` for consignments
+    pub fn kind(&self) -> Option<ObjectKind> {
+        match self {
+            StoredObjectRef::Schema(_) => Some(ObjectKind::Schema),
+            StoredObjectRef::Genesis(_) => Some(ObjectKind::Genesis),
+            StoredObjectRef::Anchor(_) => Some(ObjectKind::Anchor),
+            StoredObjectRef::Transition(_) => Some(ObjectKind::Transition),
+            StoredObjectRef::Extension(_) => Some(ObjectKind::Extension),
+            StoredObjectRef::Consignment(_) => None,
+        }
+    }
+}
+
+/// Encoding of the files kept by [`DiskStorage`](super::DiskStorage), set
+/// with [`DiskStorageConfig::data_format`]. JSON and YAML require `serde`
+/// feature.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum DataFormat {
+    #[display("strict-encode")]
+    StrictEncode,
+
+    #[display("json")]
+    Json,
+
+    #[display("yaml")]
+    Yaml,
+}
+
+/// Level of zstd compression applied to the stored files
+#[cfg(feature = "compression")]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{0}")]
+pub struct CompressionLevel(pub i32);
+
+#[cfg(feature = "compression")]
+impl Default for CompressionLevel {
+    fn default() -> Self { CompressionLevel(zstd::DEFAULT_COMPRESSION_LEVEL) }
+}
+
+/// Callbacks invoked by [`DiskStorage`](super::DiskStorage) around each read,
+/// write and removal of a stored object, allowing to feed operation counts and
+/// latencies into a metrics library of choice. Callbacks are run synchronously
+/// by the thread doing the operation, so they must be cheap. All of them do
+/// nothing by default.
+///
+/// Durations include syncing of the written data to the disk; sizes are
+/// the ones of the stored files, so they are affected by the compression.
+pub trait StorageObserver: Debug + Send + Sync {
+    /// Object of `kind` was read from a file of `bytes` size
+    fn on_read(&self, _kind: ObjectKind, _bytes: u64, _duration: Duration) {}
+
+    /// Object of `kind` was written into a file of `bytes` size
+    fn on_write(&self, _kind: ObjectKind, _bytes: u64, _duration: Duration) {}
+
+    /// Object of `kind` was removed (or was already absent)
+    fn on_remove(&self, _kind: ObjectKind, _duration: Duration) {}
+
+    /// Operation over an object of `kind` failed. Reads of absent objects
+    /// are reported here with [`DiskStorageError::NotFound`].
+    fn on_error(&self, _kind: ObjectKind, _err: &DiskStorageError) {}
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(Debug)]
+pub struct DiskStorageConfig {
+    pub data_dir: PathBuf,
+
+    /// Flush each written file and its directory to the disk before
+    /// reporting success. Disabling speeds up bulk imports at the cost of
+    /// possible data loss on a crash.
+    pub sync_writes: bool,
+
+    /// Open the storage for reading only: all mutating operations fail with
+    /// [`DiskStorageError::ReadOnly`] and no directories are created.
+    ///
+    /// Read-only storages take a shared lock on the data directory, while
+    /// the writable ones take an exclusive lock, so a data directory may be
+    /// opened either by any number of readers or by a single writer. Readers
+    /// never write into the data directory, so if the lock file is missing
+    /// (the data directory has never been opened for writing) they read it
+    /// without the lock.
+    pub read_only: bool,
+
+    /// Wait for the lock on the data directory held by other processes to be
+    /// released, instead of failing with [`DiskStorageError::Locked`] at once
+    pub wait_for_lock: bool,
+
+    /// Prefix prepended to the names of all subdirectories (like
+    /// `signet-anchors`), allowing multiple networks to share the same data
+    /// directory
+    pub network_prefix: Option<String>,
+
+    /// Write SHA256 checksum of each stored file into `<name>.rgb.sha256`
+    /// sidecar file and check it on each read. Files without a sidecar are
+    /// read without the check.
+    pub checksums: bool,
+
+    /// Write [`DiskStorageConfig::OBJECT_FORMAT_VERSION`] of each stored file
+    /// into `<name>.rgb.format` sidecar file, reported by
+    /// [`DiskStorage::transition_format_version`](super::DiskStorage::transition_format_version)
+    /// and similar methods. Files written with a different version fail to be
+    /// read with [`DiskStorageError::FormatVersionMismatch`] rather than with a
+    /// decoding error. Files without a sidecar are read without the check.
+    pub format_versions: bool,
+
+    /// Compress newly written files with zstd. Files are read regardless of
+    /// this setting, so plain and compressed files may be mixed in the same
+    /// data directory.
+    #[cfg(feature = "compression")]
+    pub compression: Option<CompressionLevel>,
+
+    /// Place stored files under two levels of subdirectories named after the
+    /// first two bytes of the object id (like `transitions/ab/cd/<id>.rgb`),
+    /// keeping directories small for huge stashes. Existing data directory
+    /// is converted into the configured layout with
+    /// [`DiskStorage::migrate_layout`](super::DiskStorage::migrate_layout).
+    pub sharded: bool,
+
+    /// Naming of the stored files, [`DefaultPathMapper`] unless a custom
+    /// layout is required
+    pub path_mapper: Arc<dyn PathMapper>,
+
+    /// File system keeping the data directory, [`StdFileSystem`] unless the
+    /// files are kept outside of the local disk
+    pub file_system: Arc<dyn FileSystem>,
+
+    /// Unix permission bits (like `0o700`) set on the directories created by
+    /// the storage, overriding the process umask. Ignored on other
+    /// platforms.
+    pub dir_mode: Option<u32>,
+
+    /// Unix permission bits (like `0o600`) set on the files written by the
+    /// storage, overriding the process umask. Ignored on other platforms.
+    pub file_mode: Option<u32>,
+
+    /// Instead of removing files of the deleted objects, mark them with
+    /// `<name>.rgb.deleted` tombstones keeping time of the deletion, so
+    /// deletions can be told apart from objects which were never stored.
+    /// Tombstoned objects are reported as absent; adding such object again
+    /// removes the tombstone. Old tombstones are removed together with their
+    /// files by
+    /// [`DiskStorage::purge_tombstones`](super::DiskStorage::purge_tombstones).
+    /// Tombstones are ignored (and the objects are visible again) when the
+    /// setting is off.
+    pub tombstones: bool,
+
+    /// Recompute id of each object read from the disk and fail with
+    /// [`DiskStorageError::IdMismatch`] if it differs from the id in the name
+    /// of its file, catching misplaced or relabeled files.
+    /// [`DiskStorage::verify`](super::DiskStorage::verify) checks the ids
+    /// regardless of this setting.
+    pub verify_ids: bool,
+
+    /// Encoding of the stored objects. Besides the default strict encoding,
+    /// objects may be kept as JSON or YAML with `serde` feature, which is
+    /// useful for human-readable mirrors of the stash; these are never
+    /// compressed. The format of the existing files is not detected, so a
+    /// data directory must be always opened with the same format.
+    pub data_format: DataFormat,
+
+    /// Limit of the total size of the stored files (as reported by
+    /// [`DiskStorage::disk_usage`](super::DiskStorage::disk_usage)); writes
+    /// which would exceed it fail with [`DiskStorageError::QuotaExceeded`]
+    /// before anything is written. Sizes of the objects are estimated from
+    /// their uncompressed strict encoding.
+    pub max_data_dir_bytes: Option<u64>,
+
+    /// Free space which must remain on the file system keeping the data
+    /// directory after each write; writes fail with
+    /// [`DiskStorageError::InsufficientSpace`] otherwise. Not checked on file
+    /// systems unable to report their free space.
+    pub min_free_bytes: Option<u64>,
+
+    /// Limits of the sizes of the objects of each kind; objects of the kinds
+    /// absent from the map are not limited. Reads of the files larger than
+    /// the limit fail with [`DiskStorageError::ObjectTooLarge`] before their
+    /// data are decoded, protecting against objects which decode into huge
+    /// amounts of memory. Additions fail the same way before anything is
+    /// written, with the size estimated from the uncompressed strict
+    /// encoding of the object. Compressed files are limited by their
+    /// compressed size.
+    pub max_object_bytes: BTreeMap<ObjectKind, u64>,
+
+    /// Retry operations of the [`DiskStorageConfig::file_system`] failing
+    /// with transient errors, which is required for data directories on
+    /// network file systems. Operations fail on the first error if unset.
+    pub retry: Option<RetryPolicy>,
+
+    /// Receiver of the read, write and removal events of the stored objects,
+    /// allowing to collect storage metrics
+    pub observer: Option<Arc<dyn StorageObserver>>,
+}
+
+/// Uses the stash directory of the daemon running with the default data
+/// directory and network
+impl Default for DiskStorageConfig {
+    fn default() -> Self {
+        DiskStorageConfig::new(PathBuf::from(
+            STASHD_STASH
+                .replace("{data_dir}", RGB_DATA_DIR)
+                .replace("{network}", RGB_NETWORK),
+        ))
+    }
+}
+
+impl DiskStorageConfig {
+    pub const RGB_FILE_EXT: &'static str = "rgb";
+
+    /// Version of the data directory layout and stored objects encoding.
+    /// Must be increased each time the existing data directories become
+    /// unreadable by the new code.
+    pub const STORAGE_VERSION: u16 = 1;
+
+    /// Version of the strict encoding of the stored objects, which follows
+    /// the releases of rgb-core changing the encoding. Recorded for each
+    /// written file with [`DiskStorageConfig::format_versions`].
+    pub const OBJECT_FORMAT_VERSION: u16 = 1;
+
+    /// Number of the listed files starting from which the
+    /// [`Store`](crate::stashd::storage::Store) methods listing ids parse the
+    /// file names on the rayon thread pool, if the `rayon` feature is enabled
+    pub const PARALLEL_SCAN_MIN: usize = 4096;
+
+    /// Constructs configuration with default settings for the provided data
+    /// directory
+    #[inline]
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            data_dir,
+            sync_writes: true,
+            read_only: false,
+            wait_for_lock: false,
+            network_prefix: None,
+            checksums: false,
+            format_versions: false,
+            #[cfg(feature = "compression")]
+            compression: None,
+            sharded: false,
+            path_mapper: Arc::new(DefaultPathMapper),
+            file_system: Arc::new(StdFileSystem),
+            dir_mode: None,
+            file_mode: None,
+            tombstones: false,
+            verify_ids: false,
+            data_format: DataFormat::StrictEncode,
+            max_data_dir_bytes: None,
+            min_free_bytes: None,
+            max_object_bytes: bmap! {},
+            retry: None,
+            observer: None,
+        }
+    }
+
+    /// Sets [`DiskStorageConfig::sync_writes`]
+    #[inline]
+    pub fn with_sync_writes(mut self, sync_writes: bool) -> Self {
+        self.sync_writes = sync_writes;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::read_only`]
+    #[inline]
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::wait_for_lock`]
+    #[inline]
+    pub fn with_wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+        self.wait_for_lock = wait_for_lock;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::network_prefix`]
+    #[inline]
+    pub fn with_network_prefix(mut self, prefix: impl ToString) -> Self {
+        self.network_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::checksums`]
+    #[inline]
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::format_versions`]
+    #[inline]
+    pub fn with_format_versions(mut self, format_versions: bool) -> Self {
+        self.format_versions = format_versions;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::compression`]
+    #[cfg(feature = "compression")]
+    #[inline]
+    pub fn with_compression(mut self, compression: Option<CompressionLevel>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::sharded`]
+    #[inline]
+    pub fn with_sharded(mut self, sharded: bool) -> Self {
+        self.sharded = sharded;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::path_mapper`]
+    #[inline]
+    pub fn with_path_mapper(mut self, path_mapper: impl PathMapper + 'static) -> Self {
+        self.path_mapper = Arc::new(path_mapper);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::file_system`]
+    #[inline]
+    pub fn with_file_system(mut self, file_system: impl FileSystem + 'static) -> Self {
+        self.file_system = Arc::new(file_system);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::dir_mode`]
+    #[inline]
+    pub fn with_dir_mode(mut self, dir_mode: u32) -> Self {
+        self.dir_mode = Some(dir_mode);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::file_mode`]
+    #[inline]
+    pub fn with_file_mode(mut self, file_mode: u32) -> Self {
+        self.file_mode = Some(file_mode);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::tombstones`]
+    #[inline]
+    pub fn with_tombstones(mut self, tombstones: bool) -> Self {
+        self.tombstones = tombstones;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::verify_ids`]
+    #[inline]
+    pub fn with_verify_ids(mut self, verify_ids: bool) -> Self {
+        self.verify_ids = verify_ids;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::data_format`]
+    #[inline]
+    pub fn with_data_format(mut self, data_format: DataFormat) -> Self {
+        self.data_format = data_format;
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::max_data_dir_bytes`]
+    #[inline]
+    pub fn with_max_data_dir_bytes(mut self, max_data_dir_bytes: u64) -> Self {
+        self.max_data_dir_bytes = Some(max_data_dir_bytes);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::min_free_bytes`]
+    #[inline]
+    pub fn with_min_free_bytes(mut self, min_free_bytes: u64) -> Self {
+        self.min_free_bytes = Some(min_free_bytes);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::max_object_bytes`] limit for the objects of
+    /// the `kind`
+    #[inline]
+    pub fn with_max_object_bytes(mut self, kind: ObjectKind, max_object_bytes: u64) -> Self {
+        self.max_object_bytes.insert(kind, max_object_bytes);
+        self
+    }
+
+    /// Fails with [`DiskStorageError::UnsupportedFormat`] if objects can't be
+    /// kept in the configured [`DiskStorageConfig::data_format`]
+    pub fn check_data_format(&self) -> Result<(), DiskStorageError> {
+        match self.data_format {
+            DataFormat::StrictEncode => Ok(()),
+            #[cfg(feature = "serde")]
+            DataFormat::Json | DataFormat::Yaml => Ok(()),
+            #[cfg(not(feature = "serde"))]
+            format => Err(DiskStorageError::UnsupportedFormat(format)),
+        }
+    }
+
+    /// Sets [`DiskStorageConfig::retry`]
+    #[inline]
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Sets [`DiskStorageConfig::observer`]
+    #[inline]
+    pub fn with_observer(mut self, observer: Arc<dyn StorageObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    pub const CHECKSUM_FILE_EXT: &'static str = "sha256";
+
+    /// Sidecar file keeping checksum of the stored file
+    #[inline]
+    pub fn checksum_filename(filename: &Path) -> PathBuf {
+        let mut name = filename.as_os_str().to_os_string();
+        name.push(".");
+        name.push(Self::CHECKSUM_FILE_EXT);
+        PathBuf::from(name)
+    }
+
+    pub const FORMAT_FILE_EXT: &'static str = "format";
+
+    /// Sidecar file keeping format version of the stored file, used with
+    /// [`DiskStorageConfig::format_versions`]
+    #[inline]
+    pub fn format_filename(filename: &Path) -> PathBuf {
+        let mut name = filename.as_os_str().to_os_string();
+        name.push(".");
+        name.push(Self::FORMAT_FILE_EXT);
+        PathBuf::from(name)
+    }
+
+    pub const TOMBSTONE_FILE_EXT: &'static str = "deleted";
+
+    /// Marker of the deleted object kept in the file, used with
+    /// [`DiskStorageConfig::tombstones`]
+    #[inline]
+    pub fn tombstone_filename(filename: &Path) -> PathBuf {
+        let mut name = filename.as_os_str().to_os_string();
+        name.push(".");
+        name.push(Self::TOMBSTONE_FILE_EXT);
+        PathBuf::from(name)
+    }
+
+    pub const EXPIRY_FILE_EXT: &'static str = "expires";
+
+    /// Marker keeping expiry time of the object kept in the file, written by
+    /// [`DiskStorage::add_transition_with_ttl`](super::DiskStorage::add_transition_with_ttl)
+    #[inline]
+    pub fn expiry_filename(filename: &Path) -> PathBuf {
+        let mut name = filename.as_os_str().to_os_string();
+        name.push(".");
+        name.push(Self::EXPIRY_FILE_EXT);
+        PathBuf::from(name)
+    }
+
+    /// Tells whether the object kept in the file is deleted, provided
+    /// [`DiskStorageConfig::tombstones`] are enabled
+    #[inline]
+    pub fn is_tombstoned(&self, filename: &Path) -> bool {
+        self.tombstones && self.file_system.exists(&Self::tombstone_filename(filename))
+    }
+
+    /// Applies [`DiskStorageConfig::network_prefix`] to the name
+    #[inline]
+    pub(super) fn prefixed(&self, name: &str) -> String {
+        match self.network_prefix {
+            Some(ref prefix) => format!("{}-{}", prefix, name),
+            None => name.to_owned(),
+        }
+    }
+
+    /// File keeping version of the storage format used by the data directory
+    #[inline]
+    pub fn version_filename(&self) -> PathBuf {
+        self.data_dir
+            .join(format!(".{}", self.prefixed("rgb-storage-version")))
+    }
+
+    /// File used to hold a lock on the data directory; see
+    /// [`DiskStorageConfig::read_only`]
+    #[inline]
+    pub fn lock_filename(&self) -> PathBuf {
+        self.data_dir.join(format!(".{}", self.prefixed("lock")))
+    }
+
+    /// Journal of the files changed by a batch of writes which is not committed
+    /// yet; see [`DiskStorage::begin_batch`](super::DiskStorage::begin_batch)
+    #[inline]
+    pub fn journal_filename(&self) -> PathBuf {
+        self.data_dir.join(format!(".{}", self.prefixed("journal")))
+    }
+
+    /// Directory keeping copies of the files which existed before being
+    /// changed by a batch of writes which is not committed yet, restored if
+    /// the batch is rolled back
+    #[inline]
+    pub fn journal_backup_dir(&self) -> PathBuf {
+        self.data_dir
+            .join(format!(".{}", self.prefixed("journal-backup")))
+    }
+
+    #[inline]
+    pub fn schemata_dir(&self) -> PathBuf { self.data_dir.join(self.prefixed("schemata")) }
+
+    #[inline]
+    pub fn geneses_dir(&self) -> PathBuf { self.data_dir.join(self.prefixed("geneses")) }
+
+    #[inline]
+    pub fn anchors_dir(&self) -> PathBuf { self.data_dir.join(self.prefixed("anchors")) }
+
+    #[inline]
+    pub fn transitions_dir(&self) -> PathBuf { self.data_dir.join(self.prefixed("transitions")) }
+
+    #[inline]
+    pub fn extensions_dir(&self) -> PathBuf { self.data_dir.join(self.prefixed("extensions")) }
+
+    #[inline]
+    pub fn consignments_dir(&self) -> PathBuf { self.data_dir.join(self.prefixed("consignments")) }
+
+    /// Returns directory keeping objects of the given kind
+    pub fn kind_dir(&self, kind: ObjectKind) -> PathBuf {
+        match kind {
+            ObjectKind::Schema => self.schemata_dir(),
+            ObjectKind::Genesis => self.geneses_dir(),
+            ObjectKind::Anchor => self.anchors_dir(),
+            ObjectKind::Transition => self.transitions_dir(),
+            ObjectKind::Extension => self.extensions_dir(),
+        }
+    }
+
+    /// Composes name of the file keeping object with the given `id` inside
+    /// the category `dir`, applying [`DiskStorageConfig::sharded`] layout
+    fn object_filename(&self, dir: PathBuf, id: &[u8], name: PathBuf) -> PathBuf {
+        let dir = if self.sharded {
+            dir.join(format!("{:02x}", id[0]))
+                .join(format!("{:02x}", id[1]))
+        } else {
+            dir
+        };
+        dir.join(name)
+    }
+
+    #[inline]
+    pub fn schema_filename(&self, schema_id: &SchemaId) -> PathBuf {
+        self.object_filename(
+            self.schemata_dir(),
+            &schema_id[..],
+            self.path_mapper.schema_path(schema_id),
+        )
+    }
+
+    #[inline]
+    pub fn genesis_filename(&self, contract_id: &ContractId) -> PathBuf {
+        self.object_filename(
+            self.geneses_dir(),
+            &contract_id[..],
+            self.path_mapper.genesis_path(contract_id),
+        )
+    }
+
+    #[inline]
+    pub fn anchor_filename(&self, anchor_id: &AnchorId) -> PathBuf {
+        self.object_filename(
+            self.anchors_dir(),
+            &anchor_id[..],
+            self.path_mapper.anchor_path(anchor_id),
+        )
+    }
+
+    #[inline]
+    pub fn transition_filename(&self, node_id: &NodeId) -> PathBuf {
+        self.object_filename(
+            self.transitions_dir(),
+            &node_id[..],
+            self.path_mapper.transition_path(node_id),
+        )
+    }
+
+    #[inline]
+    pub fn extension_filename(&self, node_id: &NodeId) -> PathBuf {
+        self.object_filename(
+            self.extensions_dir(),
+            &node_id[..],
+            self.path_mapper.extension_path(node_id),
+        )
+    }
+
+    #[inline]
+    pub fn consignment_filename(&self, id: &ConsignmentId) -> PathBuf {
+        self.object_filename(
+            self.consignments_dir(),
+            &id[..],
+            self.path_mapper.consignment_path(id),
+        )
+    }
+
+    /// Parses id of the schema from the name of its file.
+    ///
+    /// Each of the `parse_*_filename` methods is the inverse of the matching
+    /// `*_filename` method: for any id `parse_x_filename(&x_filename(&id))`
+    /// returns `Some(id)`. Only the last component of the path is parsed, so
+    /// both full paths and bare file names are accepted; names reserved for
+    /// checksum sidecars and temporary files are never parsed as ids.
+    #[inline]
+    pub fn parse_schema_filename(&self, filename: &Path) -> Option<SchemaId> {
+        self.path_mapper
+            .parse_schema_path(object_file_name(filename)?)
+    }
+
+    #[inline]
+    pub fn parse_genesis_filename(&self, filename: &Path) -> Option<ContractId> {
+        self.path_mapper
+            .parse_genesis_path(object_file_name(filename)?)
+    }
+
+    #[inline]
+    pub fn parse_anchor_filename(&self, filename: &Path) -> Option<AnchorId> {
+        self.path_mapper
+            .parse_anchor_path(object_file_name(filename)?)
+    }
+
+    #[inline]
+    pub fn parse_transition_filename(&self, filename: &Path) -> Option<NodeId> {
+        self.path_mapper
+            .parse_transition_path(object_file_name(filename)?)
+    }
+
+    #[inline]
+    pub fn parse_extension_filename(&self, filename: &Path) -> Option<NodeId> {
+        self.path_mapper
+            .parse_extension_path(object_file_name(filename)?)
+    }
+
+    #[inline]
+    pub fn parse_consignment_filename(&self, filename: &Path) -> Option<ConsignmentId> {
+        self.path_mapper
+            .parse_consignment_path(object_file_name(filename)?)
+    }
+
+    /// Parses name of a file found among the files of the `kind` objects,
+    /// returning the path where the object with the parsed id is kept.
+    /// Returns `None` for names which are not names of the stored objects.
+    pub fn object_location(&self, kind: ObjectKind, name: &str) -> Option<PathBuf> {
+        let name = Path::new(name);
+        match kind {
+            ObjectKind::Schema => self
+                .parse_schema_filename(name)
+                .map(|id| self.schema_filename(&id)),
+            ObjectKind::Genesis => self
+                .parse_genesis_filename(name)
+                .map(|id| self.genesis_filename(&id)),
+            ObjectKind::Anchor => self
+                .parse_anchor_filename(name)
+                .map(|id| self.anchor_filename(&id)),
+            ObjectKind::Transition => self
+                .parse_transition_filename(name)
+                .map(|id| self.transition_filename(&id)),
+            ObjectKind::Extension => self
+                .parse_extension_filename(name)
+                .map(|id| self.extension_filename(&id)),
+        }
+    }
+
+    /// Tells which object is kept in the file at `path`, reversing the
+    /// `*_filename` methods: the category of the object is found from the
+    /// directory containing the file, and its id is parsed from the file
+    /// name. `path` must start with [`DiskStorageConfig::data_dir`] spelled
+    /// the same way.
+    ///
+    /// Files of the categories which names are not valid ids (including the
+    /// sidecars) are reported with [`DiskStorageError::BadSchemaId`] and
+    /// similar errors. Paths outside of the categories, as well as object
+    /// files kept outside their location in the configured layout, are
+    /// reported with [`DiskStorageError::UnknownPath`].
+    pub fn classify(&self, path: &Path) -> Result<StoredObjectRef, DiskStorageError> {
+        let unknown = || DiskStorageError::UnknownPath {
+            path: path.to_owned(),
+        };
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let (object, location) = if path.starts_with(self.consignments_dir()) {
+            let id =
+                self.parse_consignment_filename(path)
+                    .ok_or_else(|| DiskStorageError::BadHex {
+                        filename: name.to_owned(),
+                    })?;
+            (
+                StoredObjectRef::Consignment(id),
+                self.consignment_filename(&id),
+            )
+        } else {
+            let kind = ObjectKind::ALL
+                .iter()
+                .copied()
+                .find(|kind| path.starts_with(self.kind_dir(*kind)))
+                .ok_or_else(unknown)?;
+            let bad = |filename: String| match kind {
+                ObjectKind::Schema => DiskStorageError::BadSchemaId { filename },
+                ObjectKind::Genesis => DiskStorageError::BadContractId { filename },
+                _ => DiskStorageError::BadHex { filename },
+            };
+            match kind {
+                ObjectKind::Schema => self
+                    .parse_schema_filename(path)
+                    .map(|id| (StoredObjectRef::Schema(id), self.schema_filename(&id))),
+                ObjectKind::Genesis => self
+                    .parse_genesis_filename(path)
+                    .map(|id| (StoredObjectRef::Genesis(id), self.genesis_filename(&id))),
+                ObjectKind::Anchor => self
+                    .parse_anchor_filename(path)
+                    .map(|id| (StoredObjectRef::Anchor(id), self.anchor_filename(&id))),
+                ObjectKind::Transition => self.parse_transition_filename(path).map(|id| {
+                    (
+                        StoredObjectRef::Transition(id),
+                        self.transition_filename(&id),
+                    )
+                }),
+                ObjectKind::Extension => self
+                    .parse_extension_filename(path)
+                    .map(|id| (StoredObjectRef::Extension(id), self.extension_filename(&id))),
+            }
+            .ok_or_else(|| bad(name.to_owned()))?
+        };
+        if location != path {
+            return Err(unknown());
+        }
+        Ok(object)
+    }
+
+    /// Lists directories which directly contain files of the category `dir`:
+    /// the category directory itself for the flat layout, or all of its
+    /// shard directories for the sharded one
+    pub fn object_dirs(&self, dir: PathBuf) -> Result<Vec<PathBuf>, io::Error> {
+        if self.sharded {
+            shard_dirs(&*self.file_system, &dir)
+        } else {
+            Ok(vec![dir])
+        }
+    }
+
+    /// Lists paths of all files keeping objects of the given kind
+    pub fn object_paths(&self, kind: ObjectKind) -> Result<Vec<PathBuf>, io::Error> {
+        let mut paths = vec![];
+        for dir in self.object_dirs(self.kind_dir(kind))? {
+            for name in read_filenames(&*self.file_system, &dir)? {
+                if self.object_location(kind, &name).is_some() {
+                    paths.push(dir.join(name));
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Lazily reads names of the files found among the files of the category
+    /// `dir`, except checksum sidecars, tombstones, expiry markers, temporary
+    /// files and files of the tombstoned objects. In the sharded layout shard directories are
+    /// opened one by one as the iterator advances.
+    pub(super) fn object_names_iter(
+        &self,
+        dir: PathBuf,
+    ) -> Result<impl Iterator<Item = Result<String, io::Error>>, io::Error> {
+        let tombstones = self.tombstones;
+        let file_system = self.file_system.clone();
+        Ok(self
+            .object_dirs(dir)?
+            .into_iter()
+            .flat_map(move |dir| {
+                let fs = file_system.clone();
+                let names: Box<dyn Iterator<Item = Result<String, io::Error>>> =
+                    match dir_filenames(&*fs, &dir) {
+                        Ok(names) => Box::new(names.filter(move |name| {
+                            !matches!(name, Ok(name) if tombstones
+                                && fs.exists(&DiskStorageConfig::tombstone_filename(&dir.join(name))))
+                        })),
+                        Err(err) => Box::new(iter::once(Err(err))),
+                    };
+                names
+            })
+            .filter(|name| !matches!(name, Ok(name) if is_reserved_name(name))))
+    }
+
+    #[inline]
+    pub fn schema_names_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<String, io::Error>>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Schema))
+    }
+
+    #[inline]
+    pub fn genesis_names_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<String, io::Error>>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Genesis))
+    }
+
+    #[inline]
+    pub fn schema_names(&self) -> Result<Vec<String>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Schema))?
+            .collect()
+    }
+
+    #[inline]
+    pub fn genesis_names(&self) -> Result<Vec<String>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Genesis))?
+            .collect()
+    }
+
+    #[inline]
+    pub fn anchor_names(&self) -> Result<Vec<String>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Anchor))?
+            .collect()
+    }
+
+    #[inline]
+    pub fn transition_names(&self) -> Result<Vec<String>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Transition))?
+            .collect()
+    }
+
+    #[inline]
+    pub fn extension_names(&self) -> Result<Vec<String>, io::Error> {
+        self.object_names_iter(self.kind_dir(ObjectKind::Extension))?
+            .collect()
+    }
+
+    #[inline]
+    pub fn consignment_names(&self) -> Result<Vec<String>, io::Error> {
+        self.object_names_iter(self.consignments_dir())?.collect()
+    }
+
+    /// Counts files of the `kind` objects without parsing their names: the
+    /// files skipped by [`DiskStorageConfig::schema_names`] and the like, as
+    /// well as the files rejected by [`PathMapper::is_object_name`], are not
+    /// counted.
+    pub fn object_count(&self, kind: ObjectKind) -> Result<usize, io::Error> {
+        let mut count = 0usize;
+        for name in self.object_names_iter(self.kind_dir(kind))? {
+            if self.path_mapper.is_object_name(&name?) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn test_disk_classify() {
+        let config = DiskStorageConfig::new(PathBuf::from("/data")).with_sharded(true);
+        let schema_id = rgb20::schema::schema().schema_id();
+        let node_id = Extension::default().node_id();
+        assert_eq!(
+            config.classify(&config.schema_filename(&schema_id)).ok(),
+            Some(StoredObjectRef::Schema(schema_id))
+        );
+        assert_eq!(
+            config.classify(&config.extension_filename(&node_id)).ok(),
+            Some(StoredObjectRef::Extension(node_id))
+        );
+        let flat = config
+            .extensions_dir()
+            .join(config.extension_filename(&node_id).file_name().unwrap());
+        assert!(matches!(
+            config.classify(&flat),
+            Err(DiskStorageError::UnknownPath { .. })
+        ));
+        let checksum = DiskStorageConfig::checksum_filename(&config.transition_filename(&node_id));
+        assert!(matches!(
+            config.classify(&checksum),
+            Err(DiskStorageError::BadHex { .. })
+        ));
+        assert!(matches!(
+            config.classify(&config.version_filename()),
+            Err(DiskStorageError::UnknownPath { .. })
+        ));
+    }
+
+    #[test]
+    fn test_disk_filename_roundtrip() {
+        let schema_id = SchemaId::from_inner(Hash::hash(b"schema"));
+        let contract_id = ContractId::from_inner(Hash::hash(b"genesis"));
+        let anchor_id = AnchorId::from_inner(Hash::hash(b"anchor"));
+        let node_id = NodeId::from_inner(Hash::hash(b"node"));
+        let consignment_id = ConsignmentId::from_inner(Hash::hash(b"consignment"));
+
+        let flat = DiskStorageConfig::new(PathBuf::from("data"));
+        let sharded = flat
+            .clone()
+            .with_sharded(true)
+            .with_network_prefix("testnet");
+        for config in &[flat, sharded] {
+            assert_eq!(
+                config.parse_schema_filename(&config.schema_filename(&schema_id)),
+                Some(schema_id)
+            );
+            assert_eq!(
+                config.parse_genesis_filename(&config.genesis_filename(&contract_id)),
+                Some(contract_id)
+            );
+            assert_eq!(
+                config.parse_anchor_filename(&config.anchor_filename(&anchor_id)),
+                Some(anchor_id)
+            );
+            assert_eq!(
+                config.parse_transition_filename(&config.transition_filename(&node_id)),
+                Some(node_id)
+            );
+            assert_eq!(
+                config.parse_extension_filename(&config.extension_filename(&node_id)),
+                Some(node_id)
+            );
+            assert_eq!(
+                config.parse_consignment_filename(&config.consignment_filename(&consignment_id)),
+                Some(consignment_id)
+            );
+            assert_eq!(
+                config.parse_anchor_filename(&DiskStorageConfig::checksum_filename(
+                    &config.anchor_filename(&anchor_id)
+                )),
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,248 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::fmt::{self, Debug, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::DataFormat;
+use crate::error::{BootstrapError, ServiceErrorDomain};
+use crate::stashd::storage::filesystem::IoContext;
+use crate::stashd::storage::IoOp;
+
+#[derive(Debug, Error, From)]
+pub enum DiskStorageError {
+    /// File system operation failed
+    Io {
+        op: IoOp,
+        path: PathBuf,
+        source: io::Error,
+    },
+
+    NotFound,
+
+    #[from(bitcoin::hashes::Error)]
+    HashName,
+
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    /// Name of the stored file can't be parsed as a schema id
+    BadSchemaId {
+        filename: String,
+    },
+
+    /// Name of the stored file can't be parsed as a contract id
+    BadContractId {
+        filename: String,
+    },
+
+    /// Name of the stored file can't be parsed as a hex-encoded id
+    BadHex {
+        filename: String,
+    },
+
+    BrokenArchive,
+
+    ReadOnly,
+
+    /// Data directory is locked by another process: either by a writer, or,
+    /// for the storage opened for writing, by readers
+    Locked {
+        holder: LockHolder,
+    },
+
+    /// A batch of writes is already started with
+    /// [`DiskStorage::begin_batch`](super::DiskStorage::begin_batch)
+    BatchInProgress,
+
+    /// There is no batch of writes to commit
+    NoBatch,
+
+    #[cfg(feature = "serde")]
+    #[from]
+    Json(serde_json::Error),
+
+    #[cfg(feature = "serde")]
+    #[from]
+    Yaml(serde_yaml::Error),
+
+    /// Objects can't be stored in the configured
+    /// [`DiskStorageConfig::data_format`](super::DiskStorageConfig::data_format)
+    UnsupportedFormat(DataFormat),
+
+    /// Write would make the stored files exceed
+    /// [`DiskStorageConfig::max_data_dir_bytes`](super::DiskStorageConfig::max_data_dir_bytes)
+    QuotaExceeded {
+        used: u64,
+        limit: u64,
+    },
+
+    /// Write would leave less than
+    /// [`DiskStorageConfig::min_free_bytes`](super::DiskStorageConfig::min_free_bytes)
+    /// of free space on the file system
+    InsufficientSpace {
+        available: u64,
+        required: u64,
+    },
+
+    /// Error reported by the index
+    Index(String),
+
+    /// Stored file does not match its checksum
+    ChecksumMismatch {
+        id: String,
+    },
+
+    /// Object exceeds
+    /// [`DiskStorageConfig::max_object_bytes`](super::DiskStorageConfig::max_object_bytes)
+    /// limit of its kind
+    ObjectTooLarge {
+        id: String,
+        size: u64,
+    },
+
+    /// Id computed from the stored object differs from the id in the name of
+    /// its file
+    IdMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    /// Data directory (or one of its parents) is a symlink which target does
+    /// not exist, like a volume which is not mounted
+    DataDirUnavailable {
+        path: PathBuf,
+    },
+
+    /// Data directory or one of the storage directories inside it does not
+    /// exist, reported by
+    /// [`DiskStorage::open_existing`](super::DiskStorage::open_existing)
+    DataDirNotFound {
+        path: PathBuf,
+    },
+
+    /// Path is not a location where the storage keeps any of its objects;
+    /// see [`DiskStorageConfig::classify`](super::DiskStorageConfig::classify)
+    UnknownPath {
+        path: PathBuf,
+    },
+
+    /// Data directory was created with a different version of the storage
+    /// format
+    IncompatibleVersion {
+        found: u16,
+        expected: u16,
+    },
+
+    /// Stored object was encoded with a different
+    /// [`DiskStorageConfig::OBJECT_FORMAT_VERSION`](super::DiskStorageConfig::OBJECT_FORMAT_VERSION)
+    /// and must be migrated before it can be read
+    FormatVersionMismatch {
+        path: PathBuf,
+        found: u16,
+        expected: u16,
+    },
+}
+
+impl DiskStorageError {
+    /// Attaches the operation and the path to an I/O error which did not
+    /// come from the file system and thus carries none; other errors are
+    /// returned intact
+    pub(super) fn with_path(self, op: IoOp, path: &Path) -> Self {
+        match self {
+            DiskStorageError::Io {
+                op: IoOp::Other,
+                source,
+                ..
+            } => DiskStorageError::Io {
+                op,
+                path: path.to_owned(),
+                source,
+            },
+            err => err,
+        }
+    }
+
+    /// Tells whether the error reports a stored file which name is not a
+    /// valid id
+    pub fn is_bad_filename(&self) -> bool {
+        matches!(
+            self,
+            DiskStorageError::BadSchemaId { .. }
+                | DiskStorageError::BadContractId { .. }
+                | DiskStorageError::BadHex { .. }
+        )
+    }
+}
+
+impl Display for DiskStorageError {
+    /// Displays I/O errors together with the failed operation and its path
+    /// (like `write "/data/anchors/<id>.rgb": permission denied`); other
+    /// errors are displayed in their debug form
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiskStorageError::Io { op, path, source } => {
+                write!(f, "{} {:?}: {}", op, path, source)
+            }
+            err => Debug::fmt(err, f),
+        }
+    }
+}
+
+impl From<io::Error> for DiskStorageError {
+    /// Recovers the operation and the path attached to the errors by the
+    /// file system of the storage; other errors are reported as
+    /// [`IoOp::Other`] with an empty path
+    fn from(err: io::Error) -> Self {
+        if !err.get_ref().is_some_and(|inner| inner.is::<IoContext>()) {
+            return DiskStorageError::Io {
+                op: IoOp::Other,
+                path: PathBuf::new(),
+                source: err,
+            };
+        }
+        let IoContext { op, path, source } = *err
+            .into_inner()
+            .and_then(|inner| inner.downcast().ok())
+            .expect("error is checked to carry IoContext");
+        DiskStorageError::Io { op, path, source }
+    }
+}
+
+impl From<DiskStorageError> for ServiceErrorDomain {
+    fn from(err: DiskStorageError) -> Self {
+        match err {
+            DiskStorageError::NotFound => ServiceErrorDomain::NotFound,
+            err => ServiceErrorDomain::Storage(err.to_string()),
+        }
+    }
+}
+
+impl From<DiskStorageError> for BootstrapError {
+    fn from(_: DiskStorageError) -> Self { BootstrapError::StorageError }
+}
+
+/// Owner of the lock on the data directory conflicting with the storage
+/// being opened, reported by [`DiskStorageError::Locked`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum LockHolder {
+    /// One or more processes which opened the storage read-only
+    #[display("readers")]
+    Readers,
+
+    /// Process which opened the storage for writing
+    #[display("writer")]
+    Writer,
+}
@@ -0,0 +1,430 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::path::{Path, PathBuf};
+use std::{io, iter};
+
+use super::scan::{read_filenames, shard_dirs};
+use super::{ensure_dir, set_mode, DiskStorage, DiskStorageConfig, DiskStorageError};
+use crate::stashd::storage::ObjectKind;
+use crate::util::file::*;
+
+/// Prefix of the journal lines recording files which existed before the
+/// batch, followed by the number of their copy in
+/// [`DiskStorageConfig::journal_backup_dir`] and the name of the file. Other
+/// lines name files created by the batch.
+const SAVED_PREFIX: &str = "saved ";
+
+/// Stored file followed by its checksum and format sidecars, tombstone and
+/// expiry marker
+fn object_files(filename: &Path) -> [PathBuf; 5] {
+    [
+        filename.to_owned(),
+        DiskStorageConfig::checksum_filename(filename),
+        DiskStorageConfig::format_filename(filename),
+        DiskStorageConfig::tombstone_filename(filename),
+        DiskStorageConfig::expiry_filename(filename),
+    ]
+}
+
+impl DiskStorage {
+    /// Starts a batch of writes which is applied atomically with respect to
+    /// crashes: each object file changed by the batch is recorded in the
+    /// journal before it is written, and if the batch is not finished with
+    /// [`DiskStorage::commit`] (because of a crash or the storage being
+    /// dropped), the next [`DiskStorage::new`] on the same data directory
+    /// undoes all of the changes, rolling the stash back to its state before
+    /// the batch.
+    ///
+    /// Files created by the batch are removed by the rollback. Files which
+    /// already existed before the batch, including the ones of tombstoned
+    /// objects, are saved together with their sidecars and markers into
+    /// [`DiskStorageConfig::journal_backup_dir`] before being overwritten,
+    /// removed or tombstoned, and are restored by the rollback. Clearing of
+    /// the object categories, [`DiskStorage::compact`] and
+    /// [`DiskStorage::migrate_layout`] can't be journaled, so they fail with
+    /// [`DiskStorageError::BatchInProgress`] until the batch is committed.
+    pub fn begin_batch(&mut self) -> Result<(), DiskStorageError> {
+        self.check_writable()?;
+        if self.batch {
+            return Err(DiskStorageError::BatchInProgress);
+        }
+        let journal_filename = self.config.journal_filename();
+        self.fs().write(&journal_filename, &[])?;
+        set_mode(self.fs(), &journal_filename, self.config.file_mode)?;
+        if self.config.sync_writes {
+            self.fs().sync_file(&journal_filename)?;
+            self.fs().sync_dir(&self.config.data_dir)?;
+        }
+        self.batch = true;
+        Ok(())
+    }
+
+    /// Commits the batch of writes started with [`DiskStorage::begin_batch`]
+    /// by truncating its journal and removing the saved files
+    pub fn commit(&mut self) -> Result<(), DiskStorageError> {
+        if !self.batch {
+            return Err(DiskStorageError::NoBatch);
+        }
+        self.batch = false;
+        self.truncate_journal()
+    }
+
+    /// Records `filename` in the journal of the current batch before its
+    /// first change by the batch: a missing file is recorded to be removed by
+    /// the rollback, while an existing one (even if tombstoned) is saved
+    /// together with its sidecars and markers to be restored
+    pub(super) fn journal_change(&self, filename: &Path) -> Result<(), DiskStorageError> {
+        if !self.batch {
+            return Ok(());
+        }
+        let mut journaled = self.journaled.lock().unwrap_or_else(|err| err.into_inner());
+        if journaled.contains(filename) {
+            return Ok(());
+        }
+        let name = filename
+            .strip_prefix(&self.config.data_dir)
+            .unwrap_or(filename);
+        let line = if self.fs().exists(filename) {
+            let seq = journaled.len();
+            self.save_object(filename, seq)?;
+            format!("{}{} {}\n", SAVED_PREFIX, seq, name.display())
+        } else {
+            format!("{}\n", name.display())
+        };
+        let journal_filename = self.config.journal_filename();
+        self.fs().append(&journal_filename, line.as_bytes())?;
+        if self.config.sync_writes {
+            self.fs().sync_file(&journal_filename)?;
+        }
+        journaled.insert(filename.to_owned());
+        Ok(())
+    }
+
+    /// Links the file, its sidecars and markers into
+    /// [`DiskStorageConfig::journal_backup_dir`] under the `seq` number.
+    /// Stored files are never modified in place, so the links keep their
+    /// content. Copies left by a failed attempt are replaced.
+    fn save_object(&self, filename: &Path, seq: usize) -> Result<(), DiskStorageError> {
+        let backup_dir = self.config.journal_backup_dir();
+        ensure_dir(self.fs(), &backup_dir, self.config.dir_mode)?;
+        let backup = backup_dir.join(seq.to_string());
+        for (file, saved) in object_files(filename).iter().zip(&object_files(&backup)) {
+            match self.fs().remove_file(saved) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            match self.fs().link_or_copy(file, saved) {
+                Ok(()) if self.config.sync_writes => self.fs().sync_file(saved)?,
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if self.config.sync_writes {
+            self.fs().sync_dir(&backup_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the file, its sidecars and markers saved by
+    /// [`DiskStorage::save_object`] into `backup`, removing the ones which
+    /// were missing when saved. Saved files are kept until the journal is
+    /// truncated, so a restoration interrupted by a crash is repeated.
+    fn restore_object(&self, filename: &Path, backup: &Path) -> Result<(), DiskStorageError> {
+        for (file, saved) in object_files(filename).iter().zip(&object_files(backup)) {
+            if !self.fs().exists(saved) {
+                match self.fs().remove_file(file) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+                continue;
+            }
+            let temp = temp_filename(file);
+            if let Err(err) = self
+                .fs()
+                .link_or_copy(saved, &temp)
+                .and_then(|_| self.fs().rename(&temp, file))
+            {
+                let _ = self.fs().remove_file(&temp);
+                return Err(err.into());
+            }
+        }
+        if self.config.sync_writes {
+            if let Some(dir) = filename.parent() {
+                self.fs().sync_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes changes recorded in the journal of a batch which was not
+    /// committed, and truncates the journal
+    pub(super) fn rollback_journal(&self) -> Result<(), DiskStorageError> {
+        let journal_filename = self.config.journal_filename();
+        let journal = match self.fs().read_to_string(&journal_filename) {
+            Ok(journal) if !journal.is_empty() => journal,
+            // Files may be left saved by a crash right after the commit
+            Ok(_) => return self.remove_journal_backups(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return self.remove_journal_backups()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // The last line may be torn by a crash in the middle of its write;
+        // the file it names is never changed before the line is complete
+        let complete = match journal.rfind('\n') {
+            Some(pos) => &journal[..pos],
+            None => "",
+        };
+        let backup_dir = self.config.journal_backup_dir();
+        let (mut removed, mut restored) = (0usize, 0usize);
+        for line in complete.lines().filter(|line| !line.is_empty()) {
+            let saved = line
+                .strip_prefix(SAVED_PREFIX)
+                .and_then(|rest| rest.split_once(' '));
+            match saved {
+                Some((seq, name)) => {
+                    self.restore_object(&self.config.data_dir.join(name), &backup_dir.join(seq))?;
+                    restored += 1;
+                }
+                None => {
+                    if self.remove_object(self.config.data_dir.join(line))? {
+                        removed += 1;
+                    }
+                }
+            }
+        }
+        debug!(
+            "Rolled back {} objects written and {} objects changed by an uncommitted batch",
+            removed, restored
+        );
+        self.truncate_journal()
+    }
+
+    /// Truncates the journal of the batch, and then removes the files saved
+    /// by the batch, so they are never missing while the journal refers to
+    /// them
+    fn truncate_journal(&self) -> Result<(), DiskStorageError> {
+        let journal_filename = self.config.journal_filename();
+        self.fs().write(&journal_filename, &[])?;
+        if self.config.sync_writes {
+            self.fs().sync_file(&journal_filename)?;
+            self.fs().sync_dir(&self.config.data_dir)?;
+        }
+        self.journaled
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clear();
+        self.remove_journal_backups()
+    }
+
+    /// Removes files saved by the last batch into
+    /// [`DiskStorageConfig::journal_backup_dir`], if any
+    fn remove_journal_backups(&self) -> Result<(), DiskStorageError> {
+        let backup_dir = self.config.journal_backup_dir();
+        if !self.fs().exists(&backup_dir) {
+            return Ok(());
+        }
+        for name in read_filenames(self.fs(), &backup_dir)? {
+            match self.fs().remove_file(&backup_dir.join(name)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        self.fs().remove_dir(&backup_dir)?;
+        Ok(())
+    }
+
+    /// Removes temporary files left by the writes interrupted by a crash,
+    /// returning number of the removed files. Only files named after the
+    /// pattern of [`temp_filename`] are touched; the exclusive lock on the
+    /// data directory guarantees that none of them is being written.
+    pub(super) fn remove_temp_files(&self) -> Result<usize, DiskStorageError> {
+        let mut dirs = vec![self.config.data_dir.clone()];
+        let categories = ObjectKind::ALL
+            .iter()
+            .map(|kind| self.config.kind_dir(*kind))
+            .chain(iter::once(self.config.consignments_dir()));
+        for dir in categories {
+            if !self.fs().exists(&dir) {
+                continue;
+            }
+            // Temporary files may be left in both layouts
+            dirs.extend(shard_dirs(self.fs(), &dir)?);
+            dirs.push(dir);
+        }
+        let mut removed = 0usize;
+        for dir in dirs {
+            for name in read_filenames(self.fs(), &dir)? {
+                if !is_temp_filename(&name) {
+                    continue;
+                }
+                let temp = dir.join(name);
+                debug!("Removing leftover temporary file {:?}", temp);
+                match self.fs().remove_file(&temp) {
+                    Ok(()) => removed += 1,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        if removed > 0 {
+            warn!(
+                "Removed {} temporary files left by interrupted writes",
+                removed
+            );
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::time::Duration;
+
+    use rgb::prelude::*;
+
+    use super::*;
+    use crate::stashd::storage::{DiskStorageConfig, Store};
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_disk_transaction() {
+        let data_dir = TempPath::new("disk-txn");
+
+        let genesis = Genesis::default();
+        let transition = Transition::default();
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        let res: Result<(), DiskStorageError> = storage.transaction(|txn| {
+            txn.add_transition(&transition);
+            Err(DiskStorageError::NoBatch)
+        });
+        assert!(matches!(res, Err(DiskStorageError::NoBatch)));
+        assert!(storage.transition_ids().unwrap().is_empty());
+
+        fs::remove_dir_all(storage.config.geneses_dir()).unwrap();
+        let res = storage.transaction(|txn| {
+            txn.add_transition(&transition);
+            txn.add_genesis(&genesis);
+            Ok::<_, DiskStorageError>(())
+        });
+        assert!(res.is_err());
+        assert!(!storage.has_transition(&transition.node_id()).unwrap());
+
+        fs::create_dir_all(storage.config.geneses_dir()).unwrap();
+        let count = storage
+            .transaction(|txn| {
+                txn.add_transition(&transition);
+                txn.add_genesis(&genesis);
+                Ok::<_, DiskStorageError>(txn.len())
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(storage.has_transition(&transition.node_id()).unwrap());
+        assert!(storage.has_genesis(&genesis.contract_id()).unwrap());
+
+        // Removal is undone together with the rest of the failed transaction
+        fs::remove_dir_all(storage.config.geneses_dir()).unwrap();
+        let res = storage.transaction(|txn| {
+            txn.remove_transition(&transition.node_id());
+            txn.add_genesis(&genesis);
+            Ok::<_, DiskStorageError>(())
+        });
+        assert!(res.is_err());
+        assert!(storage.has_transition(&transition.node_id()).unwrap());
+    }
+
+    #[test]
+    fn test_disk_batch_rollback_on_reopen() {
+        let data_dir = TempPath::new("disk-batch");
+
+        let schema = rgb20::schema::schema();
+        let genesis = Genesis::default();
+        let transition = Transition::default();
+        let extension = Extension::default();
+        let config = DiskStorageConfig::new(data_dir.to_path_buf());
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        storage.add_schema(&schema).unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        storage
+            .add_transition_with_ttl(&transition, Duration::from_secs(3600))
+            .unwrap();
+        let expiry = storage.transition_expiry(&transition.node_id()).unwrap();
+
+        storage.begin_batch().unwrap();
+        storage.remove_schema(&schema.schema_id()).unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        // Makes the transition permanent, removing its expiry marker
+        storage.add_transition(&transition).unwrap();
+        storage.add_extension(&extension).unwrap();
+        assert!(matches!(
+            storage.clear_extensions(),
+            Err(DiskStorageError::BatchInProgress)
+        ));
+        // Dropping the storage without commit imitates a crash
+        drop(storage);
+
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        assert!(storage.has_schema(&schema.schema_id()).unwrap());
+        assert!(storage.has_genesis(&genesis.contract_id()).unwrap());
+        assert!(storage.has_transition(&transition.node_id()).unwrap());
+        assert_eq!(
+            storage.transition_expiry(&transition.node_id()).unwrap(),
+            expiry
+        );
+        assert!(!storage.has_extension(&extension.node_id()).unwrap());
+        assert!(!storage.config.journal_backup_dir().exists());
+
+        storage.begin_batch().unwrap();
+        storage.add_extension(&extension).unwrap();
+        storage.commit().unwrap();
+        drop(storage);
+
+        let storage = DiskStorage::new(config).unwrap();
+        assert!(storage.has_extension(&extension.node_id()).unwrap());
+    }
+
+    #[test]
+    fn test_disk_batch_rollback_tombstoned() {
+        let data_dir = TempPath::new("disk-batch-tomb");
+
+        let genesis = Genesis::default();
+        let transition = Transition::default();
+        let config = DiskStorageConfig::new(data_dir.to_path_buf()).with_tombstones(true);
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        storage.remove_genesis(&genesis.contract_id()).unwrap();
+        storage.add_transition(&transition).unwrap();
+
+        storage.begin_batch().unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        assert!(storage.has_genesis(&genesis.contract_id()).unwrap());
+        storage.remove_transition(&transition.node_id()).unwrap();
+        drop(storage);
+
+        let storage = DiskStorage::new(config).unwrap();
+        assert!(!storage.has_genesis(&genesis.contract_id()).unwrap());
+        assert!(storage
+            .genesis_tombstone(&genesis.contract_id())
+            .unwrap()
+            .is_some());
+        assert!(storage.has_transition(&transition.node_id()).unwrap());
+        assert_eq!(
+            storage.transition_tombstone(&transition.node_id()).unwrap(),
+            None
+        );
+        assert!(!storage.config.journal_backup_dir().exists());
+    }
+}
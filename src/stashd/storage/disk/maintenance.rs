@@ -0,0 +1,331 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::{io, iter};
+
+use bp::dbc::Anchor;
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::scan::{read_filenames, shard_dirs, sub_shards};
+use super::{
+    check_object_id, is_reserved_name, is_sidecar_ext, DiskStorage, DiskStorageConfig,
+    DiskStorageError, StoredObject,
+};
+use crate::stashd::storage::ObjectKind;
+use crate::util::file::*;
+
+/// Report entry for a stored file which can't be decoded
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct VerifyError {
+    pub path: PathBuf,
+    pub kind: ObjectKind,
+    pub error: String,
+}
+
+/// Leftovers removed by [`DiskStorage::compact`]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct CompactReport {
+    /// Checksum and format sidecars, tombstones and expiry markers which
+    /// stored files were missing
+    pub orphaned_sidecars: usize,
+    /// Temporary files left by interrupted writes
+    pub temp_files: usize,
+    /// Empty shard directories
+    pub empty_dirs: usize,
+}
+
+impl DiskStorage {
+    /// Moves files kept in a layout other than the configured one (see
+    /// [`DiskStorageConfig::sharded`]) into their configured locations,
+    /// returning number of the moved files. Checksum sidecars, tombstones and
+    /// expiry markers are moved together with their files; emptied shard
+    /// directories are left in place. Files which names are not valid ids are
+    /// not touched.
+    ///
+    /// The same method moves files into the locations returned by a new
+    /// [`DiskStorageConfig::path_mapper`], provided the mapper is able to
+    /// parse the old file names. Fails with
+    /// [`DiskStorageError::BatchInProgress`] within a batch of writes.
+    pub fn migrate_layout(&mut self) -> Result<usize, DiskStorageError> {
+        self.check_writable()?;
+        self.check_no_batch()?;
+        let mut moved = 0;
+        let mut touched = BTreeSet::new();
+        for kind in ObjectKind::ALL.iter().copied() {
+            moved += self.relocate_dir(
+                self.config.kind_dir(kind),
+                |name| self.config.object_location(kind, name),
+                &mut touched,
+            )?;
+        }
+        moved += self.relocate_dir(
+            self.config.consignments_dir(),
+            |name| {
+                self.config
+                    .path_mapper
+                    .parse_consignment_path(name)
+                    .map(|id| self.config.consignment_filename(&id))
+            },
+            &mut touched,
+        )?;
+        if self.config.sync_writes {
+            for dir in touched {
+                self.fs().sync_dir(&dir)?;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Moves files found in the category `dir`, both in the flat and sharded
+    /// locations, into the paths returned by `locate`. Directories changed by
+    /// the moves are collected into `touched`.
+    fn relocate_dir(
+        &self,
+        dir: PathBuf,
+        locate: impl Fn(&str) -> Option<PathBuf>,
+        touched: &mut BTreeSet<PathBuf>,
+    ) -> Result<usize, DiskStorageError> {
+        let mut moved = 0;
+        if !self.fs().exists(&dir) {
+            return Ok(moved);
+        }
+        let mut dirs = shard_dirs(self.fs(), &dir)?;
+        dirs.push(dir);
+        for dir in dirs {
+            for name in read_filenames(self.fs(), &dir)? {
+                let path = dir.join(&name);
+                let target = match locate(&name) {
+                    Some(target) if !is_reserved_name(&name) && target != path => target,
+                    _ => continue,
+                };
+                self.create_shard_dir(&target)?;
+                for sidecar in &[
+                    DiskStorageConfig::checksum_filename,
+                    DiskStorageConfig::format_filename,
+                    DiskStorageConfig::tombstone_filename,
+                    DiskStorageConfig::expiry_filename,
+                ] {
+                    if self.fs().exists(&sidecar(&path)) {
+                        self.fs().rename(&sidecar(&path), &sidecar(&target))?;
+                    }
+                }
+                self.fs().rename(&path, &target)?;
+                if let Some(parent) = target.parent() {
+                    touched.insert(parent.to_path_buf());
+                }
+                touched.insert(dir.clone());
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Links files of `dir` into `dest` for
+    /// [`SnapshotStore::snapshot`](crate::stashd::storage::SnapshotStore::snapshot),
+    /// descending into the subdirectories; returns number of the linked files
+    pub(super) fn snapshot_dir(&self, dir: &Path, dest: &Path) -> Result<usize, DiskStorageError> {
+        self.fs().create_dir_all(dest)?;
+        let lock_filename = self.config.lock_filename();
+        let mut linked = 0;
+        for entry in self.fs().read_dir(dir)? {
+            let entry = entry?;
+            let target = match entry.path.file_name() {
+                Some(name) => dest.join(name),
+                None => continue,
+            };
+            if entry.is_dir {
+                linked += self.snapshot_dir(&entry.path, &target)?;
+            } else if entry.path != lock_filename
+                && entry.path.extension().and_then(|ext| ext.to_str()) != Some(TEMP_FILE_EXT)
+            {
+                self.fs().link_or_copy(&entry.path, &target)?;
+                linked += 1;
+            }
+        }
+        if self.config.sync_writes {
+            self.fs().sync_dir(dest)?;
+        }
+        Ok(linked)
+    }
+
+    /// Tidies up the data directory after the churn of additions and
+    /// removals, returning what was removed: checksum and format sidecars,
+    /// tombstones and expiry markers left without their stored files,
+    /// temporary files of interrupted writes, and empty shard directories
+    /// (including the ones emptied by [`DiskStorage::migrate_layout`]).
+    /// Stored files and sidecars of the present files are never touched.
+    ///
+    /// Shards holding few files are not merged, since the location of each
+    /// file follows from its id; they are removed once they become empty.
+    /// Meant to be run during maintenance, since it scans all of the storage
+    /// directories; fails with [`DiskStorageError::BatchInProgress`] within a
+    /// batch of writes.
+    pub fn compact(&mut self) -> Result<CompactReport, DiskStorageError> {
+        self.check_writable()?;
+        self.check_no_batch()?;
+        let mut report = CompactReport {
+            temp_files: self.remove_temp_files()?,
+            ..CompactReport::default()
+        };
+        let mut touched = BTreeSet::new();
+        let categories = ObjectKind::ALL
+            .iter()
+            .map(|kind| self.config.kind_dir(*kind))
+            .chain(iter::once(self.config.consignments_dir()));
+        for dir in categories {
+            if !self.fs().exists(&dir) {
+                continue;
+            }
+            // Leftovers may be found in both layouts
+            let mut dirs = shard_dirs(self.fs(), &dir)?;
+            dirs.push(dir.clone());
+            for dir in dirs {
+                let removed = self.remove_orphaned_sidecars(&dir)?;
+                if removed > 0 {
+                    report.orphaned_sidecars += removed;
+                    touched.insert(dir);
+                }
+            }
+            for shard in sub_shards(self.fs(), &dir)? {
+                for sub_shard in sub_shards(self.fs(), &shard)? {
+                    if self.remove_empty_dir(&sub_shard)? {
+                        report.empty_dirs += 1;
+                        touched.insert(shard.clone());
+                    }
+                }
+                if self.remove_empty_dir(&shard)? {
+                    report.empty_dirs += 1;
+                    touched.insert(dir.clone());
+                }
+            }
+        }
+        if self.config.sync_writes {
+            for dir in touched {
+                if self.fs().exists(&dir) {
+                    self.fs().sync_dir(&dir)?;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Removes sidecars and markers kept in `dir` which stored files are
+    /// missing, returning their number
+    fn remove_orphaned_sidecars(&self, dir: &Path) -> Result<usize, DiskStorageError> {
+        let names = read_filenames(self.fs(), dir)?;
+        let mut removed = 0;
+        for name in &names {
+            let path = Path::new(name);
+            if !is_sidecar_ext(path.extension().and_then(|ext| ext.to_str())) {
+                continue;
+            }
+            let primary = path.with_extension("");
+            if names.iter().any(|name| Path::new(name) == primary) {
+                continue;
+            }
+            let sidecar = dir.join(name);
+            debug!("Removing orphaned sidecar {:?}", sidecar);
+            match self.fs().remove_file(&sidecar) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes the directory if it has no entries, returning whether it was
+    /// removed
+    fn remove_empty_dir(&self, dir: &Path) -> Result<bool, DiskStorageError> {
+        if self.fs().read_dir(dir)?.next().is_some() {
+            return Ok(false);
+        }
+        debug!("Removing empty shard directory {:?}", dir);
+        self.fs().remove_dir(dir)?;
+        Ok(true)
+    }
+
+    /// Tries to decode every stored file, returning list of the files which
+    /// are broken, including the files holding objects with ids different
+    /// from the ones in the file names. Unlike normal reads, a broken file
+    /// does not stop the process, so the whole data directory gets checked
+    /// at once.
+    pub fn verify(&self) -> Result<Vec<VerifyError>, DiskStorageError> {
+        let mut report = vec![];
+        self.verify_dir::<Schema>(ObjectKind::Schema, &mut report)?;
+        self.verify_dir::<Genesis>(ObjectKind::Genesis, &mut report)?;
+        self.verify_dir::<Anchor<MerkleBlock>>(ObjectKind::Anchor, &mut report)?;
+        self.verify_dir::<Transition>(ObjectKind::Transition, &mut report)?;
+        self.verify_dir::<Extension>(ObjectKind::Extension, &mut report)?;
+        Ok(report)
+    }
+
+    fn verify_dir<T: StoredObject>(
+        &self,
+        kind: ObjectKind,
+        report: &mut Vec<VerifyError>,
+    ) -> Result<(), DiskStorageError> {
+        for path in self.config.object_paths(kind)? {
+            let res = self
+                .decode_object::<T>(path.clone())
+                .and_then(|object| check_object_id(&self.config, &object, &path));
+            if let Err(err) = res {
+                report.push(VerifyError {
+                    path,
+                    kind,
+                    error: err.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stashd::storage::{SnapshotStore, Store};
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_disk_snapshot() {
+        let root = TempPath::new("disk-snapshot");
+        let snapshot_dir = root.join("snapshot");
+
+        let genesis = Genesis::default();
+        let transition = Transition::default();
+        let config = DiskStorageConfig::new(root.join("data")).with_checksums(true);
+        let mut storage = DiskStorage::new(config).unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        storage.snapshot(&snapshot_dir).unwrap();
+        storage.add_transition(&transition).unwrap();
+        assert!(storage.snapshot(&snapshot_dir).is_err());
+
+        let snapshot = DiskStorage::open_existing(
+            DiskStorageConfig::new(snapshot_dir)
+                .with_checksums(true)
+                .with_read_only(true),
+        )
+        .unwrap();
+        assert_eq!(snapshot.genesis(&genesis.contract_id()).unwrap(), genesis);
+        assert!(!snapshot.has_transition(&transition.node_id()).unwrap());
+    }
+}
@@ -0,0 +1,307 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bp::dbc::AnchorId;
+use rgb::prelude::*;
+
+use super::scan::read_filenames;
+use super::{set_mode, DiskStorage, DiskStorageConfig, DiskStorageError, ObjectOp};
+use crate::stashd::storage::{IoOp, ObjectKind};
+use crate::util::file::*;
+
+/// Parses time kept in a tombstone or an expiry marker
+fn parse_marker(data: &str) -> Result<SystemTime, io::Error> {
+    let secs = data
+        .trim()
+        .parse::<u64>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+impl DiskStorage {
+    /// Returns time of the deletion of the schema, if it was deleted with
+    /// [`DiskStorageConfig::tombstones`] enabled and the tombstone is not
+    /// purged yet
+    #[inline]
+    pub fn schema_tombstone(&self, id: &SchemaId) -> Result<Option<SystemTime>, DiskStorageError> {
+        self.tombstone(&self.config.schema_filename(id))
+    }
+
+    #[inline]
+    pub fn genesis_tombstone(
+        &self,
+        id: &ContractId,
+    ) -> Result<Option<SystemTime>, DiskStorageError> {
+        self.tombstone(&self.config.genesis_filename(id))
+    }
+
+    #[inline]
+    pub fn anchor_tombstone(&self, id: &AnchorId) -> Result<Option<SystemTime>, DiskStorageError> {
+        self.tombstone(&self.config.anchor_filename(id))
+    }
+
+    #[inline]
+    pub fn transition_tombstone(
+        &self,
+        id: &NodeId,
+    ) -> Result<Option<SystemTime>, DiskStorageError> {
+        self.tombstone(&self.config.transition_filename(id))
+    }
+
+    #[inline]
+    pub fn extension_tombstone(&self, id: &NodeId) -> Result<Option<SystemTime>, DiskStorageError> {
+        self.tombstone(&self.config.extension_filename(id))
+    }
+
+    /// Removes tombstones of the objects deleted before the given time
+    /// together with the files of the objects, returning number of the
+    /// purged objects
+    #[inline]
+    pub fn purge_tombstones(&mut self, before: SystemTime) -> Result<usize, DiskStorageError> {
+        self.purge_markers(DiskStorageConfig::TOMBSTONE_FILE_EXT, before)
+    }
+
+    /// Writes state transition which is removed by [`DiskStorage::expire_now`]
+    /// once `ttl` passes, returning whether the transition was already present.
+    /// Adding the transition again with
+    /// [`Store::add_transition`](crate::stashd::storage::Store::add_transition)
+    /// makes it permanent, while adding it with a new `ttl` replaces its expiry
+    /// time. The expiry time is rounded up to whole seconds, so the transition
+    /// is never expired before `ttl` passes. Expired transitions remain
+    /// readable until removed.
+    pub fn add_transition_with_ttl(
+        &mut self,
+        transition: &Transition,
+        ttl: Duration,
+    ) -> Result<bool, DiskStorageError> {
+        self.check_writable()?;
+        let filename = self.config.transition_filename(&transition.node_id());
+        let exists = self.is_present(&filename);
+        self.traced(ObjectOp::Add, ObjectKind::Transition, &filename, || {
+            self.place_object(transition, &filename)?;
+            // A crash before the marker is written leaves the transition
+            // permanent, which is preferable to losing it
+            self.write_marker(
+                &DiskStorageConfig::expiry_filename(&filename),
+                SystemTime::now() + ttl,
+            )?;
+            if self.config.sync_writes {
+                if let Some(dir) = filename.parent() {
+                    self.fs().sync_dir(dir)?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(exists)
+    }
+
+    /// Returns expiry time of the transition written with
+    /// [`DiskStorage::add_transition_with_ttl`]
+    #[inline]
+    pub fn transition_expiry(&self, id: &NodeId) -> Result<Option<SystemTime>, DiskStorageError> {
+        self.read_marker(&DiskStorageConfig::expiry_filename(
+            &self.config.transition_filename(id),
+        ))
+    }
+
+    /// Removes all objects which expiry time has passed, returning number of
+    /// the removed objects; with [`DiskStorageConfig::tombstones`] they are
+    /// tombstoned instead. Meant to be called periodically by the owner of
+    /// the storage.
+    #[inline]
+    pub fn expire_now(&mut self) -> Result<usize, DiskStorageError> {
+        self.purge_markers(DiskStorageConfig::EXPIRY_FILE_EXT, SystemTime::now())
+    }
+
+    /// Deletes the objects marked with time markers with the `ext` extension
+    /// which are older than `before`, and removes the markers. Files of the
+    /// tombstoned objects are removed only when their tombstones are purged;
+    /// other objects are tombstoned with [`DiskStorageConfig::tombstones`].
+    fn purge_markers(&mut self, ext: &str, before: SystemTime) -> Result<usize, DiskStorageError> {
+        self.check_writable()?;
+        let mut purged = 0;
+        for kind in ObjectKind::ALL.iter().copied() {
+            let dir = self.config.kind_dir(kind);
+            if !self.fs().exists(&dir) {
+                continue;
+            }
+            for dir in self.config.object_dirs(dir)? {
+                for name in read_filenames(self.fs(), &dir)? {
+                    let marker = dir.join(&name);
+                    if marker.extension().and_then(|ext| ext.to_str()) != Some(ext) {
+                        continue;
+                    }
+                    let time = match self.read_marker(&marker)? {
+                        Some(time) => time,
+                        // Purged concurrently by somebody else
+                        None => continue,
+                    };
+                    if time >= before {
+                        continue;
+                    }
+                    let filename = marker.with_extension("");
+                    if ext == DiskStorageConfig::TOMBSTONE_FILE_EXT {
+                        self.remove_object(filename)?;
+                    } else {
+                        self.delete_object(filename)?;
+                    }
+                    // A marker left without the file (or kept next to a
+                    // tombstoned one) is removed alone
+                    match self.fs().remove_file(&marker) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                        _ => {}
+                    }
+                    purged += 1;
+                }
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Atomically writes `time` into the tombstone or expiry `marker`, leaving
+    /// syncing of its directory to the caller. Markers keep whole seconds, so
+    /// the time is rounded up to the next second.
+    pub(super) fn write_marker(
+        &self,
+        marker: &Path,
+        time: SystemTime,
+    ) -> Result<(), DiskStorageError> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs() + u64::from(since_epoch.subsec_nanos() > 0);
+        let temp = temp_filename(marker);
+        let res = self
+            .fs()
+            .write(&temp, format!("{}\n", secs).as_bytes())
+            .and_then(|_| {
+                set_mode(self.fs(), &temp, self.config.file_mode)?;
+                if self.config.sync_writes {
+                    self.fs().sync_file(&temp)?;
+                }
+                self.fs().rename(&temp, marker)
+            });
+        if let Err(err) = res {
+            let _ = self.fs().remove_file(&temp);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Reads time kept in the tombstone or expiry `marker`, if it exists
+    fn read_marker(&self, marker: &Path) -> Result<Option<SystemTime>, DiskStorageError> {
+        match self.fs().read_to_string(marker) {
+            Ok(data) => parse_marker(&data)
+                .map(Some)
+                .map_err(|err| DiskStorageError::from(err).with_path(IoOp::Read, marker)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Returns time of the deletion of the object kept in the file, if the
+    /// object is tombstoned
+    pub(super) fn tombstone(
+        &self,
+        filename: &Path,
+    ) -> Result<Option<SystemTime>, DiskStorageError> {
+        if !self.config.tombstones {
+            return Ok(None);
+        }
+        self.read_marker(&DiskStorageConfig::tombstone_filename(filename))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::stashd::storage::Store;
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_disk_transition_ttl() {
+        let data_dir = TempPath::new("disk-ttl");
+
+        let transition = Transition::default();
+        let node_id = transition.node_id();
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        let hour = Duration::from_secs(3600);
+        let before = SystemTime::now();
+        assert!(!storage.add_transition_with_ttl(&transition, hour).unwrap());
+        let after = SystemTime::now();
+        // Expiry time is rounded up to whole seconds
+        let expiry = storage.transition_expiry(&node_id).unwrap().unwrap();
+        assert_eq!(expiry.duration_since(UNIX_EPOCH).unwrap().subsec_nanos(), 0);
+        assert!(expiry >= before + hour);
+        assert!(expiry <= after + hour + Duration::from_secs(1));
+        assert_eq!(storage.expire_now().unwrap(), 0);
+        assert!(storage.add_transition(&transition).unwrap());
+        assert_eq!(storage.transition_expiry(&node_id).unwrap(), None);
+
+        assert!(storage
+            .add_transition_with_ttl(&transition, Duration::from_secs(0))
+            .unwrap());
+        let expiry = storage.transition_expiry(&node_id).unwrap().unwrap();
+        thread::sleep(
+            expiry.duration_since(SystemTime::now()).unwrap_or_default()
+                + Duration::from_millis(10),
+        );
+        assert!(storage.has_transition(&node_id).unwrap());
+        assert_eq!(storage.expire_now().unwrap(), 1);
+        assert!(!storage.has_transition(&node_id).unwrap());
+        assert_eq!(storage.transition_expiry(&node_id).unwrap(), None);
+        assert_eq!(storage.expire_now().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_disk_expiry_and_pruning_tombstoned() {
+        let data_dir = TempPath::new("disk-ttl-tomb");
+
+        let transition = Transition::default();
+        let node_id = transition.node_id();
+        let config = DiskStorageConfig::new(data_dir.to_path_buf()).with_tombstones(true);
+        let mut storage = DiskStorage::new(config).unwrap();
+        storage
+            .add_transition_with_ttl(&transition, Duration::from_secs(0))
+            .unwrap();
+        let expiry = storage.transition_expiry(&node_id).unwrap().unwrap();
+        thread::sleep(
+            expiry.duration_since(SystemTime::now()).unwrap_or_default()
+                + Duration::from_millis(10),
+        );
+        assert_eq!(storage.expire_now().unwrap(), 1);
+        assert!(!storage.has_transition(&node_id).unwrap());
+        assert!(storage.transition_tombstone(&node_id).unwrap().is_some());
+        assert_eq!(storage.transition_expiry(&node_id).unwrap(), None);
+
+        storage.add_transition(&transition).unwrap();
+        assert_eq!(storage.prune_transitions(|_| false).unwrap(), 1);
+        assert!(!storage.has_transition(&node_id).unwrap());
+        let deleted = storage.transition_tombstone(&node_id).unwrap().unwrap();
+        assert!(storage.config.transition_filename(&node_id).exists());
+
+        // Only purging of the tombstones removes the files
+        assert_eq!(
+            storage
+                .purge_tombstones(deleted + Duration::from_secs(1))
+                .unwrap(),
+            1
+        );
+        assert_eq!(storage.transition_tombstone(&node_id).unwrap(), None);
+        assert!(!storage.config.transition_filename(&node_id).exists());
+    }
+}
@@ -0,0 +1,1796 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use std::{io, iter, thread};
+
+use amplify::Wrapper;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash};
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::{debug, debug_span};
+
+use super::filesystem::{ContextFileSystem, RetryFileSystem};
+use super::{
+    ConsignmentId, ConsignmentStore, FileSystem, IoOp, LockGuard, ObjectKind, SnapshotStore, Store,
+    StoreMeta, Txn,
+};
+use crate::stashd::index::Index;
+use crate::util::file::*;
+
+mod archive;
+mod config;
+mod error;
+mod journal;
+mod maintenance;
+mod markers;
+mod scan;
+mod usage;
+
+pub use archive::{ImportCount, ImportFailure, ImportStats};
+#[cfg(feature = "compression")]
+pub use config::CompressionLevel;
+pub use config::{DataFormat, DiskStorageConfig, StorageObserver, StoredObjectRef};
+pub use error::{DiskStorageError, LockHolder};
+pub use maintenance::{CompactReport, VerifyError};
+use scan::{collect_ids, for_each_object, read_filenames};
+pub use usage::{CategoryUsage, StorageHealth, StorageStats};
+
+/// Interval of the attempts to take the lock on the data directory with
+/// [`DiskStorageConfig::wait_for_lock`]
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Checks whether the name belongs to a checksum or format sidecar, a
+/// tombstone, an expiry marker or a temporary file
+fn is_reserved_name(name: &str) -> bool {
+    let ext = Path::new(name).extension().and_then(|ext| ext.to_str());
+    is_sidecar_ext(ext) || ext == Some(TEMP_FILE_EXT)
+}
+
+/// Checks whether the extension belongs to a file kept next to a stored
+/// file: a checksum or format sidecar, a tombstone or an expiry marker
+fn is_sidecar_ext(ext: Option<&str>) -> bool {
+    ext == Some(DiskStorageConfig::CHECKSUM_FILE_EXT)
+        || ext == Some(DiskStorageConfig::FORMAT_FILE_EXT)
+        || ext == Some(DiskStorageConfig::TOMBSTONE_FILE_EXT)
+        || ext == Some(DiskStorageConfig::EXPIRY_FILE_EXT)
+}
+
+/// Sets Unix permission bits of the file or directory, if `mode` is given
+#[inline]
+fn set_mode(fs: &dyn FileSystem, path: &Path, mode: Option<u32>) -> io::Result<()> {
+    match mode {
+        Some(mode) => fs.set_permissions(path, mode),
+        None => Ok(()),
+    }
+}
+
+/// Creates the directory unless it exists. Existing directories are not
+/// checked in advance, since `create_dir_all` does nothing for them, unless
+/// [`DiskStorageConfig::dir_mode`] has to be set on the created directory
+/// only.
+fn ensure_dir(fs: &dyn FileSystem, dir: &Path, mode: Option<u32>) -> io::Result<()> {
+    if mode.is_none() {
+        return fs.create_dir_all(dir);
+    }
+    if fs.exists(dir) {
+        return Ok(());
+    }
+    debug!("RGB storage directory {:?} is not found; creating one", dir);
+    fs.create_dir_all(dir)?;
+    set_mode(fs, dir, mode)
+}
+
+/// Serde bounds required from the stored objects, so they can be kept in
+/// JSON or YAML; empty without `serde` feature
+#[cfg(feature = "serde")]
+trait SerdeObject: Serialize + DeserializeOwned {}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + DeserializeOwned> SerdeObject for T {}
+
+#[cfg(not(feature = "serde"))]
+trait SerdeObject {}
+
+#[cfg(not(feature = "serde"))]
+impl<T> SerdeObject for T {}
+
+/// Objects stored in the files named after their own ids, which are derived
+/// from the object data
+trait StoredObject: ReadWrite + SerdeObject {
+    type Id: PartialEq + Display;
+
+    /// Kind of the object; `None` for consignments, which are not a part of
+    /// the [`Store`] data
+    const KIND: Option<ObjectKind>;
+
+    fn object_id(&self) -> Self::Id;
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<Self::Id>;
+}
+
+impl StoredObject for Schema {
+    type Id = SchemaId;
+    const KIND: Option<ObjectKind> = Some(ObjectKind::Schema);
+
+    fn object_id(&self) -> SchemaId { self.schema_id() }
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<SchemaId> {
+        config.parse_schema_filename(filename)
+    }
+}
+
+impl StoredObject for Genesis {
+    type Id = ContractId;
+    const KIND: Option<ObjectKind> = Some(ObjectKind::Genesis);
+
+    fn object_id(&self) -> ContractId { self.contract_id() }
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<ContractId> {
+        config.parse_genesis_filename(filename)
+    }
+}
+
+impl StoredObject for Anchor<MerkleBlock> {
+    type Id = AnchorId;
+    const KIND: Option<ObjectKind> = Some(ObjectKind::Anchor);
+
+    fn object_id(&self) -> AnchorId { self.anchor_id() }
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<AnchorId> {
+        config.parse_anchor_filename(filename)
+    }
+}
+
+impl StoredObject for Transition {
+    type Id = NodeId;
+    const KIND: Option<ObjectKind> = Some(ObjectKind::Transition);
+
+    fn object_id(&self) -> NodeId { self.node_id() }
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<NodeId> {
+        config.parse_transition_filename(filename)
+    }
+}
+
+impl StoredObject for Extension {
+    type Id = NodeId;
+    const KIND: Option<ObjectKind> = Some(ObjectKind::Extension);
+
+    fn object_id(&self) -> NodeId { self.node_id() }
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<NodeId> {
+        config.parse_extension_filename(filename)
+    }
+}
+
+impl StoredObject for Consignment {
+    type Id = ConsignmentId;
+    const KIND: Option<ObjectKind> = None;
+
+    fn object_id(&self) -> ConsignmentId { ConsignmentId::from_consignment(self) }
+
+    fn parse_filename(config: &DiskStorageConfig, filename: &Path) -> Option<ConsignmentId> {
+        config.parse_consignment_filename(filename)
+    }
+}
+
+/// Checks that the id of the object read from `filename` matches the id in
+/// the file name; names which are not ids are not checked
+fn check_object_id<T: StoredObject>(
+    config: &DiskStorageConfig,
+    object: &T,
+    filename: &Path,
+) -> Result<(), DiskStorageError> {
+    let expected = match T::parse_filename(config, filename) {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let actual = object.object_id();
+    if actual != expected {
+        return Err(DiskStorageError::IdMismatch {
+            path: filename.to_owned(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Decodes object kept in the file in the given format, transparently
+/// decompressing strict-encoded data
+fn decode_file<T: StoredObject>(
+    fs: &dyn FileSystem,
+    filename: &Path,
+    format: DataFormat,
+) -> Result<T, DiskStorageError> {
+    let reader = fs.open(filename)?;
+    match format {
+        DataFormat::StrictEncode => Ok(T::read_buffered(reader)?),
+        #[cfg(feature = "serde")]
+        DataFormat::Json => Ok(serde_json::from_reader(reader)?),
+        #[cfg(feature = "serde")]
+        DataFormat::Yaml => Ok(serde_yaml::from_reader(reader)?),
+        #[cfg(not(feature = "serde"))]
+        format => Err(DiskStorageError::UnsupportedFormat(format)),
+    }
+}
+
+/// Computes hex-encoded SHA256 checksum of the file, reading it in chunks
+/// so large files are never loaded into memory as a whole
+fn file_checksum(fs: &dyn FileSystem, filename: &Path) -> Result<String, io::Error> {
+    let mut engine = sha256::Hash::engine();
+    io::copy(&mut fs.open(filename)?, &mut engine)?;
+    Ok(sha256::Hash::from_engine(engine).to_hex())
+}
+
+/// Operations over the stored objects reported to the tracing spans and to
+/// the [`StorageObserver`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display)]
+enum ObjectOp {
+    #[display("read")]
+    Read,
+
+    #[display("add")]
+    Add,
+
+    #[display("remove")]
+    Remove,
+}
+
+/// Keeps all source/binary RGB contract data, stash etc
+#[derive(Debug, Display)]
+#[display(Debug)]
+pub struct DiskStorage {
+    config: DiskStorageConfig,
+    /// Lock on the data directory preventing other processes from writing
+    /// into it (and, unless the storage is read-only, from reading it).
+    /// Unlocked on drop. Locks nothing for a read-only storage opened
+    /// without the lock file.
+    _lock: LockGuard,
+    /// Whether a batch of writes is started, so the changed files are
+    /// recorded in the journal
+    batch: bool,
+    /// Files already recorded in the journal of the current batch
+    journaled: Mutex<BTreeSet<PathBuf>>,
+    /// Estimate of the total size of the stored files used to check
+    /// [`DiskStorageConfig::max_data_dir_bytes`], or [`u64::MAX`] until
+    /// computed. Not decreased by removals, so it is re-computed before
+    /// reporting the quota as exceeded.
+    used_bytes: AtomicU64,
+}
+
+impl DiskStorage {
+    #[inline]
+    pub fn new(config: DiskStorageConfig) -> Result<Self, DiskStorageError> {
+        Self::open(config, true)
+    }
+
+    /// Opens storage which data directory must already exist, failing with
+    /// [`DiskStorageError::DataDirNotFound`] if the data directory or any of
+    /// the storage directories inside it is missing. Unlike
+    /// [`DiskStorage::new`], never creates an empty stash, which protects
+    /// tools inspecting an existing stash from mistyped paths.
+    #[inline]
+    pub fn open_existing(config: DiskStorageConfig) -> Result<Self, DiskStorageError> {
+        Self::open(config, false)
+    }
+
+    fn open(mut config: DiskStorageConfig, create: bool) -> Result<Self, DiskStorageError> {
+        debug!("Instantiating RGB storage (disk storage) ...");
+
+        config.check_data_format()?;
+        if let Some(retry) = config.retry.clone() {
+            config.file_system = Arc::new(RetryFileSystem::new(config.file_system, retry));
+        }
+        config.file_system = Arc::new(ContextFileSystem::new(config.file_system));
+        let fs = &*config.file_system;
+        config.data_dir = Self::resolve_data_dir(fs, &config.data_dir)?;
+        if !create {
+            let dirs = iter::once(config.data_dir.clone())
+                .chain(ObjectKind::ALL.iter().map(|kind| config.kind_dir(*kind)))
+                .chain(iter::once(config.consignments_dir()));
+            for path in dirs {
+                if !fs.exists(&path) {
+                    return Err(DiskStorageError::DataDirNotFound { path });
+                }
+            }
+        }
+        if config.read_only {
+            debug!("RGB storage is opened in read-only mode");
+            if !fs.exists(&config.data_dir) {
+                return Err(DiskStorageError::DataDirNotFound {
+                    path: config.data_dir,
+                });
+            }
+            let lock = Self::acquire_lock(&config)?;
+            if fs.exists(&config.version_filename()) {
+                Self::check_version(&config)?;
+            }
+            return Ok(Self {
+                config,
+                _lock: lock,
+                batch: false,
+                journaled: Mutex::new(BTreeSet::new()),
+                used_bytes: AtomicU64::new(u64::MAX),
+            });
+        }
+
+        ensure_dir(fs, &config.data_dir, config.dir_mode)?;
+        let lock = Self::acquire_lock(&config)?;
+        set_mode(fs, &config.lock_filename(), config.file_mode)?;
+        let version_filename = config.version_filename();
+        if fs.exists(&version_filename) {
+            Self::check_version(&config)?;
+        } else {
+            debug!(
+                "Writing RGB storage version {} into {:?}",
+                DiskStorageConfig::STORAGE_VERSION,
+                version_filename
+            );
+            fs.write(
+                &version_filename,
+                format!("{}\n", DiskStorageConfig::STORAGE_VERSION).as_bytes(),
+            )?;
+            set_mode(fs, &version_filename, config.file_mode)?;
+            if config.sync_writes {
+                fs.sync_file(&version_filename)?;
+                fs.sync_dir(&config.data_dir)?;
+            }
+        }
+
+        // Each of the directories is ensured separately, completing layouts
+        // left partially created by an interrupted bootstrap
+        for dir in ObjectKind::ALL
+            .iter()
+            .map(|kind| config.kind_dir(*kind))
+            .chain(iter::once(config.consignments_dir()))
+        {
+            ensure_dir(fs, &dir, config.dir_mode)?;
+        }
+
+        let storage = Self {
+            config,
+            _lock: lock,
+            batch: false,
+            journaled: Mutex::new(BTreeSet::new()),
+            used_bytes: AtomicU64::new(u64::MAX),
+        };
+        storage.rollback_journal()?;
+        storage.remove_temp_files()?;
+        Ok(storage)
+    }
+
+    /// Takes the lock on the data directory, shared for a read-only storage
+    /// and exclusive otherwise. If the lock is held by other processes, waits
+    /// for its release with [`DiskStorageConfig::wait_for_lock`] or fails
+    /// with [`DiskStorageError::Locked`] otherwise. A read-only storage does
+    /// not create the missing lock file and goes without the lock.
+    fn acquire_lock(config: &DiskStorageConfig) -> Result<LockGuard, DiskStorageError> {
+        let fs = &*config.file_system;
+        let lock_filename = config.lock_filename();
+        loop {
+            let res = if config.read_only {
+                fs.lock_shared(&lock_filename)
+            } else {
+                fs.lock(&lock_filename)
+            };
+            match res {
+                Ok(lock) => return Ok(lock),
+                Err(err) if config.read_only && err.kind() == io::ErrorKind::NotFound => {
+                    debug!(
+                        "No lock file in {:?}, reading without the lock",
+                        config.data_dir
+                    );
+                    return Ok(Box::new(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err.into()),
+            }
+            if !config.wait_for_lock {
+                return Err(DiskStorageError::Locked {
+                    holder: Self::lock_holder(config),
+                });
+            }
+            thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+
+    /// Finds out who holds the lock which can't be taken. Readers are
+    /// blocked only by a writer; a writer is blocked by readers if a shared
+    /// lock can still be taken.
+    fn lock_holder(config: &DiskStorageConfig) -> LockHolder {
+        if config.read_only {
+            return LockHolder::Writer;
+        }
+        match config.file_system.lock_shared(&config.lock_filename()) {
+            Ok(_) => LockHolder::Readers,
+            Err(_) => LockHolder::Writer,
+        }
+    }
+
+    /// Resolves symlinks in the path of the data directory, so the storage
+    /// directories are created inside the symlink target. A data directory
+    /// which does not exist yet is returned as is. If the data directory or
+    /// one of its parents is a dangling symlink, fails with
+    /// [`DiskStorageError::DataDirUnavailable`] instead of trying to create
+    /// directories over it.
+    fn resolve_data_dir(fs: &dyn FileSystem, data_dir: &Path) -> Result<PathBuf, DiskStorageError> {
+        match fs.canonicalize(data_dir) {
+            Ok(data_dir) => return Ok(data_dir),
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            Err(_) => {}
+        }
+        match data_dir
+            .ancestors()
+            .find(|path| fs.is_dangling_symlink(path))
+        {
+            Some(link) => Err(DiskStorageError::DataDirUnavailable {
+                path: link.to_owned(),
+            }),
+            None => Ok(data_dir.to_owned()),
+        }
+    }
+
+    fn read_version(config: &DiskStorageConfig) -> Result<u16, DiskStorageError> {
+        let version_filename = config.version_filename();
+        config
+            .file_system
+            .read_to_string(&version_filename)?
+            .trim()
+            .parse::<u16>()
+            .map_err(|err| {
+                DiskStorageError::from(io::Error::new(io::ErrorKind::InvalidData, err))
+                    .with_path(IoOp::Read, &version_filename)
+            })
+    }
+
+    fn check_version(config: &DiskStorageConfig) -> Result<(), DiskStorageError> {
+        let found = Self::read_version(config)?;
+        if found != DiskStorageConfig::STORAGE_VERSION {
+            return Err(DiskStorageError::IncompatibleVersion {
+                found,
+                expected: DiskStorageConfig::STORAGE_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes all transitions for which `keep` returns `false`, returning
+    /// number of the removed transitions. With
+    /// [`DiskStorageConfig::tombstones`] the removed transitions are
+    /// tombstoned, like the ones removed with
+    /// [`Store::remove_transition`]. A transition file which can't be decoded
+    /// stops the process with an error; transitions removed before that
+    /// remain removed.
+    pub fn prune_transitions<F: FnMut(&Transition) -> bool>(
+        &mut self,
+        mut keep: F,
+    ) -> Result<usize, DiskStorageError> {
+        self.check_writable()?;
+        let mut removed = 0;
+        for id in self.transition_ids()? {
+            let filename = self.config.transition_filename(&id);
+            let transition: Transition = match self.read_object(filename.clone()) {
+                Ok(transition) => transition,
+                // Removed by somebody else since we have listed the directory
+                Err(DiskStorageError::NotFound) => continue,
+                Err(err) => return Err(err),
+            };
+            if !keep(&transition) && self.delete_object(filename)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns [`DiskStorageConfig::OBJECT_FORMAT_VERSION`] the schema was
+    /// written with, or `None` if it was written without
+    /// [`DiskStorageConfig::format_versions`]. Fails with
+    /// [`DiskStorageError::NotFound`] if the schema is absent.
+    #[inline]
+    pub fn schema_format_version(&self, id: &SchemaId) -> Result<Option<u16>, DiskStorageError> {
+        self.format_version(self.config.schema_filename(id))
+    }
+
+    #[inline]
+    pub fn genesis_format_version(&self, id: &ContractId) -> Result<Option<u16>, DiskStorageError> {
+        self.format_version(self.config.genesis_filename(id))
+    }
+
+    #[inline]
+    pub fn anchor_format_version(&self, id: &AnchorId) -> Result<Option<u16>, DiskStorageError> {
+        self.format_version(self.config.anchor_filename(id))
+    }
+
+    #[inline]
+    pub fn transition_format_version(&self, id: &NodeId) -> Result<Option<u16>, DiskStorageError> {
+        self.format_version(self.config.transition_filename(id))
+    }
+
+    #[inline]
+    pub fn extension_format_version(&self, id: &NodeId) -> Result<Option<u16>, DiskStorageError> {
+        self.format_version(self.config.extension_filename(id))
+    }
+
+    /// Loads anchor of the transition, resolving its id with the `index`.
+    /// Returns `None` if the index does not know the transition; an index
+    /// entry pointing to an anchor which is absent from the storage is
+    /// reported as [`DiskStorageError::NotFound`].
+    pub fn anchor_for_transition<I: Index>(
+        &self,
+        node_id: &NodeId,
+        index: &I,
+    ) -> Result<Option<Anchor<MerkleBlock>>, DiskStorageError> {
+        let anchor_id = match index.anchor_id_by_transition_id(*node_id) {
+            Ok(anchor_id) => anchor_id,
+            Err(err) if I::is_not_found(&err) => return Ok(None),
+            Err(err) => return Err(DiskStorageError::Index(err.to_string())),
+        };
+        self.anchor(&anchor_id).map(Some)
+    }
+
+    /// Loads anchor of the state extension, resolving its id with
+    /// [`Index::anchor_id_by_extension_id`]; see
+    /// [`DiskStorage::anchor_for_transition`]. Extensions are committed to by
+    /// the anchors in the same way as transitions, so the LNPBP-4 proof of the
+    /// returned anchor contains the extension node id.
+    pub fn anchor_for_extension<I: Index>(
+        &self,
+        node_id: &NodeId,
+        index: &I,
+    ) -> Result<Option<Anchor<MerkleBlock>>, DiskStorageError> {
+        let anchor_id = match index.anchor_id_by_extension_id(*node_id) {
+            Ok(anchor_id) => anchor_id,
+            Err(err) if I::is_not_found(&err) => return Ok(None),
+            Err(err) => return Err(DiskStorageError::Index(err.to_string())),
+        };
+        self.anchor(&anchor_id).map(Some)
+    }
+
+    /// File system keeping the data directory
+    #[inline]
+    fn fs(&self) -> &dyn FileSystem { &*self.config.file_system }
+
+    #[inline]
+    fn check_writable(&self) -> Result<(), DiskStorageError> {
+        if self.config.read_only {
+            return Err(DiskStorageError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Fails with [`DiskStorageError::BatchInProgress`] if a batch of writes
+    /// is started, since the operation can't be recorded in its journal
+    fn check_no_batch(&self) -> Result<(), DiskStorageError> {
+        if self.batch {
+            return Err(DiskStorageError::BatchInProgress);
+        }
+        Ok(())
+    }
+
+    /// Removes all files of the `kind` objects together with their checksum
+    /// sidecars, tombstones and expiry markers, leaving the category directory
+    /// itself (and shard directories, if any) in place
+    fn clear_dir(&self, kind: ObjectKind) -> Result<(), DiskStorageError> {
+        self.check_no_batch()?;
+        let dir = self.config.kind_dir(kind);
+        if !self.fs().exists(&dir) {
+            return Ok(());
+        }
+        for dir in self.config.object_dirs(dir)? {
+            for name in read_filenames(self.fs(), &dir)? {
+                let ext = Path::new(&name).extension().and_then(|ext| ext.to_str());
+                if is_sidecar_ext(ext) || self.config.object_location(kind, &name).is_some() {
+                    self.fs().remove_file(&dir.join(name))?;
+                }
+            }
+            if self.config.sync_writes {
+                self.fs().sync_dir(&dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs all files kept in the category `dir`, both in the flat and
+    /// sharded locations, together with the directories keeping them. Files
+    /// removed concurrently are skipped.
+    fn sync_category(&self, dir: PathBuf) -> Result<(), DiskStorageError> {
+        if !self.fs().exists(&dir) {
+            return Ok(());
+        }
+        let mut touched = BTreeSet::new();
+        for object_dir in self.config.object_dirs(dir.clone())? {
+            for name in read_filenames(self.fs(), &object_dir)? {
+                match self.fs().sync_file(&object_dir.join(name)) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+            }
+            touched.extend(
+                object_dir
+                    .ancestors()
+                    .take_while(|parent| parent.starts_with(&dir))
+                    .map(Path::to_path_buf),
+            );
+        }
+        for dir in touched {
+            self.fs().sync_dir(&dir)?;
+        }
+        Ok(())
+    }
+
+    /// Creates shard directories for `filename` in the sharded layout, if
+    /// they do not exist yet. New directories are synced into their parents
+    /// when [`DiskStorageConfig::sync_writes`] is set.
+    fn create_shard_dir(&self, filename: &Path) -> Result<(), DiskStorageError> {
+        let dir = match filename.parent() {
+            Some(dir) if self.config.sharded && !self.fs().exists(dir) => dir,
+            _ => return Ok(()),
+        };
+        self.fs().create_dir_all(dir)?;
+        for shard in dir.ancestors().take(2) {
+            set_mode(self.fs(), shard, self.config.dir_mode)?;
+        }
+        if self.config.sync_writes {
+            for parent in dir.ancestors().skip(1).take(2) {
+                self.fs().sync_dir(parent)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the file together with its checksum and format sidecars,
+    /// tombstone and expiry marker, returning whether it existed. A file which
+    /// is missing, including one removed concurrently by somebody else, is not
+    /// an error.
+    fn remove_object(&self, filename: PathBuf) -> Result<bool, DiskStorageError> {
+        self.journal_change(&filename)?;
+        match self.fs().remove_file(&filename) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+        for sidecar in &[
+            DiskStorageConfig::checksum_filename(&filename),
+            DiskStorageConfig::format_filename(&filename),
+            DiskStorageConfig::tombstone_filename(&filename),
+            DiskStorageConfig::expiry_filename(&filename),
+        ] {
+            match self.fs().remove_file(sidecar) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        if self.config.sync_writes {
+            if let Some(dir) = filename.parent() {
+                self.fs().sync_dir(dir)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Deletes the object kept in the file, returning whether it was
+    /// present. With [`DiskStorageConfig::tombstones`] the file is kept and
+    /// marked with a tombstone instead.
+    fn delete_object(&self, filename: PathBuf) -> Result<bool, DiskStorageError> {
+        if !self.config.tombstones {
+            return self.remove_object(filename);
+        }
+        if !self.is_present(&filename) {
+            return Ok(false);
+        }
+        self.journal_change(&filename)?;
+        self.write_marker(
+            &DiskStorageConfig::tombstone_filename(&filename),
+            SystemTime::now(),
+        )?;
+        if self.config.sync_writes {
+            if let Some(dir) = filename.parent() {
+                self.fs().sync_dir(dir)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Tells whether the object kept in the file is stored and not deleted
+    #[inline]
+    fn is_present(&self, filename: &Path) -> bool {
+        self.fs().exists(filename) && !self.config.is_tombstoned(filename)
+    }
+
+    /// Returns modification time of the file, reporting absent file as
+    /// [`DiskStorageError::NotFound`]
+    fn modified(&self, filename: PathBuf) -> Result<SystemTime, DiskStorageError> {
+        self.fs()
+            .metadata(&filename)
+            .and_then(|meta| {
+                meta.modified.ok_or_else(|| {
+                    io::Error::other("modification time is not supported by the file system")
+                })
+            })
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => DiskStorageError::NotFound,
+                _ => DiskStorageError::from(err).with_path(IoOp::Metadata, &filename),
+            })
+    }
+
+    /// Reads object from `filename`, checking its id if
+    /// [`DiskStorageConfig::verify_ids`] is set
+    fn read_object<T: StoredObject>(&self, filename: PathBuf) -> Result<T, DiskStorageError> {
+        let object = self.decode_object::<T>(filename.clone())?;
+        if self.config.verify_ids {
+            check_object_id(&self.config, &object, &filename)?;
+        }
+        Ok(object)
+    }
+
+    /// Decodes object from `filename`, reporting absent file as
+    /// [`DiskStorageError::NotFound`] rather than a generic I/O error
+    fn decode_object<T: StoredObject>(&self, filename: PathBuf) -> Result<T, DiskStorageError> {
+        if self.config.is_tombstoned(&filename) {
+            return Err(DiskStorageError::NotFound);
+        }
+        if self.config.format_versions {
+            self.check_format_version(&filename)?;
+        }
+        if self.object_limit::<T>().is_some() {
+            let size = match self.fs().metadata(&filename) {
+                Ok(meta) => meta.len,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(DiskStorageError::NotFound)
+                }
+                Err(err) => {
+                    return Err(DiskStorageError::from(err).with_path(IoOp::Metadata, &filename))
+                }
+            };
+            self.check_object_size::<T>(&filename, size)?;
+        }
+        let checksum_filename = DiskStorageConfig::checksum_filename(&filename);
+        let res = if self.config.checksums && self.fs().exists(&checksum_filename) {
+            self.read_checked(&filename, &checksum_filename)
+        } else {
+            decode_file::<T>(self.fs(), &filename, self.config.data_format)
+        };
+        res.map_err(|err| match err {
+            DiskStorageError::Encoding(strict_encoding::Error::Io(err))
+                if err.into_inner() == io::ErrorKind::NotFound =>
+            {
+                DiskStorageError::NotFound
+            }
+            DiskStorageError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+                DiskStorageError::NotFound
+            }
+            err => err,
+        })
+    }
+
+    /// Fails with [`DiskStorageError::FormatVersionMismatch`] if the format
+    /// sidecar of `filename` records version other than the current one
+    fn check_format_version(&self, filename: &Path) -> Result<(), DiskStorageError> {
+        match self.read_format_version(filename)? {
+            Some(found) if found != DiskStorageConfig::OBJECT_FORMAT_VERSION => {
+                Err(DiskStorageError::FormatVersionMismatch {
+                    path: filename.to_owned(),
+                    found,
+                    expected: DiskStorageConfig::OBJECT_FORMAT_VERSION,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads version kept in the format sidecar of `filename`, if there is
+    /// one
+    fn read_format_version(&self, filename: &Path) -> Result<Option<u16>, DiskStorageError> {
+        let format_filename = DiskStorageConfig::format_filename(filename);
+        let data = match self.fs().read_to_string(&format_filename) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        data.trim()
+            .parse()
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            .map_err(|err| DiskStorageError::from(err).with_path(IoOp::Read, &format_filename))
+    }
+
+    /// Reports format version of the object kept in `filename`, or `None`
+    /// if it was written without [`DiskStorageConfig::format_versions`]
+    fn format_version(&self, filename: PathBuf) -> Result<Option<u16>, DiskStorageError> {
+        if !self.is_present(&filename) {
+            return Err(DiskStorageError::NotFound);
+        }
+        self.read_format_version(&filename)
+    }
+
+    fn read_checked<T: StoredObject>(
+        &self,
+        filename: &Path,
+        checksum_filename: &Path,
+    ) -> Result<T, DiskStorageError> {
+        let checksum = self.fs().read_to_string(checksum_filename)?;
+        if file_checksum(self.fs(), filename)? != checksum.trim() {
+            return Err(DiskStorageError::ChecksumMismatch {
+                id: filename
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            });
+        }
+        decode_file::<T>(self.fs(), filename, self.config.data_format)
+    }
+
+    /// Runs storage operation `op` over the object kept in `filename`,
+    /// reporting its outcome to [`DiskStorageConfig::observer`]. With
+    /// `tracing` feature the operation runs inside a tracing span carrying
+    /// the object kind and id, and once it is complete emits a debug event
+    /// with its duration (including syncing of the written data to the
+    /// disk) and the size of the stored file.
+    fn traced<T>(
+        &self,
+        op: ObjectOp,
+        kind: ObjectKind,
+        filename: &Path,
+        f: impl FnOnce() -> Result<T, DiskStorageError>,
+    ) -> Result<T, DiskStorageError> {
+        if self.config.observer.is_none() && !cfg!(feature = "tracing") {
+            return f();
+        }
+        #[cfg(feature = "tracing")]
+        let span = {
+            let id = filename
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            debug_span!("storage", %op, %kind, %id)
+        };
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        let start = Instant::now();
+        let res = f();
+        let elapsed = start.elapsed();
+        let bytes = match op {
+            ObjectOp::Remove => None,
+            _ => self.fs().metadata(filename).map(|meta| meta.len).ok(),
+        };
+        #[cfg(feature = "tracing")]
+        debug!(
+            elapsed_us = elapsed.as_micros() as u64,
+            ?bytes,
+            success = res.is_ok(),
+            "{} {}",
+            op,
+            kind
+        );
+        if let Some(ref observer) = self.config.observer {
+            match (&res, op) {
+                (Err(err), _) => observer.on_error(kind, err),
+                (Ok(_), ObjectOp::Read) => {
+                    observer.on_read(kind, bytes.unwrap_or_default(), elapsed)
+                }
+                (Ok(_), ObjectOp::Add) => {
+                    observer.on_write(kind, bytes.unwrap_or_default(), elapsed)
+                }
+                (Ok(_), ObjectOp::Remove) => observer.on_remove(kind, elapsed),
+            }
+        }
+        res
+    }
+
+    /// Writes object into a temporary sibling of `filename` and then atomically
+    /// renames it into place, so a crash during the write can't leave a
+    /// truncated `.rgb` file behind. Leftover temporary files have `.tmp`
+    /// extension and are ignored by directory scans.
+    ///
+    /// If [`DiskStorageConfig::sync_writes`] is set, both the written file
+    /// and its directory are synced to the disk before returning.
+    fn write_object(
+        &self,
+        object: &impl StoredObject,
+        filename: PathBuf,
+    ) -> Result<(), DiskStorageError> {
+        self.place_object(object, &filename)?;
+        if self.config.sync_writes {
+            if let Some(dir) = filename.parent() {
+                self.fs().sync_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as [`DiskStorage::write_object`], but leaves syncing of the
+    /// directory to the caller, which allows to do it once per batch of
+    /// writes.
+    fn place_object<T: StoredObject>(
+        &self,
+        object: &T,
+        filename: &Path,
+    ) -> Result<(), DiskStorageError> {
+        let len = self.estimated_len(object);
+        self.check_object_size::<T>(filename, len)?;
+        self.check_quota(len)?;
+        self.create_shard_dir(filename)?;
+        self.journal_change(filename)?;
+        let temp = temp_filename(filename);
+        if let Err(err) = self
+            .write_temp(object, &temp)
+            .and_then(|_| self.write_checksum(&temp, filename))
+            .and_then(|_| self.write_format_version(filename))
+            .and_then(|_| {
+                self.fs()
+                    .rename(&temp, filename)
+                    .map_err(DiskStorageError::from)
+            })
+        {
+            let _ = self.fs().remove_file(&temp);
+            return Err(err);
+        }
+        self.add_used_bytes(len);
+        if self.config.is_tombstoned(filename) {
+            self.fs()
+                .remove_file(&DiskStorageConfig::tombstone_filename(filename))?;
+        }
+        // Objects written without expiry are permanent
+        match self
+            .fs()
+            .remove_file(&DiskStorageConfig::expiry_filename(filename))
+        {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes checksum sidecar for `filename`, whose data are currently kept
+    /// in `temp`. The sidecar is placed before the file itself, so the file
+    /// is never visible without a matching checksum.
+    fn write_checksum(&self, temp: &Path, filename: &Path) -> Result<(), DiskStorageError> {
+        if !self.config.checksums {
+            return Ok(());
+        }
+        let checksum = file_checksum(self.fs(), temp)?;
+        let checksum_filename = DiskStorageConfig::checksum_filename(filename);
+        let checksum_temp = temp_filename(&checksum_filename);
+        let res = self
+            .fs()
+            .write(&checksum_temp, checksum.as_bytes())
+            .and_then(|_| {
+                set_mode(self.fs(), &checksum_temp, self.config.file_mode)?;
+                if self.config.sync_writes {
+                    self.fs().sync_file(&checksum_temp)?;
+                }
+                self.fs().rename(&checksum_temp, &checksum_filename)
+            });
+        if let Err(err) = res {
+            let _ = self.fs().remove_file(&checksum_temp);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Writes format sidecar for `filename` before the file itself is
+    /// placed, like [`DiskStorage::write_checksum`] does
+    fn write_format_version(&self, filename: &Path) -> Result<(), DiskStorageError> {
+        if !self.config.format_versions {
+            return Ok(());
+        }
+        let format_filename = DiskStorageConfig::format_filename(filename);
+        let format_temp = temp_filename(&format_filename);
+        let version = DiskStorageConfig::OBJECT_FORMAT_VERSION.to_string();
+        let res = self
+            .fs()
+            .write(&format_temp, version.as_bytes())
+            .and_then(|_| {
+                set_mode(self.fs(), &format_temp, self.config.file_mode)?;
+                if self.config.sync_writes {
+                    self.fs().sync_file(&format_temp)?;
+                }
+                self.fs().rename(&format_temp, &format_filename)
+            });
+        if let Err(err) = res {
+            let _ = self.fs().remove_file(&format_temp);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    fn write_temp(&self, object: &impl StoredObject, temp: &Path) -> Result<(), DiskStorageError> {
+        let mut writer = self.fs().create(temp)?;
+        match self.config.data_format {
+            #[cfg(feature = "compression")]
+            DataFormat::StrictEncode => match self.config.compression {
+                Some(level) => object.write_compressed(&mut writer, level.0)?,
+                None => object.write_stream(&mut writer)?,
+            },
+            #[cfg(not(feature = "compression"))]
+            DataFormat::StrictEncode => object.write_stream(&mut writer)?,
+            #[cfg(feature = "serde")]
+            DataFormat::Json => {
+                serde_json::to_writer(&mut writer, object)?;
+                0
+            }
+            #[cfg(feature = "serde")]
+            DataFormat::Yaml => {
+                serde_yaml::to_writer(&mut writer, object)?;
+                0
+            }
+            #[cfg(not(feature = "serde"))]
+            format => return Err(DiskStorageError::UnsupportedFormat(format)),
+        };
+        writer.flush()?;
+        drop(writer);
+        set_mode(self.fs(), temp, self.config.file_mode)?;
+        if self.config.sync_writes {
+            self.fs().sync_file(temp)?;
+        }
+        Ok(())
+    }
+}
+
+impl Store for DiskStorage {
+    type Error = DiskStorageError;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, DiskStorageError::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
+        self.scan_ids(
+            self.config.schemata_dir(),
+            DiskStorageConfig::parse_schema_filename,
+            |filename| DiskStorageError::BadSchemaId { filename },
+        )
+    }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.config.object_count(ObjectKind::Schema)?)
+    }
+
+    fn schema_ids_with_prefix(&self, prefix: &str) -> Result<Vec<SchemaId>, Self::Error> {
+        self.scan_ids_with_prefix(
+            self.config.schemata_dir(),
+            prefix,
+            self.config.path_mapper.schema_name_prefix(prefix),
+            DiskStorageConfig::parse_schema_filename,
+            |filename| DiskStorageError::BadSchemaId { filename },
+        )
+    }
+
+    #[inline]
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        let filename = self.config.schema_filename(id);
+        self.traced(ObjectOp::Read, ObjectKind::Schema, &filename, || {
+            self.read_object(filename.clone())
+        })
+    }
+
+    #[inline]
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        Ok(self.is_present(&self.config.schema_filename(id)))
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.schema_filename(&schema.schema_id());
+        let exists = self.is_present(&filename);
+        self.traced(ObjectOp::Add, ObjectKind::Schema, &filename, || {
+            self.write_object(schema, filename.clone())
+        })?;
+        Ok(exists)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.schema_filename(id);
+        self.traced(ObjectOp::Remove, ObjectKind::Schema, &filename, || {
+            self.delete_object(filename.clone())
+        })
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        self.clear_dir(ObjectKind::Schema)
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
+        self.scan_ids(
+            self.config.geneses_dir(),
+            DiskStorageConfig::parse_genesis_filename,
+            |filename| DiskStorageError::BadContractId { filename },
+        )
+    }
+
+    fn contract_id_set(&self) -> Result<BTreeSet<ContractId>, Self::Error> {
+        self.scan_ids(
+            self.config.geneses_dir(),
+            DiskStorageConfig::parse_genesis_filename,
+            |filename| DiskStorageError::BadContractId { filename },
+        )
+    }
+
+    fn contract_ids_with_prefix(&self, prefix: &str) -> Result<Vec<ContractId>, Self::Error> {
+        self.scan_ids_with_prefix(
+            self.config.geneses_dir(),
+            prefix,
+            self.config.path_mapper.genesis_name_prefix(prefix),
+            DiskStorageConfig::parse_genesis_filename,
+            |filename| DiskStorageError::BadContractId { filename },
+        )
+    }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.config.object_count(ObjectKind::Genesis)?)
+    }
+
+    #[inline]
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        let filename = self.config.genesis_filename(id);
+        self.traced(ObjectOp::Read, ObjectKind::Genesis, &filename, || {
+            self.read_object(filename.clone())
+        })
+    }
+
+    #[inline]
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        Ok(self.is_present(&self.config.genesis_filename(id)))
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.genesis_filename(&genesis.contract_id());
+        let exists = self.is_present(&filename);
+        self.traced(ObjectOp::Add, ObjectKind::Genesis, &filename, || {
+            self.write_object(genesis, filename.clone())
+        })?;
+        Ok(exists)
+    }
+
+    #[inline]
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.genesis_filename(id);
+        self.traced(ObjectOp::Remove, ObjectKind::Genesis, &filename, || {
+            self.delete_object(filename.clone())
+        })
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        self.clear_dir(ObjectKind::Genesis)
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        self.scan_ids(
+            self.config.anchors_dir(),
+            DiskStorageConfig::parse_anchor_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    fn anchor_id_set(&self) -> Result<BTreeSet<AnchorId>, Self::Error> {
+        self.scan_ids(
+            self.config.anchors_dir(),
+            DiskStorageConfig::parse_anchor_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    #[inline]
+    fn anchor_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.config.object_count(ObjectKind::Anchor)?)
+    }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        let filename = self.config.anchor_filename(id);
+        self.traced(ObjectOp::Read, ObjectKind::Anchor, &filename, || {
+            self.read_object(filename.clone())
+        })
+    }
+
+    fn for_each_anchor<E, F>(&self, f: F) -> Result<(), E>
+    where
+        E: From<Self::Error>,
+        F: FnMut(AnchorId, Anchor<MerkleBlock>) -> Result<(), E>,
+    {
+        for_each_object(self.anchor_ids_iter()?, |id| self.anchor(id), f)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        Ok(self.is_present(&self.config.anchor_filename(id)))
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.anchor_filename(&anchor.anchor_id());
+        let exists = self.is_present(&filename);
+        self.traced(ObjectOp::Add, ObjectKind::Anchor, &filename, || {
+            self.write_object(anchor, filename.clone())
+        })?;
+        Ok(exists)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.anchor_filename(id);
+        self.traced(ObjectOp::Remove, ObjectKind::Anchor, &filename, || {
+            self.delete_object(filename.clone())
+        })
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        self.clear_dir(ObjectKind::Anchor)
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.scan_ids(
+            self.config.transitions_dir(),
+            DiskStorageConfig::parse_transition_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    fn transition_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        self.scan_ids(
+            self.config.transitions_dir(),
+            DiskStorageConfig::parse_transition_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    #[inline]
+    fn transition_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.config.object_count(ObjectKind::Transition)?)
+    }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        let filename = self.config.transition_filename(id);
+        self.traced(ObjectOp::Read, ObjectKind::Transition, &filename, || {
+            self.read_object(filename.clone())
+        })
+    }
+
+    fn transitions_many(
+        &self,
+        ids: &[NodeId],
+    ) -> Result<BTreeMap<NodeId, Transition>, Self::Error> {
+        let mut transitions = BTreeMap::new();
+        for id in ids {
+            // Opening the file right away saves us from a separate existence
+            // check for each of the transitions
+            match self.read_object(self.config.transition_filename(id)) {
+                Ok(transition) => {
+                    transitions.insert(*id, transition);
+                }
+                Err(DiskStorageError::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(transitions)
+    }
+
+    fn for_each_transition<E, F>(&self, f: F) -> Result<(), E>
+    where
+        E: From<Self::Error>,
+        F: FnMut(NodeId, Transition) -> Result<(), E>,
+    {
+        for_each_object(self.transition_ids_iter()?, |id| self.transition(id), f)
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        Ok(self.is_present(&self.config.transition_filename(id)))
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.transition_filename(&transition.node_id());
+        let exists = self.is_present(&filename);
+        self.traced(ObjectOp::Add, ObjectKind::Transition, &filename, || {
+            self.write_object(transition, filename.clone())
+        })?;
+        Ok(exists)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        self.check_writable()?;
+        // Fail before writing any of the transitions if the whole batch can't
+        // fit, or some of them is too large
+        let mut len = 0u64;
+        for transition in transitions {
+            let transition_len = self.estimated_len(transition);
+            self.check_object_size::<Transition>(
+                &self.config.transition_filename(&transition.node_id()),
+                transition_len,
+            )?;
+            len += transition_len;
+        }
+        self.check_quota(len)?;
+        let mut created = 0;
+        let mut dirs = BTreeSet::new();
+        for transition in transitions {
+            let filename = self.config.transition_filename(&transition.node_id());
+            if !self.is_present(&filename) {
+                created += 1;
+            }
+            self.traced(ObjectOp::Add, ObjectKind::Transition, &filename, || {
+                self.place_object(transition, &filename)
+            })?;
+            if let Some(dir) = filename.parent() {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+        if self.config.sync_writes {
+            for dir in dirs {
+                self.fs().sync_dir(&dir)?;
+            }
+        }
+        Ok(created)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.transition_filename(id);
+        self.traced(ObjectOp::Remove, ObjectKind::Transition, &filename, || {
+            self.delete_object(filename.clone())
+        })
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        self.clear_dir(ObjectKind::Transition)
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.scan_ids(
+            self.config.extensions_dir(),
+            DiskStorageConfig::parse_extension_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    fn extension_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        self.scan_ids(
+            self.config.extensions_dir(),
+            DiskStorageConfig::parse_extension_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    #[inline]
+    fn extension_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.config.object_count(ObjectKind::Extension)?)
+    }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        let filename = self.config.extension_filename(id);
+        self.traced(ObjectOp::Read, ObjectKind::Extension, &filename, || {
+            self.read_object(filename.clone())
+        })
+    }
+
+    fn for_each_extension<E, F>(&self, f: F) -> Result<(), E>
+    where
+        E: From<Self::Error>,
+        F: FnMut(NodeId, Extension) -> Result<(), E>,
+    {
+        for_each_object(self.extension_ids_iter()?, |id| self.extension(id), f)
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        Ok(self.is_present(&self.config.extension_filename(id)))
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.extension_filename(&extension.node_id());
+        let exists = self.is_present(&filename);
+        self.traced(ObjectOp::Add, ObjectKind::Extension, &filename, || {
+            self.write_object(extension, filename.clone())
+        })?;
+        Ok(exists)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self.config.extension_filename(id);
+        self.traced(ObjectOp::Remove, ObjectKind::Extension, &filename, || {
+            self.delete_object(filename.clone())
+        })
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        self.clear_dir(ObjectKind::Extension)
+    }
+
+    /// Each write is already flushed to the disk with
+    /// [`DiskStorageConfig::sync_writes`]; otherwise syncs all the stored
+    /// files and the directories keeping them
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.config.sync_writes || self.config.read_only {
+            return Ok(());
+        }
+        for kind in ObjectKind::ALL.iter().copied() {
+            self.sync_category(self.config.kind_dir(kind))?;
+        }
+        self.sync_category(self.config.consignments_dir())?;
+        self.fs().sync_dir(&self.config.data_dir)?;
+        Ok(())
+    }
+
+    /// Applies the transaction as a batch of writes (see
+    /// [`DiskStorage::begin_batch`]), so all of its mutations, including
+    /// removals, are undone if a mutation of the transaction fails, or by the
+    /// next [`DiskStorage::new`] after a crash. Within an already started
+    /// batch the transaction becomes a part of that batch.
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> {
+        if self.batch {
+            return txn.apply_to(self);
+        }
+        self.begin_batch()?;
+        if let Err(err) = txn.apply_to(self) {
+            self.batch = false;
+            if let Err(rollback_err) = self.rollback_journal() {
+                warn!("Unable to roll back failed transaction: {}", rollback_err);
+            }
+            return Err(err);
+        }
+        self.commit()
+    }
+}
+
+impl StoreMeta for DiskStorage {
+    fn schema_modified(&self, id: &SchemaId) -> Result<SystemTime, Self::Error> {
+        self.modified(self.config.schema_filename(id))
+    }
+
+    fn genesis_modified(&self, id: &ContractId) -> Result<SystemTime, Self::Error> {
+        self.modified(self.config.genesis_filename(id))
+    }
+
+    fn anchor_modified(&self, id: &AnchorId) -> Result<SystemTime, Self::Error> {
+        self.modified(self.config.anchor_filename(id))
+    }
+
+    fn transition_modified(&self, id: &NodeId) -> Result<SystemTime, Self::Error> {
+        self.modified(self.config.transition_filename(id))
+    }
+
+    fn extension_modified(&self, id: &NodeId) -> Result<SystemTime, Self::Error> {
+        self.modified(self.config.extension_filename(id))
+    }
+}
+
+impl SnapshotStore for DiskStorage {
+    /// Hard-links all files of the data directory (including sidecars,
+    /// consignments and the journal of an uncommitted batch) into `dest`,
+    /// which makes the snapshot near-instant and free of extra space; the
+    /// files are copied where the file system can't link them. Temporary
+    /// files of the writes in progress and the lock file are skipped. `dest`
+    /// must lie outside of the data directory.
+    ///
+    /// Linked files remain valid snapshots, since the stored files are never
+    /// modified in place, but replaced by renaming new files into their
+    /// place. The storage can't be written while borrowed for the snapshot,
+    /// and the lock on the data directory keeps other processes from
+    /// writing it, so the snapshot is consistent.
+    fn snapshot(&self, dest: &Path) -> Result<(), Self::Error> {
+        if self.fs().exists(dest) {
+            return Err(DiskStorageError::from(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "snapshot destination already exists",
+            ))
+            .with_path(IoOp::CreateDir, dest));
+        }
+        let linked = self.snapshot_dir(&self.config.data_dir, dest)?;
+        debug!("Snapshot of {} files is taken into {:?}", linked, dest);
+        Ok(())
+    }
+}
+
+impl ConsignmentStore for DiskStorage {
+    fn consignment_ids(&self) -> Result<Vec<ConsignmentId>, Self::Error> {
+        collect_ids(self.consignment_ids_iter()?)
+    }
+
+    fn consignment(&self, id: &ConsignmentId) -> Result<Consignment, Self::Error> {
+        self.read_object(self.config.consignment_filename(id))
+    }
+
+    fn has_consignment(&self, id: &ConsignmentId) -> Result<bool, Self::Error> {
+        Ok(self.fs().exists(&self.config.consignment_filename(id)))
+    }
+
+    fn add_consignment(&mut self, consignment: &Consignment) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let filename = self
+            .config
+            .consignment_filename(&ConsignmentId::from_consignment(consignment));
+        let exists = self.fs().exists(&filename);
+        self.write_object(consignment, filename)?;
+        Ok(exists)
+    }
+
+    fn remove_consignment(&mut self, id: &ConsignmentId) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        self.remove_object(self.config.consignment_filename(id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::stashd::storage::assert_store_conformance;
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_disk_new_extension() {
+        let data_dir = TempPath::new("disk-test");
+
+        let extension = Extension::default();
+        let extension_node_id = extension.node_id();
+
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        assert!(!storage.add_extension(&extension).unwrap());
+        assert!(storage.has_extension(&extension_node_id).unwrap());
+        assert_eq!(storage.extension(&extension_node_id).unwrap(), extension);
+        assert_eq!(vec![extension_node_id], storage.extension_ids().unwrap());
+    }
+
+    #[test]
+    fn test_disk_partial_layout() {
+        let data_dir = TempPath::new("disk-partial");
+
+        let config = DiskStorageConfig::new(data_dir.to_path_buf());
+        drop(DiskStorage::new(config.clone()).unwrap());
+        fs::remove_dir_all(config.extensions_dir()).unwrap();
+        fs::remove_dir_all(config.consignments_dir()).unwrap();
+
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        assert!(!storage.add_extension(&Extension::default()).unwrap());
+        assert!(config.consignments_dir().is_dir());
+    }
+
+    #[test]
+    fn test_disk_locking() {
+        let data_dir = TempPath::new("disk-locking");
+
+        let config = DiskStorageConfig::new(data_dir.to_path_buf());
+        let read_only = config.clone().with_read_only(true);
+        let writer = DiskStorage::new(config.clone()).unwrap();
+        assert!(matches!(
+            DiskStorage::new(config.clone()),
+            Err(DiskStorageError::Locked {
+                holder: LockHolder::Writer
+            })
+        ));
+        assert!(matches!(
+            DiskStorage::new(read_only.clone()),
+            Err(DiskStorageError::Locked {
+                holder: LockHolder::Writer
+            })
+        ));
+        drop(writer);
+
+        let first = DiskStorage::new(read_only.clone()).unwrap();
+        let second = DiskStorage::new(read_only).unwrap();
+        assert!(matches!(
+            DiskStorage::new(config.clone()),
+            Err(DiskStorageError::Locked {
+                holder: LockHolder::Readers
+            })
+        ));
+        drop(first);
+        assert!(DiskStorage::new(config.clone()).is_err());
+        drop(second);
+        assert!(DiskStorage::new(config).is_ok());
+    }
+
+    #[test]
+    fn test_disk_reader_without_lock_file() {
+        let data_dir = TempPath::new("disk-no-lock");
+
+        let config = DiskStorageConfig::new(data_dir.to_path_buf());
+        drop(DiskStorage::new(config.clone()).unwrap());
+        fs::remove_file(config.lock_filename()).unwrap();
+
+        // Reader goes without the lock instead of creating the lock file
+        let reader = DiskStorage::new(config.clone().with_read_only(true)).unwrap();
+        assert!(!config.lock_filename().exists());
+        assert!(reader.schema_ids().unwrap().is_empty());
+        drop(reader);
+        assert!(DiskStorage::new(config).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_disk_reader_without_write_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let data_dir = TempPath::new("disk-no-write");
+
+        let config = DiskStorageConfig::new(data_dir.to_path_buf());
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        storage.add_genesis(&Genesis::default()).unwrap();
+        drop(storage);
+        let read_only = config.clone().with_read_only(true);
+        let set_mode = |path: &Path, mode| {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap()
+        };
+
+        // Shared lock is taken on the lock file opened for reading only
+        set_mode(&config.lock_filename(), 0o444);
+        set_mode(&data_dir, 0o555);
+        let reader = DiskStorage::new(read_only.clone()).unwrap();
+        assert!(reader
+            .has_genesis(&Genesis::default().contract_id())
+            .unwrap());
+        drop(reader);
+
+        set_mode(&data_dir, 0o755);
+        fs::remove_file(config.lock_filename()).unwrap();
+        set_mode(&data_dir, 0o555);
+        let reader = DiskStorage::new(read_only).unwrap();
+        assert!(reader
+            .has_genesis(&Genesis::default().contract_id())
+            .unwrap());
+        assert!(!config.lock_filename().exists());
+        drop(reader);
+
+        set_mode(&data_dir, 0o755);
+    }
+
+    #[test]
+    fn test_disk_extension_anchor() {
+        use crate::stashd::index::{BTreeIndex, BTreeIndexConfig};
+        use crate::stashd::storage::sample_anchor;
+
+        let data_dir = TempPath::new("disk-ext-anchor");
+
+        let extension = Extension::default();
+        let node_id = extension.node_id();
+        let anchor = sample_anchor(0, &[node_id]);
+
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.join("stash"))).unwrap();
+        let mut index = BTreeIndex::new(BTreeIndexConfig::new(data_dir.join("index"))).unwrap();
+        storage.add_extension(&extension).unwrap();
+        assert_eq!(
+            storage.anchor_for_extension(&node_id, &index).unwrap(),
+            None
+        );
+
+        storage.add_anchor(&anchor).unwrap();
+        index.index_anchor(&anchor).unwrap();
+        let resolved = storage
+            .anchor_for_extension(&node_id, &index)
+            .unwrap()
+            .unwrap();
+        assert_eq!(resolved, anchor);
+        assert!(resolved
+            .lnpbp4_proof
+            .into_iter()
+            .any(|message| message.into_inner() == node_id.into_inner().into_inner()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_disk_symlinked_data_dir() {
+        use std::os::unix::fs::symlink;
+
+        let root = TempPath::new("disk-symlink");
+        fs::create_dir_all(&root).unwrap();
+        let target = root.join("volume");
+        let link = root.join("data");
+        symlink(&target, &link).unwrap();
+
+        // Target is not mounted: nothing is created over the dangling link
+        let err = DiskStorage::new(DiskStorageConfig::new(link.clone())).unwrap_err();
+        assert!(matches!(err, DiskStorageError::DataDirUnavailable { ref path } if *path == link));
+        let err = DiskStorage::new(DiskStorageConfig::new(link.join("signet"))).unwrap_err();
+        assert!(matches!(err, DiskStorageError::DataDirUnavailable { ref path } if *path == link));
+        assert!(!target.exists());
+
+        // Once the target is available, the storage is kept inside it
+        fs::create_dir(&target).unwrap();
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(link.clone())).unwrap();
+        assert!(!storage.add_genesis(&Genesis::default()).unwrap());
+        assert!(DiskStorageConfig::new(target.clone())
+            .genesis_filename(&Genesis::default().contract_id())
+            .exists());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_disk_compression() {
+        use strict_encoding::StrictEncode;
+
+        let data_dir = TempPath::new("disk-zstd");
+
+        let schema = rgb20::schema::schema();
+        let schema_id = schema.schema_id();
+        let config = DiskStorageConfig::new(data_dir.to_path_buf())
+            .with_compression(Some(CompressionLevel::default()));
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        storage.add_schema(&schema).unwrap();
+        let data = fs::read(config.schema_filename(&schema_id)).unwrap();
+        assert_eq!(&data[..4], &[0x28, 0xB5, 0x2F, 0xFD]);
+        assert!(data.len() < schema.strict_serialize().unwrap().len());
+        assert_eq!(storage.schema(&schema_id).unwrap(), schema);
+        drop(storage);
+
+        // Files written without compression remain readable
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        storage.add_genesis(&Genesis::default()).unwrap();
+        drop(storage);
+        let storage = DiskStorage::new(config).unwrap();
+        assert_eq!(storage.schema(&schema_id).unwrap(), schema);
+        assert_eq!(
+            storage.genesis(&Genesis::default().contract_id()).unwrap(),
+            Genesis::default()
+        );
+    }
+
+    #[test]
+    fn test_disk_checksum_mismatch() {
+        let data_dir = TempPath::new("disk-checksum");
+
+        let schema = rgb20::schema::schema();
+        let schema_id = schema.schema_id();
+        let config = DiskStorageConfig::new(data_dir.to_path_buf()).with_checksums(true);
+        let mut storage = DiskStorage::new(config.clone()).unwrap();
+        storage.add_schema(&schema).unwrap();
+        let filename = config.schema_filename(&schema_id);
+        assert!(DiskStorageConfig::checksum_filename(&filename).exists());
+        assert_eq!(storage.schema(&schema_id).unwrap(), schema);
+
+        let mut data = fs::read(&filename).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        fs::write(&filename, data).unwrap();
+        assert!(matches!(
+            storage.schema(&schema_id),
+            Err(DiskStorageError::ChecksumMismatch { id }) if id == schema_id.to_string()
+        ));
+
+        // Files without sidecars are read unchecked
+        storage.add_genesis(&Genesis::default()).unwrap();
+        let contract_id = Genesis::default().contract_id();
+        fs::remove_file(DiskStorageConfig::checksum_filename(
+            &config.genesis_filename(&contract_id),
+        ))
+        .unwrap();
+        assert_eq!(storage.genesis(&contract_id).unwrap(), Genesis::default());
+    }
+
+    #[test]
+    fn test_disk_conformance() {
+        let data_dir = TempPath::new("disk-conformance");
+
+        let mut n = 0;
+        assert_store_conformance(|| {
+            n += 1;
+            DiskStorage::new(DiskStorageConfig::new(data_dir.join(n.to_string()))).unwrap()
+        });
+    }
+}
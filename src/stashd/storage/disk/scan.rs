@@ -0,0 +1,437 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rgb::prelude::*;
+
+use super::{is_reserved_name, is_sidecar_ext, DiskStorage, DiskStorageConfig, DiskStorageError};
+use crate::stashd::storage::{ConsignmentId, DirEntry, FileSystem, ObjectKind};
+
+/// Lazily lists names of the files inside `dir`, skipping subdirectories.
+/// Names which are not valid unicode can't be our files and are skipped as
+/// well; they are reported by [`DiskStorage::ignored_files`].
+pub(super) fn dir_filenames(
+    fs: &dyn FileSystem,
+    dir: &Path,
+) -> Result<impl Iterator<Item = Result<String, io::Error>>, io::Error> {
+    Ok(fs.read_dir(dir)?.filter_map(|entry| match entry {
+        Ok(entry) if entry.is_dir => None,
+        Ok(entry) => match entry.path.file_name()?.to_str() {
+            Some(name) => Some(Ok(name.to_owned())),
+            None => {
+                debug!("Skipping file with non-unicode name {:?}", entry.path);
+                None
+            }
+        },
+        Err(err) => Some(Err(err)),
+    }))
+}
+
+#[inline]
+pub(super) fn read_filenames(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<String>, io::Error> {
+    dir_filenames(fs, dir)?.collect()
+}
+
+/// Returns last component of the path if it may be a name of an object file
+pub(super) fn object_file_name(filename: &Path) -> Option<&str> {
+    filename
+        .file_name()?
+        .to_str()
+        .filter(|name| !is_reserved_name(name))
+}
+
+/// Lists second-level shard directories inside the category `dir`; entries
+/// which are not shard directories are skipped
+pub(super) fn shard_dirs(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut dirs = vec![];
+    for shard in sub_shards(fs, dir)? {
+        dirs.extend(sub_shards(fs, &shard)?);
+    }
+    Ok(dirs)
+}
+
+pub(super) fn sub_shards(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut dirs = vec![];
+    if !fs.exists(dir) {
+        return Ok(dirs);
+    }
+    for entry in fs.read_dir(dir)? {
+        let entry = entry?;
+        if is_shard_dir(&entry) {
+            dirs.push(entry.path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Lists entries of `dir` which are not shard directories
+fn non_shard_entries(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut entries = vec![];
+    for entry in fs.read_dir(dir)? {
+        let entry = entry?;
+        if !is_shard_dir(&entry) {
+            entries.push(entry.path);
+        }
+    }
+    Ok(entries)
+}
+
+/// Checks whether the entry is a shard directory, i.e. a directory named by
+/// two lowercase hex digits
+fn is_shard_dir(entry: &DirEntry) -> bool {
+    entry.is_dir
+        && entry
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| {
+                name.len() == 2 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+            })
+            .unwrap_or_default()
+}
+
+/// Collects ids returned by one of `DiskStorage::*_ids_iter` methods; files
+/// which names are not valid ids are skipped (see
+/// [`DiskStorage::ignored_files`])
+pub(super) fn collect_ids<I, C: Default + Extend<I>>(
+    ids: impl Iterator<Item = Result<I, DiskStorageError>>,
+) -> Result<C, DiskStorageError> {
+    let mut list = C::default();
+    for id in ids {
+        match id {
+            Ok(id) => list.extend(Some(id)),
+            Err(err) if err.is_bad_filename() => debug!("Skipping stored file: {}", err),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(list)
+}
+
+/// Loads objects listed by one of `DiskStorage::*_ids_iter` methods one by one,
+/// passing them to `f`; see
+/// [`Store::for_each_anchor`](crate::stashd::storage::Store::for_each_anchor)
+pub(super) fn for_each_object<I, T, E>(
+    ids: impl Iterator<Item = Result<I, DiskStorageError>>,
+    load: impl Fn(&I) -> Result<T, DiskStorageError>,
+    mut f: impl FnMut(I, T) -> Result<(), E>,
+) -> Result<(), E>
+where
+    E: From<DiskStorageError>,
+{
+    for id in ids {
+        let id = match id {
+            Ok(id) => id,
+            Err(err) if err.is_bad_filename() => continue,
+            Err(err) => return Err(err.into()),
+        };
+        match load(&id) {
+            Ok(object) => f(id, object)?,
+            // Removed by somebody else since we have listed the directory
+            Err(DiskStorageError::NotFound) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+impl DiskStorage {
+    /// Lazily reads ids of the `dir` objects from the data directory with
+    /// `parse`, reporting files which names are not valid ids with the error
+    /// produced by `bad`
+    fn ids_iter<I>(
+        &self,
+        dir: PathBuf,
+        parse: fn(&DiskStorageConfig, &Path) -> Option<I>,
+        bad: fn(String) -> DiskStorageError,
+    ) -> Result<impl Iterator<Item = Result<I, DiskStorageError>>, DiskStorageError> {
+        let config = self.config.clone();
+        Ok(self.config.object_names_iter(dir)?.map(move |name| {
+            let name = name?;
+            parse(&config, Path::new(&name)).ok_or_else(|| bad(name))
+        }))
+    }
+
+    /// Collects ids of the `dir` objects like [`collect_ids`] does for
+    /// [`DiskStorage::ids_iter`]. With the `rayon` feature, names of the
+    /// listings of at least [`DiskStorageConfig::PARALLEL_SCAN_MIN`] files are
+    /// parsed in parallel, while the directories are still read by the
+    /// calling thread. Files which names are not valid ids are skipped.
+    #[cfg(not(feature = "rayon"))]
+    #[inline]
+    pub(super) fn scan_ids<I: Send, C: Default + Extend<I>>(
+        &self,
+        dir: PathBuf,
+        parse: fn(&DiskStorageConfig, &Path) -> Option<I>,
+        bad: fn(String) -> DiskStorageError,
+    ) -> Result<C, DiskStorageError> {
+        collect_ids(self.ids_iter(dir, parse, bad)?)
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(super) fn scan_ids<I: Send, C: Default + Extend<I>>(
+        &self,
+        dir: PathBuf,
+        parse: fn(&DiskStorageConfig, &Path) -> Option<I>,
+        bad: fn(String) -> DiskStorageError,
+    ) -> Result<C, DiskStorageError> {
+        use rayon::prelude::*;
+
+        let names = self
+            .config
+            .object_names_iter(dir)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let config = &self.config;
+        if names.len() < DiskStorageConfig::PARALLEL_SCAN_MIN {
+            return collect_ids(
+                names
+                    .into_iter()
+                    .map(|name| parse(config, Path::new(&name)).ok_or_else(|| bad(name))),
+            );
+        }
+        let ids = names
+            .par_iter()
+            .filter_map(|name| {
+                let id = parse(config, Path::new(name));
+                if id.is_none() {
+                    debug!("Skipping stored file: {}", bad(name.clone()));
+                }
+                id
+            })
+            .collect::<Vec<_>>();
+        let mut list = C::default();
+        list.extend(ids);
+        Ok(list)
+    }
+
+    /// Collects ids of the `dir` objects which bech32 representation starts
+    /// with `prefix`. Names of the files not starting with `name_prefix` (see
+    /// [`PathMapper::schema_name_prefix`](crate::stashd::storage::PathMapper::schema_name_prefix))
+    /// are skipped without being parsed.
+    pub(super) fn scan_ids_with_prefix<I: ToBech32>(
+        &self,
+        dir: PathBuf,
+        prefix: &str,
+        name_prefix: Option<String>,
+        parse: fn(&DiskStorageConfig, &Path) -> Option<I>,
+        bad: fn(String) -> DiskStorageError,
+    ) -> Result<Vec<I>, DiskStorageError> {
+        let mut ids = vec![];
+        for name in self.config.object_names_iter(dir)? {
+            let name = name?;
+            if matches!(name_prefix, Some(ref name_prefix) if !name.starts_with(name_prefix.as_str()))
+            {
+                continue;
+            }
+            match parse(&self.config, Path::new(&name)) {
+                Some(id) if id.to_bech32().to_string().starts_with(prefix) => ids.push(id),
+                Some(_) => {}
+                None => debug!("Skipping stored file: {}", bad(name)),
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Lazily reads schema ids from the data directory, without collecting
+    /// all of them in memory.
+    ///
+    /// Each of the `*_ids_iter` methods reports a file which name is not a
+    /// valid id as an error entry (like [`DiskStorageError::BadSchemaId`]) and
+    /// continues the listing; these entries are skipped by the
+    /// [`Store`](crate::stashd::storage::Store) methods listing the ids, which
+    /// parse large listings in parallel with the `rayon` feature.
+    pub fn schema_ids_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<SchemaId, DiskStorageError>>, DiskStorageError> {
+        self.ids_iter(
+            self.config.schemata_dir(),
+            DiskStorageConfig::parse_schema_filename,
+            |filename| DiskStorageError::BadSchemaId { filename },
+        )
+    }
+
+    /// Lazily reads contract ids from the data directory, without collecting
+    /// all of them in memory
+    pub fn contract_ids_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<ContractId, DiskStorageError>>, DiskStorageError> {
+        self.ids_iter(
+            self.config.geneses_dir(),
+            DiskStorageConfig::parse_genesis_filename,
+            |filename| DiskStorageError::BadContractId { filename },
+        )
+    }
+
+    #[inline]
+    pub fn anchor_ids_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<AnchorId, DiskStorageError>>, DiskStorageError> {
+        self.ids_iter(
+            self.config.anchors_dir(),
+            DiskStorageConfig::parse_anchor_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    #[inline]
+    pub fn transition_ids_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<NodeId, DiskStorageError>>, DiskStorageError> {
+        self.ids_iter(
+            self.config.transitions_dir(),
+            DiskStorageConfig::parse_transition_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    #[inline]
+    pub fn extension_ids_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<NodeId, DiskStorageError>>, DiskStorageError> {
+        self.ids_iter(
+            self.config.extensions_dir(),
+            DiskStorageConfig::parse_extension_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    #[inline]
+    pub fn consignment_ids_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<ConsignmentId, DiskStorageError>>, DiskStorageError>
+    {
+        self.ids_iter(
+            self.config.consignments_dir(),
+            DiskStorageConfig::parse_consignment_filename,
+            |filename| DiskStorageError::BadHex { filename },
+        )
+    }
+
+    /// Lists entries of the storage directories which are not stored objects
+    /// (like backup or editor swap files, subdirectories, or `.rgb` files
+    /// which names are not valid ids) and thus are ignored by the storage.
+    /// Checksum sidecars, tombstones and expiry markers are not reported.
+    pub fn ignored_files(&self) -> Result<Vec<PathBuf>, DiskStorageError> {
+        let mut ignored = vec![];
+        for kind in ObjectKind::ALL.iter().copied() {
+            let dir = self.config.kind_dir(kind);
+            if !self.fs().exists(&dir) {
+                continue;
+            }
+            if self.config.sharded {
+                // Only shard directories are expected above the stored files
+                ignored.extend(non_shard_entries(self.fs(), &dir)?);
+                for shard in sub_shards(self.fs(), &dir)? {
+                    ignored.extend(non_shard_entries(self.fs(), &shard)?);
+                }
+            }
+            for dir in self.config.object_dirs(dir)? {
+                for entry in self.fs().read_dir(&dir)? {
+                    let DirEntry { path, is_dir } = entry?;
+                    if is_sidecar_ext(path.extension().and_then(|ext| ext.to_str())) {
+                        continue;
+                    }
+                    let name = path.file_name().and_then(|name| name.to_str());
+                    match name {
+                        Some(name)
+                            if !is_dir && self.config.object_location(kind, name).is_some() => {}
+                        _ => ignored.push(path),
+                    }
+                }
+            }
+        }
+        Ok(ignored)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use amplify::Wrapper;
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::stashd::storage::Store;
+    use crate::util::TempPath;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_disk_non_unicode_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        use crate::util::file::read_dir_filenames_with_ignored;
+
+        let data_dir = TempPath::new("disk-non-unicode");
+
+        let genesis = Genesis::default();
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        let bad = storage
+            .config
+            .geneses_dir()
+            .join(OsStr::from_bytes(b"\xff\xfe.rgb"));
+        fs::write(&bad, b"").unwrap();
+
+        assert_eq!(storage.contract_ids().unwrap(), vec![genesis.contract_id()]);
+        assert_eq!(storage.config.genesis_names().unwrap().len(), 1);
+        assert_eq!(storage.ignored_files().unwrap(), vec![bad.clone()]);
+        let (names, ignored) =
+            read_dir_filenames_with_ignored(storage.config.geneses_dir(), Some("rgb")).unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(ignored, vec![bad]);
+    }
+
+    #[test]
+    fn test_disk_large_listing() {
+        let data_dir = TempPath::new("disk-large");
+
+        let storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        let mut ids = (0..DiskStorageConfig::PARALLEL_SCAN_MIN as u32)
+            .map(|no| ContractId::from_inner(Hash::hash(&no.to_be_bytes())))
+            .collect::<Vec<_>>();
+        for id in &ids {
+            fs::write(storage.config.genesis_filename(id), b"").unwrap();
+        }
+        fs::write(storage.config.geneses_dir().join("bad.rgb"), b"").unwrap();
+
+        let mut listed = storage.contract_ids().unwrap();
+        ids.sort();
+        listed.sort();
+        assert_eq!(listed, ids);
+    }
+
+    #[test]
+    fn test_disk_counts_skip_unrelated_files() {
+        let data_dir = TempPath::new("disk-counts");
+
+        let genesis = Genesis::default();
+        let schema = rgb20::schema::schema();
+        let mut storage =
+            DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf()).with_tombstones(true))
+                .unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        storage.add_schema(&schema).unwrap();
+        storage.remove_schema(&schema.schema_id()).unwrap();
+        let dir = storage.config.geneses_dir();
+        fs::write(dir.join("README"), b"").unwrap();
+        fs::write(dir.join("backup.rgb~"), b"").unwrap();
+
+        assert_eq!(storage.contract_count().unwrap(), 1);
+        assert_eq!(storage.schema_count().unwrap(), 0);
+        assert_eq!(storage.anchor_count().unwrap(), 0);
+    }
+}
@@ -0,0 +1,250 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{DiskStorage, DiskStorageError, StoredObject};
+use crate::stashd::storage::ObjectKind;
+use crate::util::file::*;
+
+/// Disk space used by a single category of the stored objects
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct CategoryUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Statistics returned by [`DiskStorage::disk_usage`]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct StorageStats {
+    pub schemata: CategoryUsage,
+    pub geneses: CategoryUsage,
+    pub anchors: CategoryUsage,
+    pub transitions: CategoryUsage,
+    pub extensions: CategoryUsage,
+}
+
+impl StorageStats {
+    /// Total size of the stored files of all the categories
+    pub fn total_bytes(&self) -> u64 {
+        self.schemata.bytes
+            + self.geneses.bytes
+            + self.anchors.bytes
+            + self.transitions.bytes
+            + self.extensions.bytes
+    }
+}
+
+/// Summary of the storage state returned by [`DiskStorage::health`]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct StorageHealth {
+    /// Version of the storage format recorded in the data directory; absent
+    /// for a directory opened read-only before it was ever initialized
+    pub version: Option<u16>,
+    /// Whether new files can be created in the data directory
+    pub writable: bool,
+    /// Number and size of the stored files for each category of the objects
+    pub usage: StorageStats,
+    /// Total size of the stored files of all categories
+    pub total_bytes: u64,
+}
+
+impl DiskStorage {
+    /// Computes number and total size of the stored files for each category
+    /// of the objects
+    pub fn disk_usage(&self) -> Result<StorageStats, DiskStorageError> {
+        Ok(StorageStats {
+            schemata: self.dir_usage(ObjectKind::Schema)?,
+            geneses: self.dir_usage(ObjectKind::Genesis)?,
+            anchors: self.dir_usage(ObjectKind::Anchor)?,
+            transitions: self.dir_usage(ObjectKind::Transition)?,
+            extensions: self.dir_usage(ObjectKind::Extension)?,
+        })
+    }
+
+    /// Collects operator-facing summary of the storage: format version,
+    /// writability of the data directory (probed by creating and removing a
+    /// temporary file) and disk usage
+    pub fn health(&self) -> Result<StorageHealth, DiskStorageError> {
+        let version = if self.fs().exists(&self.config.version_filename()) {
+            Some(Self::read_version(&self.config)?)
+        } else {
+            None
+        };
+        let usage = self.disk_usage()?;
+        Ok(StorageHealth {
+            version,
+            writable: !self.config.read_only && self.probe_writable(),
+            usage,
+            total_bytes: usage.total_bytes(),
+        })
+    }
+
+    /// Checks that `incoming` bytes can be written without exceeding
+    /// [`DiskStorageConfig::max_data_dir_bytes`](super::DiskStorageConfig::max_data_dir_bytes)
+    /// and
+    /// [`DiskStorageConfig::min_free_bytes`](super::DiskStorageConfig::min_free_bytes);
+    /// does nothing if neither is set. Called before each write, but may be
+    /// called in advance of a large import to fail before any of its data are
+    /// written.
+    pub fn check_quota(&self, incoming: u64) -> Result<(), DiskStorageError> {
+        if let Some(limit) = self.config.max_data_dir_bytes {
+            let mut used = self.used_bytes.load(Ordering::Relaxed);
+            if used == u64::MAX || used.saturating_add(incoming) > limit {
+                used = self.disk_usage()?.total_bytes();
+                self.used_bytes.store(used, Ordering::Relaxed);
+            }
+            if used.saturating_add(incoming) > limit {
+                return Err(DiskStorageError::QuotaExceeded { used, limit });
+            }
+        }
+        if let Some(min_free) = self.config.min_free_bytes {
+            if let Some(available) = self.fs().available_space(&self.config.data_dir)? {
+                let required = min_free.saturating_add(incoming);
+                if available < required {
+                    return Err(DiskStorageError::InsufficientSpace {
+                        available,
+                        required,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Accounts `written` bytes in the estimate of the used space
+    pub(super) fn add_used_bytes(&self, written: u64) {
+        let _ = self
+            .used_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                if used == u64::MAX {
+                    None
+                } else {
+                    Some(used.saturating_add(written))
+                }
+            });
+    }
+
+    /// Estimated size of the object file, or zero if neither the space nor
+    /// the size of the object is limited, so the estimate is computed only
+    /// when needed
+    pub(super) fn estimated_len<T: StoredObject>(&self, object: &T) -> u64 {
+        if self.config.max_data_dir_bytes.is_none()
+            && self.config.min_free_bytes.is_none()
+            && self.object_limit::<T>().is_none()
+        {
+            return 0;
+        }
+        let mut data = vec![];
+        // The returned length does not include the magic number
+        let _ = object.write_stream(&mut data);
+        data.len() as u64
+    }
+
+    /// [`DiskStorageConfig::max_object_bytes`](super::DiskStorageConfig::max_object_bytes)
+    /// limit of the objects of type `T`, if any
+    pub(super) fn object_limit<T: StoredObject>(&self) -> Option<u64> {
+        T::KIND.and_then(|kind| self.config.max_object_bytes.get(&kind).copied())
+    }
+
+    /// Fails with [`DiskStorageError::ObjectTooLarge`] if `size` of the
+    /// object kept in `filename` exceeds the limit of its kind
+    pub(super) fn check_object_size<T: StoredObject>(
+        &self,
+        filename: &Path,
+        size: u64,
+    ) -> Result<(), DiskStorageError> {
+        match self.object_limit::<T>() {
+            Some(limit) if size > limit => Err(DiskStorageError::ObjectTooLarge {
+                id: filename
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn probe_writable(&self) -> bool {
+        let probe = temp_filename(self.config.data_dir.join(".probe"));
+        let writable = self.fs().create(&probe).is_ok();
+        let _ = self.fs().remove_file(&probe);
+        writable
+    }
+
+    fn dir_usage(&self, kind: ObjectKind) -> Result<CategoryUsage, DiskStorageError> {
+        let mut usage = CategoryUsage::default();
+        if !self.fs().exists(&self.config.kind_dir(kind)) {
+            return Ok(usage);
+        }
+        for path in self.config.object_paths(kind)? {
+            match self.fs().metadata(&path) {
+                Ok(meta) => {
+                    usage.files += 1;
+                    usage.bytes += meta.len;
+                }
+                // File was removed since we have listed the directory
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rgb::prelude::*;
+
+    use super::*;
+    use crate::stashd::storage::{DiskStorageConfig, Store};
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_disk_max_object_bytes() {
+        let data_dir = TempPath::new("disk-max-size");
+
+        let schema = rgb20::schema::schema();
+        let schema_id = schema.schema_id();
+        let mut storage = DiskStorage::new(DiskStorageConfig::new(data_dir.to_path_buf())).unwrap();
+        storage.add_schema(&schema).unwrap();
+        drop(storage);
+
+        let config = DiskStorageConfig::new(data_dir.to_path_buf())
+            .with_max_object_bytes(ObjectKind::Schema, 64)
+            .with_max_object_bytes(ObjectKind::Transition, 1 << 20);
+        let mut storage = DiskStorage::new(config).unwrap();
+        assert!(matches!(
+            storage.schema(&schema_id),
+            Err(DiskStorageError::ObjectTooLarge { size, .. }) if size > 64
+        ));
+        assert!(matches!(
+            storage.add_schema(&schema),
+            Err(DiskStorageError::ObjectTooLarge { .. })
+        ));
+        assert!(!storage.add_transition(&Transition::default()).unwrap());
+        assert!(storage.transition(&Transition::default().node_id()).is_ok());
+    }
+}
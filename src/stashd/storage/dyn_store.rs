@@ -0,0 +1,604 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+
+use super::{AddOutcome, Store, Txn};
+use crate::error::ServiceErrorDomain;
+
+/// [`Store`] trait object with the errors erased to [`ServiceErrorDomain`],
+/// allowing the storage backend to be selected at runtime:
+///
+/// ```ignore
+/// let store: Box<DynStore> = match backend {
+///     "memory" => ErasedStore::boxed(MemoryStorage::new()),
+///     _ => ErasedStore::boxed(DiskStorage::new(config)?),
+/// };
+/// ```
+///
+/// Methods which require `Self: Sized` ([`Store::is_not_found`],
+/// [`Store::schema_for_genesis`], [`Store::contract_ids_by_schema`],
+/// [`Store::transitions_by_contract`] and `Store::for_each_*`) can't be
+/// called on the trait object itself. They are available on `Box<DynStore>`,
+/// which implements [`Store`], though the visitors and the contract queries
+/// use their provided implementations there rather than the ones of the
+/// backend.
+pub type DynStore = dyn Store<Error = ServiceErrorDomain>;
+
+/// Adapts a [`Store`] to [`DynStore`] by converting its errors into
+/// [`ServiceErrorDomain`]. Errors recognized by [`Store::is_not_found`] of
+/// the backend must convert into [`ServiceErrorDomain::NotFound`], as they do
+/// for all the stores of this crate.
+#[derive(Clone, Debug, Default)]
+pub struct ErasedStore<S: Store>(S);
+
+impl<S: Store> ErasedStore<S> {
+    #[inline]
+    pub fn new(inner: S) -> Self { ErasedStore(inner) }
+
+    /// Wraps the store into a [`DynStore`] trait object
+    #[inline]
+    pub fn boxed(inner: S) -> Box<DynStore>
+    where S: 'static {
+        Box::new(ErasedStore(inner))
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S { self.0 }
+
+    #[inline]
+    pub fn as_inner(&self) -> &S { &self.0 }
+}
+
+impl<S: Store> Store for ErasedStore<S> {
+    type Error = ServiceErrorDomain;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, ServiceErrorDomain::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
+        self.0.schema_ids().map_err(S::Error::into)
+    }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> {
+        self.0.schema_count().map_err(S::Error::into)
+    }
+
+    fn schema_ids_with_prefix(&self, prefix: &str) -> Result<Vec<SchemaId>, Self::Error> {
+        self.0
+            .schema_ids_with_prefix(prefix)
+            .map_err(S::Error::into)
+    }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        self.0.schema(id).map_err(S::Error::into)
+    }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.0.has_schema(id).map_err(S::Error::into)
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        self.0.add_schema(schema).map_err(S::Error::into)
+    }
+
+    fn add_schema_sized(&mut self, schema: &Schema) -> Result<AddOutcome, Self::Error> {
+        self.0.add_schema_sized(schema).map_err(S::Error::into)
+    }
+
+    fn replace_schema(&mut self, old: &Schema, new: &Schema) -> Result<bool, Self::Error> {
+        self.0.replace_schema(old, new).map_err(S::Error::into)
+    }
+
+    fn add_schema_if_absent(&mut self, schema: &Schema) -> Result<Option<Schema>, Self::Error> {
+        self.0.add_schema_if_absent(schema).map_err(S::Error::into)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.0.remove_schema(id).map_err(S::Error::into)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.0.clear_schemata().map_err(S::Error::into)
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
+        self.0.contract_ids().map_err(S::Error::into)
+    }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> {
+        self.0.contract_count().map_err(S::Error::into)
+    }
+
+    fn contract_id_set(&self) -> Result<BTreeSet<ContractId>, Self::Error> {
+        self.0.contract_id_set().map_err(S::Error::into)
+    }
+
+    fn contract_ids_with_prefix(&self, prefix: &str) -> Result<Vec<ContractId>, Self::Error> {
+        self.0
+            .contract_ids_with_prefix(prefix)
+            .map_err(S::Error::into)
+    }
+
+    fn contract_ids_by_schema(&self, schema_id: &SchemaId) -> Result<Vec<ContractId>, Self::Error> {
+        self.0
+            .contract_ids_by_schema(schema_id)
+            .map_err(S::Error::into)
+    }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        self.0.genesis(id).map_err(S::Error::into)
+    }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.0.has_genesis(id).map_err(S::Error::into)
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        self.0.add_genesis(genesis).map_err(S::Error::into)
+    }
+
+    fn add_genesis_sized(&mut self, genesis: &Genesis) -> Result<AddOutcome, Self::Error> {
+        self.0.add_genesis_sized(genesis).map_err(S::Error::into)
+    }
+
+    fn replace_genesis(&mut self, old: &Genesis, new: &Genesis) -> Result<bool, Self::Error> {
+        self.0.replace_genesis(old, new).map_err(S::Error::into)
+    }
+
+    fn add_genesis_if_absent(&mut self, genesis: &Genesis) -> Result<Option<Genesis>, Self::Error> {
+        self.0
+            .add_genesis_if_absent(genesis)
+            .map_err(S::Error::into)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.0.remove_genesis(id).map_err(S::Error::into)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.0.clear_geneses().map_err(S::Error::into)
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        self.0.anchor_ids().map_err(S::Error::into)
+    }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> {
+        self.0.anchor_count().map_err(S::Error::into)
+    }
+
+    fn anchor_id_set(&self) -> Result<BTreeSet<AnchorId>, Self::Error> {
+        self.0.anchor_id_set().map_err(S::Error::into)
+    }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        self.0.anchor(id).map_err(S::Error::into)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.0.has_anchor(id).map_err(S::Error::into)
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        self.0.add_anchor(anchor).map_err(S::Error::into)
+    }
+
+    fn add_anchor_sized(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<AddOutcome, Self::Error> {
+        self.0.add_anchor_sized(anchor).map_err(S::Error::into)
+    }
+
+    fn replace_anchor(
+        &mut self,
+        old: &Anchor<MerkleBlock>,
+        new: &Anchor<MerkleBlock>,
+    ) -> Result<bool, Self::Error> {
+        self.0.replace_anchor(old, new).map_err(S::Error::into)
+    }
+
+    fn add_anchor_if_absent(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<Option<Anchor<MerkleBlock>>, Self::Error> {
+        self.0.add_anchor_if_absent(anchor).map_err(S::Error::into)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.0.remove_anchor(id).map_err(S::Error::into)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.0.clear_anchors().map_err(S::Error::into)
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.0.transition_ids().map_err(S::Error::into)
+    }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> {
+        self.0.transition_count().map_err(S::Error::into)
+    }
+
+    fn transition_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        self.0.transition_id_set().map_err(S::Error::into)
+    }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        self.0.transition(id).map_err(S::Error::into)
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.0.has_transition(id).map_err(S::Error::into)
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        self.0.add_transition(transition).map_err(S::Error::into)
+    }
+
+    fn add_transition_sized(&mut self, transition: &Transition) -> Result<AddOutcome, Self::Error> {
+        self.0
+            .add_transition_sized(transition)
+            .map_err(S::Error::into)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        self.0.add_transitions(transitions).map_err(S::Error::into)
+    }
+
+    fn transitions_many(
+        &self,
+        ids: &[NodeId],
+    ) -> Result<BTreeMap<NodeId, Transition>, Self::Error> {
+        self.0.transitions_many(ids).map_err(S::Error::into)
+    }
+
+    fn has_transitions(&self, ids: &[NodeId]) -> Result<BTreeSet<NodeId>, Self::Error> {
+        self.0.has_transitions(ids).map_err(S::Error::into)
+    }
+
+    fn replace_transition(
+        &mut self,
+        old: &Transition,
+        new: &Transition,
+    ) -> Result<bool, Self::Error> {
+        self.0.replace_transition(old, new).map_err(S::Error::into)
+    }
+
+    fn add_transition_if_absent(
+        &mut self,
+        transition: &Transition,
+    ) -> Result<Option<Transition>, Self::Error> {
+        self.0
+            .add_transition_if_absent(transition)
+            .map_err(S::Error::into)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.0.remove_transition(id).map_err(S::Error::into)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.0.clear_transitions().map_err(S::Error::into)
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.0.extension_ids().map_err(S::Error::into)
+    }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> {
+        self.0.extension_count().map_err(S::Error::into)
+    }
+
+    fn extension_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        self.0.extension_id_set().map_err(S::Error::into)
+    }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        self.0.extension(id).map_err(S::Error::into)
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.0.has_extension(id).map_err(S::Error::into)
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        self.0.add_extension(extension).map_err(S::Error::into)
+    }
+
+    fn add_extension_sized(&mut self, extension: &Extension) -> Result<AddOutcome, Self::Error> {
+        self.0
+            .add_extension_sized(extension)
+            .map_err(S::Error::into)
+    }
+
+    fn replace_extension(&mut self, old: &Extension, new: &Extension) -> Result<bool, Self::Error> {
+        self.0.replace_extension(old, new).map_err(S::Error::into)
+    }
+
+    fn add_extension_if_absent(
+        &mut self,
+        extension: &Extension,
+    ) -> Result<Option<Extension>, Self::Error> {
+        self.0
+            .add_extension_if_absent(extension)
+            .map_err(S::Error::into)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.0.remove_extension(id).map_err(S::Error::into)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.0.clear_extensions().map_err(S::Error::into)
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> { self.0.clear().map_err(S::Error::into) }
+
+    fn flush(&mut self) -> Result<(), Self::Error> { self.0.flush().map_err(S::Error::into) }
+
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> {
+        self.0.apply_txn(txn).map_err(S::Error::into)
+    }
+}
+
+impl Store for Box<DynStore> {
+    type Error = ServiceErrorDomain;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, ServiceErrorDomain::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> { (**self).schema_ids() }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> { (**self).schema_count() }
+
+    fn schema_ids_with_prefix(&self, prefix: &str) -> Result<Vec<SchemaId>, Self::Error> {
+        (**self).schema_ids_with_prefix(prefix)
+    }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> { (**self).schema(id) }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> { (**self).has_schema(id) }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        (**self).add_schema(schema)
+    }
+
+    fn add_schema_sized(&mut self, schema: &Schema) -> Result<AddOutcome, Self::Error> {
+        (**self).add_schema_sized(schema)
+    }
+
+    fn replace_schema(&mut self, old: &Schema, new: &Schema) -> Result<bool, Self::Error> {
+        (**self).replace_schema(old, new)
+    }
+
+    fn add_schema_if_absent(&mut self, schema: &Schema) -> Result<Option<Schema>, Self::Error> {
+        (**self).add_schema_if_absent(schema)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        (**self).remove_schema(id)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> { (**self).clear_schemata() }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> { (**self).contract_ids() }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> { (**self).contract_count() }
+
+    fn contract_id_set(&self) -> Result<BTreeSet<ContractId>, Self::Error> {
+        (**self).contract_id_set()
+    }
+
+    fn contract_ids_with_prefix(&self, prefix: &str) -> Result<Vec<ContractId>, Self::Error> {
+        (**self).contract_ids_with_prefix(prefix)
+    }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> { (**self).genesis(id) }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> { (**self).has_genesis(id) }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        (**self).add_genesis(genesis)
+    }
+
+    fn add_genesis_sized(&mut self, genesis: &Genesis) -> Result<AddOutcome, Self::Error> {
+        (**self).add_genesis_sized(genesis)
+    }
+
+    fn replace_genesis(&mut self, old: &Genesis, new: &Genesis) -> Result<bool, Self::Error> {
+        (**self).replace_genesis(old, new)
+    }
+
+    fn add_genesis_if_absent(&mut self, genesis: &Genesis) -> Result<Option<Genesis>, Self::Error> {
+        (**self).add_genesis_if_absent(genesis)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        (**self).remove_genesis(id)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> { (**self).clear_geneses() }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> { (**self).anchor_ids() }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> { (**self).anchor_count() }
+
+    fn anchor_id_set(&self) -> Result<BTreeSet<AnchorId>, Self::Error> { (**self).anchor_id_set() }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        (**self).anchor(id)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> { (**self).has_anchor(id) }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        (**self).add_anchor(anchor)
+    }
+
+    fn add_anchor_sized(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<AddOutcome, Self::Error> {
+        (**self).add_anchor_sized(anchor)
+    }
+
+    fn replace_anchor(
+        &mut self,
+        old: &Anchor<MerkleBlock>,
+        new: &Anchor<MerkleBlock>,
+    ) -> Result<bool, Self::Error> {
+        (**self).replace_anchor(old, new)
+    }
+
+    fn add_anchor_if_absent(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<Option<Anchor<MerkleBlock>>, Self::Error> {
+        (**self).add_anchor_if_absent(anchor)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        (**self).remove_anchor(id)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> { (**self).clear_anchors() }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> { (**self).transition_ids() }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> { (**self).transition_count() }
+
+    fn transition_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        (**self).transition_id_set()
+    }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> { (**self).transition(id) }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        (**self).has_transition(id)
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        (**self).add_transition(transition)
+    }
+
+    fn add_transition_sized(&mut self, transition: &Transition) -> Result<AddOutcome, Self::Error> {
+        (**self).add_transition_sized(transition)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        (**self).add_transitions(transitions)
+    }
+
+    fn transitions_many(
+        &self,
+        ids: &[NodeId],
+    ) -> Result<BTreeMap<NodeId, Transition>, Self::Error> {
+        (**self).transitions_many(ids)
+    }
+
+    fn has_transitions(&self, ids: &[NodeId]) -> Result<BTreeSet<NodeId>, Self::Error> {
+        (**self).has_transitions(ids)
+    }
+
+    fn replace_transition(
+        &mut self,
+        old: &Transition,
+        new: &Transition,
+    ) -> Result<bool, Self::Error> {
+        (**self).replace_transition(old, new)
+    }
+
+    fn add_transition_if_absent(
+        &mut self,
+        transition: &Transition,
+    ) -> Result<Option<Transition>, Self::Error> {
+        (**self).add_transition_if_absent(transition)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        (**self).remove_transition(id)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> { (**self).clear_transitions() }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> { (**self).extension_ids() }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> { (**self).extension_count() }
+
+    fn extension_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        (**self).extension_id_set()
+    }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> { (**self).extension(id) }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> { (**self).has_extension(id) }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        (**self).add_extension(extension)
+    }
+
+    fn add_extension_sized(&mut self, extension: &Extension) -> Result<AddOutcome, Self::Error> {
+        (**self).add_extension_sized(extension)
+    }
+
+    fn replace_extension(&mut self, old: &Extension, new: &Extension) -> Result<bool, Self::Error> {
+        (**self).replace_extension(old, new)
+    }
+
+    fn add_extension_if_absent(
+        &mut self,
+        extension: &Extension,
+    ) -> Result<Option<Extension>, Self::Error> {
+        (**self).add_extension_if_absent(extension)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        (**self).remove_extension(id)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> { (**self).clear_extensions() }
+
+    fn clear(&mut self) -> Result<(), Self::Error> { (**self).clear() }
+
+    fn flush(&mut self) -> Result<(), Self::Error> { (**self).flush() }
+
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> { (**self).apply_txn(txn) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stashd::storage::MemoryStorage;
+
+    #[test]
+    fn test_boxed_store() {
+        let mut store = ErasedStore::boxed(MemoryStorage::new());
+        let genesis = Genesis::default();
+        let contract_id = genesis.contract_id();
+        assert_eq!(store.add_genesis(&genesis).ok(), Some(false));
+        assert_eq!(store.has_genesis(&contract_id).ok(), Some(true));
+        assert_eq!(store.contract_ids().ok(), Some(vec![contract_id]));
+        assert!(matches!(
+            store.schema_for_genesis(&contract_id),
+            Err(ServiceErrorDomain::Schema(_))
+        ));
+        assert_eq!(store.remove_genesis(&contract_id).ok(), Some(true));
+        let err = store.genesis(&contract_id).unwrap_err();
+        assert!(<Box<DynStore>>::is_not_found(&err));
+    }
+}
@@ -0,0 +1,708 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::fmt::Debug;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{fs, thread};
+
+use fs2::FileExt;
+
+use crate::util::file::{file, sync_dir, FileMode};
+
+/// Entry of a directory listed with [`FileSystem::read_dir`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct DirEntry {
+    /// Full path of the entry, i.e. the listed directory joined with the
+    /// entry name
+    pub path: PathBuf,
+    /// Whether the entry is a directory or a symlink to a directory
+    pub is_dir: bool,
+}
+
+/// Information about a file or directory returned by
+/// [`FileSystem::metadata`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct Metadata {
+    /// Size of the file data in bytes
+    pub len: u64,
+    pub is_dir: bool,
+    /// Time of the last modification, if supported by the file system
+    pub modified: Option<SystemTime>,
+}
+
+/// Kind of the [`FileSystem`] operation which failed, reported by
+/// [`super::DiskStorageError::Io`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum IoOp {
+    #[display("stat")]
+    Metadata,
+
+    #[display("create directory")]
+    CreateDir,
+
+    #[display("list")]
+    ReadDir,
+
+    #[display("read")]
+    Read,
+
+    #[display("write")]
+    Write,
+
+    #[display("append")]
+    Append,
+
+    #[display("rename")]
+    Rename,
+
+    #[display("link")]
+    Link,
+
+    #[display("remove")]
+    Remove,
+
+    #[display("sync")]
+    Sync,
+
+    #[display("set permissions of")]
+    SetPermissions,
+
+    #[display("lock")]
+    Lock,
+
+    #[display("query free space of")]
+    AvailableSpace,
+
+    #[display("resolve")]
+    Canonicalize,
+
+    /// I/O error which did not come from a file system operation
+    #[display("access")]
+    Other,
+}
+
+/// Payload of the errors returned by [`ContextFileSystem`], recovered into
+/// [`super::DiskStorageError::Io`]
+#[derive(Debug, Display, Error)]
+#[display("{op} {path:?}: {source}")]
+pub(super) struct IoContext {
+    pub op: IoOp,
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+impl IoContext {
+    /// Wraps the error into one of the same kind carrying the operation
+    /// and the path; errors which already carry them are left intact
+    fn wrap(op: IoOp, path: &Path, err: io::Error) -> io::Error {
+        if err.get_ref().is_some_and(|inner| inner.is::<IoContext>()) {
+            return err;
+        }
+        io::Error::new(err.kind(), IoContext {
+            op,
+            path: path.to_owned(),
+            source: err,
+        })
+    }
+}
+
+/// Guard of the lock taken with [`FileSystem::lock`]; the lock is released
+/// when the guard is dropped
+pub type LockGuard = Box<dyn Debug + Send + Sync>;
+
+/// File system operations used by [`super::DiskStorage`], allowing to keep
+/// the stored files outside of the local disk (like in memory or in a remote
+/// object storage) or to simulate failures in tests.
+///
+/// Paths are passed exactly as composed by [`super::DiskStorageConfig`], so
+/// they start with its data directory. Missing files must be reported with
+/// [`io::ErrorKind::NotFound`] errors. Atomicity of the writes relies on
+/// [`FileSystem::rename`] replacing the target file atomically; durability
+/// relies on [`FileSystem::sync_file`] and [`FileSystem::sync_dir`].
+pub trait FileSystem: Debug + Send + Sync {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    #[inline]
+    fn exists(&self, path: &Path) -> bool { self.metadata(path).is_ok() }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn read_dir(&self, path: &Path) -> io::Result<Box<dyn Iterator<Item = io::Result<DirEntry>>>>;
+
+    /// Opens the file for reading
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead>>;
+
+    /// Creates the file, truncating it if it already exists, and opens it
+    /// for writing. Data are not guaranteed to be written before the writer
+    /// is flushed.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>>;
+
+    /// Appends data to the end of the file, creating it if needed
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Makes `to` a hard link of the existing file `from`, or its copy if the
+    /// file system can't link them (like across devices). Fails if `to`
+    /// already exists. The default implementation copies the data.
+    fn link_or_copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.exists(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "link target already exists",
+            ));
+        }
+        self.write(to, &self.read(from)?)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes the empty directory. File systems without real directories
+    /// (like object storages) may ignore the call, which the default
+    /// implementation does.
+    fn remove_dir(&self, _path: &Path) -> io::Result<()> { Ok(()) }
+
+    /// Flushes data of the file to the persistent storage
+    fn sync_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Flushes directory entries (like newly created or renamed files) to
+    /// the persistent storage
+    fn sync_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Sets Unix permission bits of the file or directory; file systems
+    /// without permissions may ignore the call
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// Takes an exclusive lock on the file, creating it if needed, which is
+    /// held until the returned guard is dropped. Lock (either exclusive or
+    /// shared) held by somebody else is reported with
+    /// [`io::ErrorKind::WouldBlock`] error.
+    fn lock(&self, path: &Path) -> io::Result<LockGuard>;
+
+    /// Takes a shared lock on the existing file, which is held until the
+    /// returned guard is dropped. Any number of shared locks may be held at
+    /// once, but not together with an exclusive one, which is reported with
+    /// [`io::ErrorKind::WouldBlock`] error. File systems without shared locks
+    /// may return a guard locking nothing, which the default implementation
+    /// does.
+    fn lock_shared(&self, _path: &Path) -> io::Result<LockGuard> { Ok(Box::new(())) }
+
+    /// Returns free space available to the process on the file system
+    /// keeping the path, or `None` if the file system can't tell
+    fn available_space(&self, _path: &Path) -> io::Result<Option<u64>> { Ok(None) }
+
+    /// Resolves symlinks in the path, returning its absolute form. Fails
+    /// with [`io::ErrorKind::NotFound`] if the path or the target of one of
+    /// the symlinks does not exist. File systems without symlinks may return
+    /// the path as is, which the default implementation does.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> { Ok(path.to_owned()) }
+
+    /// Tells whether the path is a symlink which target does not exist
+    fn is_dangling_symlink(&self, _path: &Path) -> bool { false }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let mut data = String::new();
+        self.open(path)?.read_to_string(&mut data)?;
+        Ok(data)
+    }
+
+    /// Replaces content of the file with the data, creating the file if
+    /// needed
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut writer = self.create(path)?;
+        writer.write_all(data)?;
+        writer.flush()
+    }
+}
+
+/// Local file system accessed with [`std::fs`]. Files are memory-mapped for
+/// reading if `mmap` feature is enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct StdFileSystem;
+
+/// Lock taken with [`StdFileSystem::lock`], released on drop
+#[derive(Debug)]
+struct FileLock(fs::File);
+
+impl Drop for FileLock {
+    fn drop(&mut self) { let _ = self.0.unlock(); }
+}
+
+/// Reports contended lock with [`io::ErrorKind::WouldBlock`] error, as
+/// required by [`FileSystem::lock`]
+fn lock_error(err: io::Error) -> io::Error {
+    if err.kind() == fs2::lock_contended_error().kind() {
+        io::Error::new(io::ErrorKind::WouldBlock, err)
+    } else {
+        err
+    }
+}
+
+impl FileSystem for StdFileSystem {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let meta = fs::metadata(path)?;
+        Ok(Metadata {
+            len: meta.len(),
+            is_dir: meta.is_dir(),
+            modified: meta.modified().ok(),
+        })
+    }
+
+    #[inline]
+    fn exists(&self, path: &Path) -> bool { path.exists() }
+
+    #[inline]
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> { fs::create_dir_all(path) }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Box<dyn Iterator<Item = io::Result<DirEntry>>>> {
+        Ok(Box::new(fs::read_dir(path)?.map(|entry| {
+            let path = entry?.path();
+            // Follows symlinks, unlike `DirEntry::file_type`
+            Ok(DirEntry {
+                is_dir: path.is_dir(),
+                path,
+            })
+        })))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        let file = file(path, FileMode::Read)?;
+        #[cfg(feature = "mmap")]
+        {
+            // Safety: files are replaced by renaming new files into their
+            // place and never modified in place, so the mapped data can't
+            // change while being read
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            Ok(Box::new(io::Cursor::new(map)))
+        }
+        #[cfg(not(feature = "mmap"))]
+        Ok(Box::new(io::BufReader::new(file)))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(io::BufWriter::new(file(path, FileMode::Create)?)))
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?
+            .write_all(data)
+    }
+
+    #[inline]
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> { fs::rename(from, to) }
+
+    fn link_or_copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match fs::hard_link(from, to) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+                fs::copy(from, to).map(|_| ())
+            }
+            res => res,
+        }
+    }
+
+    #[inline]
+    fn remove_file(&self, path: &Path) -> io::Result<()> { fs::remove_file(path) }
+
+    #[inline]
+    fn remove_dir(&self, path: &Path) -> io::Result<()> { fs::remove_dir(path) }
+
+    fn sync_file(&self, path: &Path) -> io::Result<()> { file(path, FileMode::Write)?.sync_all() }
+
+    #[inline]
+    fn sync_dir(&self, path: &Path) -> io::Result<()> { sync_dir(path) }
+
+    #[cfg(unix)]
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(unix))]
+    #[inline]
+    fn set_permissions(&self, _path: &Path, _mode: u32) -> io::Result<()> { Ok(()) }
+
+    fn lock(&self, path: &Path) -> io::Result<LockGuard> {
+        let lock = file(path, FileMode::Create)?;
+        lock.try_lock_exclusive().map_err(lock_error)?;
+        Ok(Box::new(FileLock(lock)))
+    }
+
+    fn lock_shared(&self, path: &Path) -> io::Result<LockGuard> {
+        let lock = file(path, FileMode::Read)?;
+        // Newer std has an inherent method with the same name
+        FileExt::try_lock_shared(&lock).map_err(lock_error)?;
+        Ok(Box::new(FileLock(lock)))
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        fs2::available_space(path).map(Some)
+    }
+
+    #[inline]
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> { fs::canonicalize(path) }
+
+    fn is_dangling_symlink(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+            && matches!(fs::metadata(path), Err(err) if err.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+/// Policy of retrying file system operations failed with transient errors,
+/// which happen on network file systems (like NFS or SMB); see
+/// [`super::DiskStorageConfig::retry`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct RetryPolicy {
+    /// Number of attempts made before the error is reported, including the
+    /// first one
+    pub max_attempts: u32,
+
+    /// Delay before the first retry, doubled before each next one
+    pub base_delay: Duration,
+
+    /// Kinds of the errors which are retried; other errors are reported
+    /// immediately
+    pub transient: Vec<io::ErrorKind>,
+}
+
+impl RetryPolicy {
+    /// Constructs policy retrying [`io::ErrorKind::Interrupted`],
+    /// [`io::ErrorKind::WouldBlock`] and [`io::ErrorKind::TimedOut`] errors
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts,
+            base_delay,
+            transient: vec![
+                io::ErrorKind::Interrupted,
+                io::ErrorKind::WouldBlock,
+                io::ErrorKind::TimedOut,
+            ],
+        }
+    }
+
+    /// Sets [`RetryPolicy::transient`]
+    #[inline]
+    pub fn with_transient(mut self, transient: Vec<io::ErrorKind>) -> Self {
+        self.transient = transient;
+        self
+    }
+
+    /// Runs the operation until it succeeds, fails with non-transient error
+    /// or the attempts are exhausted, returning the last error in the latter
+    /// case
+    pub fn retry<T>(
+        &self,
+        op: &str,
+        path: &Path,
+        mut f: impl FnMut() -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut delay = self.base_delay;
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(err) if attempt < self.max_attempts && self.transient.contains(&err.kind()) => {
+                    debug!(
+                        "Retrying {} of {} in {:?} after attempt {} failed: {}",
+                        op,
+                        path.display(),
+                        delay,
+                        attempt,
+                        err
+                    );
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// File system retrying operations of the wrapped one according to the
+/// [`RetryPolicy`]. Appends are not retried since a failed append may have
+/// written part of the data, and neither are locks, so lock contention is
+/// reported immediately.
+#[derive(Debug)]
+pub(super) struct RetryFileSystem {
+    inner: Arc<dyn FileSystem>,
+    policy: RetryPolicy,
+}
+
+impl RetryFileSystem {
+    pub fn new(inner: Arc<dyn FileSystem>, policy: RetryPolicy) -> Self {
+        RetryFileSystem { inner, policy }
+    }
+}
+
+impl FileSystem for RetryFileSystem {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.policy
+            .retry("metadata", path, || self.inner.metadata(path))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.policy
+            .retry("create_dir_all", path, || self.inner.create_dir_all(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Box<dyn Iterator<Item = io::Result<DirEntry>>>> {
+        self.policy
+            .retry("read_dir", path, || self.inner.read_dir(path))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        self.policy.retry("open", path, || self.inner.open(path))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        self.policy
+            .retry("create", path, || self.inner.create(path))
+    }
+
+    #[inline]
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> { self.inner.append(path, data) }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.policy
+            .retry("rename", from, || self.inner.rename(from, to))
+    }
+
+    fn link_or_copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.policy
+            .retry("link_or_copy", from, || self.inner.link_or_copy(from, to))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.policy
+            .retry("remove_file", path, || self.inner.remove_file(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.policy
+            .retry("remove_dir", path, || self.inner.remove_dir(path))
+    }
+
+    fn sync_file(&self, path: &Path) -> io::Result<()> {
+        self.policy
+            .retry("sync_file", path, || self.inner.sync_file(path))
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.policy
+            .retry("sync_dir", path, || self.inner.sync_dir(path))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.policy.retry("set_permissions", path, || {
+            self.inner.set_permissions(path, mode)
+        })
+    }
+
+    #[inline]
+    fn lock(&self, path: &Path) -> io::Result<LockGuard> { self.inner.lock(path) }
+
+    #[inline]
+    fn lock_shared(&self, path: &Path) -> io::Result<LockGuard> { self.inner.lock_shared(path) }
+
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        self.policy
+            .retry("available_space", path, || self.inner.available_space(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.policy
+            .retry("canonicalize", path, || self.inner.canonicalize(path))
+    }
+
+    #[inline]
+    fn is_dangling_symlink(&self, path: &Path) -> bool { self.inner.is_dangling_symlink(path) }
+
+    // Whole reads and writes are retried, so the errors happening after the
+    // file was opened are retried as well
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.policy.retry("read", path, || self.inner.read(path))
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.policy
+            .retry("read", path, || self.inner.read_to_string(path))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.policy
+            .retry("write", path, || self.inner.write(path, data))
+    }
+}
+
+/// File system attaching the failed operation and its path to the errors of
+/// the wrapped one, so they can be reported by [`super::DiskStorageError::Io`].
+/// Kinds of the errors are preserved. Errors happening while reading from or
+/// writing into the opened files are not annotated.
+#[derive(Debug)]
+pub(super) struct ContextFileSystem {
+    inner: Arc<dyn FileSystem>,
+}
+
+impl ContextFileSystem {
+    pub fn new(inner: Arc<dyn FileSystem>) -> Self { ContextFileSystem { inner } }
+}
+
+impl FileSystem for ContextFileSystem {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.inner
+            .metadata(path)
+            .map_err(|err| IoContext::wrap(IoOp::Metadata, path, err))
+    }
+
+    #[inline]
+    fn exists(&self, path: &Path) -> bool { self.inner.exists(path) }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner
+            .create_dir_all(path)
+            .map_err(|err| IoContext::wrap(IoOp::CreateDir, path, err))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Box<dyn Iterator<Item = io::Result<DirEntry>>>> {
+        let dir = path.to_owned();
+        let entries = self
+            .inner
+            .read_dir(path)
+            .map_err(|err| IoContext::wrap(IoOp::ReadDir, path, err))?;
+        Ok(Box::new(entries.map(move |entry| {
+            entry.map_err(|err| IoContext::wrap(IoOp::ReadDir, &dir, err))
+        })))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        self.inner
+            .open(path)
+            .map_err(|err| IoContext::wrap(IoOp::Read, path, err))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        self.inner
+            .create(path)
+            .map_err(|err| IoContext::wrap(IoOp::Write, path, err))
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.inner
+            .append(path, data)
+            .map_err(|err| IoContext::wrap(IoOp::Append, path, err))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner
+            .rename(from, to)
+            .map_err(|err| IoContext::wrap(IoOp::Rename, from, err))
+    }
+
+    fn link_or_copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner
+            .link_or_copy(from, to)
+            .map_err(|err| IoContext::wrap(IoOp::Link, from, err))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner
+            .remove_file(path)
+            .map_err(|err| IoContext::wrap(IoOp::Remove, path, err))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner
+            .remove_dir(path)
+            .map_err(|err| IoContext::wrap(IoOp::Remove, path, err))
+    }
+
+    fn sync_file(&self, path: &Path) -> io::Result<()> {
+        self.inner
+            .sync_file(path)
+            .map_err(|err| IoContext::wrap(IoOp::Sync, path, err))
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner
+            .sync_dir(path)
+            .map_err(|err| IoContext::wrap(IoOp::Sync, path, err))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.inner
+            .set_permissions(path, mode)
+            .map_err(|err| IoContext::wrap(IoOp::SetPermissions, path, err))
+    }
+
+    fn lock(&self, path: &Path) -> io::Result<LockGuard> {
+        self.inner
+            .lock(path)
+            .map_err(|err| IoContext::wrap(IoOp::Lock, path, err))
+    }
+
+    fn lock_shared(&self, path: &Path) -> io::Result<LockGuard> {
+        self.inner
+            .lock_shared(path)
+            .map_err(|err| IoContext::wrap(IoOp::Lock, path, err))
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        self.inner
+            .available_space(path)
+            .map_err(|err| IoContext::wrap(IoOp::AvailableSpace, path, err))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner
+            .canonicalize(path)
+            .map_err(|err| IoContext::wrap(IoOp::Canonicalize, path, err))
+    }
+
+    #[inline]
+    fn is_dangling_symlink(&self, path: &Path) -> bool { self.inner.is_dangling_symlink(path) }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.inner
+            .read(path)
+            .map_err(|err| IoContext::wrap(IoOp::Read, path, err))
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.inner
+            .read_to_string(path)
+            .map_err(|err| IoContext::wrap(IoOp::Read, path, err))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.inner
+            .write(path, data)
+            .map_err(|err| IoContext::wrap(IoOp::Write, path, err))
+    }
+}
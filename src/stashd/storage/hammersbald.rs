@@ -10,16 +10,19 @@
 // You should have received a copy of the MIT License
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 use std::{fs, io};
 
+use amplify::Wrapper;
+use bitcoin::hashes::{sha256, Hash};
 use bp::dbc::{Anchor, AnchorId};
 use commit_verify::lnpbp4::MerkleBlock;
 use hammersbald::{persistent, HammersbaldAPI};
 use rgb::prelude::*;
-use strict_encoding::{strict_serialize, StrictDecode};
+use strict_encoding::{StrictDecode, StrictEncode};
 
-use super::store::Store;
+use super::store::{ConsignmentId, ConsignmentStore, Store};
 use crate::error::{BootstrapError, ServiceErrorDomain};
 
 #[derive(Debug, Display, Error, From)]
@@ -47,7 +50,12 @@ pub enum HammersbaldError {
 }
 
 impl From<HammersbaldError> for ServiceErrorDomain {
-    fn from(err: HammersbaldError) -> Self { ServiceErrorDomain::Storage(err.to_string()) }
+    fn from(err: HammersbaldError) -> Self {
+        match err {
+            HammersbaldError::DataNotFound => ServiceErrorDomain::NotFound,
+            err => ServiceErrorDomain::Storage(err.to_string()),
+        }
+    }
 }
 
 impl From<HammersbaldError> for BootstrapError {
@@ -79,6 +87,11 @@ impl HammersbaldConfig {
 
     #[inline]
     pub fn extensions_db(&self) -> PathBuf { self.data_dir.join("hammersbald").join("extensions") }
+
+    #[inline]
+    pub fn consignments_db(&self) -> PathBuf {
+        self.data_dir.join("hammersbald").join("consignments")
+    }
 }
 
 /// Keeps all Hammersbald RGB contract data, stash etc
@@ -88,6 +101,7 @@ pub struct HammersbaldStorage {
     anchors_db: Box<dyn HammersbaldAPI>,
     transitions_db: Box<dyn HammersbaldAPI>,
     extensions_db: Box<dyn HammersbaldAPI>,
+    consignments_db: Box<dyn HammersbaldAPI>,
 }
 
 impl HammersbaldStorage {
@@ -143,198 +157,224 @@ impl HammersbaldStorage {
             config.bucket_fill_targes,
         )?;
 
+        let consignments_db = persistent(
+            config
+                .consignments_db()
+                .to_str()
+                .ok_or(HammersbaldError::DataDirNotFound)?,
+            config.cached_pages,
+            config.bucket_fill_targes,
+        )?;
+
         Ok(Self {
             schemata_db,
             geneses_db,
             anchors_db,
             transitions_db,
             extensions_db,
+            consignments_db,
         })
     }
+
+    /// Lists keys of the objects present in the database. Hammersbald
+    /// iterates over all the data ever written into the database, including
+    /// overwritten and forgotten entries, so the keys are deduplicated and
+    /// checked against the live index.
+    fn keys(db: &dyn HammersbaldAPI) -> Result<Vec<Vec<u8>>, HammersbaldError> {
+        let mut seen = BTreeSet::new();
+        let mut keys = vec![];
+        for (_, key, _) in db.iter() {
+            if seen.insert(key.clone()) && db.get_keyed(&key)?.is_some() {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn ids<I: StrictDecode>(db: &dyn HammersbaldAPI) -> Result<Vec<I>, HammersbaldError> {
+        Self::keys(db)?
+            .into_iter()
+            .map(|key| Ok(I::strict_deserialize(key)?))
+            .collect()
+    }
+
+    fn get<T: StrictDecode>(db: &dyn HammersbaldAPI, key: &[u8]) -> Result<T, HammersbaldError> {
+        let (_, value) = db.get_keyed(key)?.ok_or(HammersbaldError::DataNotFound)?;
+        Ok(T::strict_deserialize(value)?)
+    }
+
+    fn has(db: &dyn HammersbaldAPI, key: &[u8]) -> Result<bool, HammersbaldError> {
+        Ok(db.get_keyed(key)?.is_some())
+    }
+
+    /// Writes the object, returning whether it was present before. Each
+    /// write is completed with a batch, making it persistent.
+    fn insert(
+        db: &mut dyn HammersbaldAPI,
+        key: &[u8],
+        object: &impl StrictEncode,
+    ) -> Result<bool, HammersbaldError> {
+        let existed = Self::has(db, key)?;
+        db.put_keyed(key, &object.strict_serialize()?)?;
+        db.batch()?;
+        Ok(existed)
+    }
+
+    fn remove(db: &mut dyn HammersbaldAPI, key: &[u8]) -> Result<bool, HammersbaldError> {
+        if !Self::has(db, key)? {
+            return Ok(false);
+        }
+        db.forget(key)?;
+        db.batch()?;
+        Ok(true)
+    }
 }
 
 impl Store for HammersbaldStorage {
     type Error = HammersbaldError;
 
-    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
-        let mut result = vec![];
-        for item in self.schemata_db.iter() {
-            result.push(SchemaId::strict_decode(&item.1[..])?);
-        }
-        Ok(result)
-    }
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, HammersbaldError::DataNotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> { Self::ids(&*self.schemata_db) }
 
     fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self
-            .schemata_db
-            .get_keyed(&key[..])?
-            .ok_or(HammersbaldError::DataNotFound)?;
-        let schema = Schema::strict_decode(&value.1[..])?;
-        Ok(schema)
+        Self::get(&*self.schemata_db, &id.strict_serialize()?)
     }
 
     fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self.schemata_db.get_keyed(&key[..])?;
-        match value {
-            Some(_) => return Ok(true),
-            None => return Ok(false),
-        }
+        Self::has(&*self.schemata_db, &id.strict_serialize()?)
     }
 
     fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
-        let schema_id = schema.schema_id();
-        let key = strict_serialize(&schema_id)?;
-        let value = strict_serialize(schema)?;
-        self.schemata_db.put_keyed(&key[..], &value[..])?;
-        Ok(true)
+        Self::insert(
+            &mut *self.schemata_db,
+            &schema.schema_id().strict_serialize()?,
+            schema,
+        )
     }
 
     fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        self.schemata_db.forget(&key[..])?;
-        Ok(true)
+        Self::remove(&mut *self.schemata_db, &id.strict_serialize()?)
     }
 
-    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
-        let mut result = vec![];
-        for item in self.geneses_db.iter() {
-            result.push(ContractId::strict_decode(&item.1[..])?);
-        }
-        Ok(result)
-    }
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> { Self::ids(&*self.geneses_db) }
 
     fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self
-            .geneses_db
-            .get_keyed(&key[..])?
-            .ok_or(HammersbaldError::DataNotFound)?;
-        let genesis = Genesis::strict_decode(&value.1[..])?;
-        Ok(genesis)
+        Self::get(&*self.geneses_db, &id.strict_serialize()?)
     }
 
     fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self.geneses_db.get_keyed(&key[..])?;
-        match value {
-            Some(_) => return Ok(true),
-            None => return Ok(false),
-        }
+        Self::has(&*self.geneses_db, &id.strict_serialize()?)
     }
 
     fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
-        let contract_id = genesis.contract_id();
-        let key = strict_serialize(&contract_id)?;
-        let value = strict_serialize(genesis)?;
-        self.geneses_db.put_keyed(&key[..], &value[..])?;
-        Ok(true)
+        Self::insert(
+            &mut *self.geneses_db,
+            &genesis.contract_id().strict_serialize()?,
+            genesis,
+        )
     }
 
     fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        self.geneses_db.forget(&key[..])?;
-        Ok(true)
+        Self::remove(&mut *self.geneses_db, &id.strict_serialize()?)
     }
 
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> { Self::ids(&*self.anchors_db) }
+
     fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self
-            .anchors_db
-            .get_keyed(&key[..])?
-            .ok_or(HammersbaldError::DataNotFound)?;
-        let anchor = Anchor::strict_decode(&value.1[..])?;
-        Ok(anchor)
+        Self::get(&*self.anchors_db, &id.strict_serialize()?)
     }
 
     fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self.anchors_db.get_keyed(&key[..])?;
-        match value {
-            Some(_) => Ok(true),
-            None => Ok(false),
-        }
+        Self::has(&*self.anchors_db, &id.strict_serialize()?)
     }
 
     fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
-        let anchor_id = anchor.anchor_id();
-        let key = strict_serialize(&anchor_id)?;
-        let value = strict_serialize(anchor)?;
-        self.anchors_db.put_keyed(&key[..], &value[..])?;
-        Ok(true)
+        Self::insert(
+            &mut *self.anchors_db,
+            &anchor.anchor_id().strict_serialize()?,
+            anchor,
+        )
     }
 
     fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        self.anchors_db.forget(&key[..])?;
-        Ok(true)
+        Self::remove(&mut *self.anchors_db, &id.strict_serialize()?)
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        Self::ids(&*self.transitions_db)
     }
 
     fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self
-            .transitions_db
-            .get_keyed(&key[..])?
-            .ok_or(HammersbaldError::DataNotFound)?;
-        let transition = Transition::strict_decode(&value.1[..])?;
-        Ok(transition)
+        Self::get(&*self.transitions_db, &id.strict_serialize()?)
     }
 
     fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self.transitions_db.get_keyed(&key[..])?;
-        match value {
-            Some(_) => Ok(true),
-            None => Ok(false),
-        }
+        Self::has(&*self.transitions_db, &id.strict_serialize()?)
     }
 
     fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
-        let node_id = transition.node_id();
-        let key = strict_serialize(&node_id)?;
-        let value = strict_serialize(transition)?;
-        self.transitions_db.put_keyed(&key[..], &value[..])?;
-        Ok(true)
+        Self::insert(
+            &mut *self.transitions_db,
+            &transition.node_id().strict_serialize()?,
+            transition,
+        )
     }
 
     fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        self.transitions_db.forget(&key[..])?;
-        Ok(true)
+        Self::remove(&mut *self.transitions_db, &id.strict_serialize()?)
     }
 
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> { Self::ids(&*self.extensions_db) }
+
     fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self
-            .extensions_db
-            .get_keyed(&key[..])?
-            .ok_or(HammersbaldError::DataNotFound)?;
-        let extension = Extension::strict_decode(&value.1[..])?;
-        Ok(extension)
+        Self::get(&*self.extensions_db, &id.strict_serialize()?)
     }
 
     fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        let value = self.extensions_db.get_keyed(&key[..])?;
-        match value {
-            Some(_) => Ok(true),
-            None => Ok(false),
-        }
+        Self::has(&*self.extensions_db, &id.strict_serialize()?)
     }
 
     fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
-        let node_id = extension.node_id();
-        let key = strict_serialize(&node_id)?;
-        let value = strict_serialize(extension)?;
-        self.extensions_db.put_keyed(&key[..], &value[..])?;
-        Ok(true)
+        Self::insert(
+            &mut *self.extensions_db,
+            &extension.node_id().strict_serialize()?,
+            extension,
+        )
     }
 
     fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
-        let key = strict_serialize(id)?;
-        self.extensions_db.forget(&key[..])?;
-        Ok(true)
+        Self::remove(&mut *self.extensions_db, &id.strict_serialize()?)
+    }
+}
+
+impl ConsignmentStore for HammersbaldStorage {
+    fn consignment_ids(&self) -> Result<Vec<ConsignmentId>, Self::Error> {
+        Self::keys(&*self.consignments_db)?
+            .into_iter()
+            .map(|key| {
+                sha256::Hash::from_slice(&key)
+                    .map(ConsignmentId::from_inner)
+                    .map_err(HammersbaldError::from)
+            })
+            .collect()
+    }
+
+    fn consignment(&self, id: &ConsignmentId) -> Result<Consignment, Self::Error> {
+        Self::get(&*self.consignments_db, &id[..])
+    }
+
+    fn has_consignment(&self, id: &ConsignmentId) -> Result<bool, Self::Error> {
+        Self::has(&*self.consignments_db, &id[..])
+    }
+
+    fn add_consignment(&mut self, consignment: &Consignment) -> Result<bool, Self::Error> {
+        let id = ConsignmentId::from_consignment(consignment);
+        Self::insert(&mut *self.consignments_db, &id[..], consignment)
+    }
+
+    fn remove_consignment(&mut self, id: &ConsignmentId) -> Result<bool, Self::Error> {
+        Self::remove(&mut *self.consignments_db, &id[..])
     }
 }
 
@@ -343,6 +383,8 @@ mod test {
     use std::env;
 
     use super::*;
+    use crate::stashd::storage::assert_store_conformance;
+    use crate::util::TempPath;
 
     // TODO #165: Add testing for Anchors when easy anchor sample
     // are availble
@@ -370,28 +412,46 @@ mod test {
 
         let mut database = HammersbaldStorage::new(config).unwrap();
 
-        assert!(database.add_schema(&schema).unwrap());
+        assert!(!database.add_schema(&schema).unwrap());
         assert!(database.has_schema(&schema_id).unwrap());
         assert_eq!(schema, database.schema(&schema_id).unwrap());
         assert_eq!(vec![schema_id], database.schema_ids().unwrap());
         assert!(database.remove_schema(&schema_id).unwrap());
 
-        assert!(database.add_genesis(&genesis).unwrap());
+        assert!(!database.add_genesis(&genesis).unwrap());
         assert!(database.has_genesis(&contract_id).unwrap());
         assert_eq!(database.genesis(&contract_id).unwrap(), genesis);
         assert!(database.remove_genesis(&contract_id).unwrap());
 
-        assert!(database.add_transition(&transition).unwrap());
+        assert!(!database.add_transition(&transition).unwrap());
         assert!(database.has_transition(&transition_node_id).unwrap());
         assert_eq!(
             database.transition(&transition_node_id).unwrap(),
             transition
         );
+        assert_eq!(vec![transition_node_id], database.transition_ids().unwrap());
 
         assert!(database.remove_transition(&transition_node_id).unwrap());
-        assert!(database.add_extension(&extension).unwrap());
+        assert!(!database.add_extension(&extension).unwrap());
         assert!(database.has_extension(&extension_node_id).unwrap());
         assert_eq!(database.extension(&extension_node_id).unwrap(), extension);
+        assert_eq!(vec![extension_node_id], database.extension_ids().unwrap());
         assert!(database.remove_extension(&extension_node_id).unwrap());
     }
+
+    #[test]
+    fn test_hammersbald_conformance() {
+        let data_dir = TempPath::new("hammersbald-conformance");
+
+        let mut n = 0;
+        assert_store_conformance(|| {
+            n += 1;
+            HammersbaldStorage::new(HammersbaldConfig {
+                data_dir: data_dir.join(n.to_string()),
+                cached_pages: 100,
+                bucket_fill_targes: 2,
+            })
+            .unwrap()
+        });
+    }
 }
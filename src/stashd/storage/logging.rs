@@ -0,0 +1,587 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use amplify::Wrapper;
+use bitcoin::hashes::{sha256, Hash};
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{ObjectKind, Store, Txn, TxnOp};
+use crate::error::ServiceErrorDomain;
+use crate::util::MagicNumber;
+
+/// Mutation of the store recorded by [`LoggingStore`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+#[repr(u8)]
+pub enum LogOp {
+    /// Addition of an object, including one which was already present
+    #[display("add")]
+    Add = 1,
+
+    /// Removal of an object, including one which was absent
+    #[display("remove")]
+    Remove = 2,
+
+    /// Removal of all objects of the kind
+    #[display("clear")]
+    Clear = 3,
+}
+
+/// Record of the operation log written by [`LoggingStore`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct LogRecord {
+    /// Time of the operation, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub op: LogOp,
+    pub kind: ObjectKind,
+    /// Id of the object; absent for [`LogOp::Clear`]
+    pub id: Option<[u8; 32]>,
+    /// Hash of the previous record of the log (zero for the first one), so
+    /// records can't be modified, removed or reordered without breaking the
+    /// chain of the hashes
+    pub prev: sha256::Hash,
+}
+
+impl LogRecord {
+    /// Hash of the strict encoding of the record, which is committed to by
+    /// the next record of the log
+    pub fn record_hash(&self) -> sha256::Hash {
+        let data = self
+            .strict_serialize()
+            .expect("memory encoders do not fail");
+        sha256::Hash::hash(&data)
+    }
+}
+
+impl StrictEncode for LogRecord {
+    fn strict_encode<E: Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        let mut len = self.timestamp.strict_encode(&mut e)?;
+        len += (self.op as u8).strict_encode(&mut e)?;
+        len += kind_code(self.kind).strict_encode(&mut e)?;
+        len += self.id.strict_encode(&mut e)?;
+        len += self.prev.strict_encode(&mut e)?;
+        Ok(len)
+    }
+}
+
+impl StrictDecode for LogRecord {
+    fn strict_decode<D: Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let timestamp = u64::strict_decode(&mut d)?;
+        let op = match u8::strict_decode(&mut d)? {
+            1 => LogOp::Add,
+            2 => LogOp::Remove,
+            3 => LogOp::Clear,
+            code => {
+                return Err(strict_encoding::Error::EnumValueNotKnown(
+                    "LogOp",
+                    code as usize,
+                ))
+            }
+        };
+        let code = u8::strict_decode(&mut d)?;
+        let kind = ObjectKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind_code(*kind) == code)
+            .ok_or(strict_encoding::Error::EnumValueNotKnown(
+                "ObjectKind",
+                code as usize,
+            ))?;
+        Ok(LogRecord {
+            timestamp,
+            op,
+            kind,
+            id: StrictDecode::strict_decode(&mut d)?,
+            prev: StrictDecode::strict_decode(&mut d)?,
+        })
+    }
+}
+
+/// Code of the object kind used in the log records
+fn kind_code(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Schema => 1,
+        ObjectKind::Genesis => 2,
+        ObjectKind::Anchor => 3,
+        ObjectKind::Transition => 4,
+        ObjectKind::Extension => 5,
+    }
+}
+
+/// Reads all records of the operation log written by [`LoggingStore`],
+/// checking the chain of their hashes. A log with a broken chain, as well as
+/// a truncated one, is reported with [`ServiceErrorDomain::Storage`] error.
+pub fn read_operation_log(path: impl AsRef<Path>) -> Result<Vec<LogRecord>, ServiceErrorDomain> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if u32::from_be_bytes(magic) != MagicNumber::OperationLog.to_u32() {
+        return Err(ServiceErrorDomain::Storage(format!(
+            "{:?} is not an operation log",
+            path
+        )));
+    }
+    let mut records = vec![];
+    let mut prev = sha256::Hash::default();
+    while !reader.fill_buf()?.is_empty() {
+        let record = LogRecord::strict_decode(&mut reader).map_err(|err| {
+            ServiceErrorDomain::Storage(format!(
+                "record {} of operation log {:?} can't be decoded: {}",
+                records.len(),
+                path,
+                err
+            ))
+        })?;
+        if record.prev != prev {
+            return Err(ServiceErrorDomain::Storage(format!(
+                "record {} of operation log {:?} does not follow the previous one",
+                records.len(),
+                path
+            )));
+        }
+        prev = record.record_hash();
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Wraps any [`Store`] recording each addition and removal of the objects
+/// into an append-only operation log file, which can be read with
+/// [`read_operation_log`].
+///
+/// Each mutation is recorded (and the log is synced to the disk) before it
+/// is passed to the inner store, so the log never misses a mutation, but may
+/// contain records of mutations which failed afterwards. Records are chained
+/// by their hashes (see [`LogRecord::prev`]), which makes modifications of
+/// the log detectable, but not the truncation of its tail. Modifications done
+/// to the inner store directly are not recorded.
+///
+/// Errors of the inner store and of the log are converted into
+/// [`ServiceErrorDomain`].
+#[derive(Debug)]
+pub struct LoggingStore<S: Store> {
+    inner: S,
+    log_path: PathBuf,
+    log: File,
+    /// Hash of the last record of the log
+    head: sha256::Hash,
+}
+
+impl<S: Store> LoggingStore<S> {
+    /// Wraps `inner` store, appending the records to the log at `log_path`.
+    /// An existing log is checked with [`read_operation_log`] and continued.
+    pub fn new(inner: S, log_path: impl Into<PathBuf>) -> Result<Self, ServiceErrorDomain> {
+        let log_path = log_path.into();
+        let head = if log_path.exists() {
+            read_operation_log(&log_path)?
+                .last()
+                .map(LogRecord::record_hash)
+                .unwrap_or_default()
+        } else {
+            let mut log = File::create(&log_path)?;
+            log.write_all(&MagicNumber::OperationLog.to_u32().to_be_bytes())?;
+            log.sync_all()?;
+            sha256::Hash::default()
+        };
+        let log = OpenOptions::new().append(true).open(&log_path)?;
+        Ok(Self {
+            inner,
+            log_path,
+            log,
+            head,
+        })
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &S { &self.inner }
+
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    #[inline]
+    pub fn log_path(&self) -> &Path { &self.log_path }
+
+    /// Appends record of the operation to the log
+    fn record(
+        &mut self,
+        op: LogOp,
+        kind: ObjectKind,
+        id: Option<[u8; 32]>,
+    ) -> Result<(), ServiceErrorDomain> {
+        let record = LogRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+            op,
+            kind,
+            id,
+            prev: self.head,
+        };
+        let data = record
+            .strict_serialize()
+            .expect("memory encoders do not fail");
+        self.log.write_all(&data)?;
+        self.log.sync_data()?;
+        self.head = sha256::Hash::hash(&data);
+        Ok(())
+    }
+
+    /// Appends record of the transaction operation to the log
+    fn record_op(&mut self, op: &TxnOp) -> Result<(), ServiceErrorDomain> {
+        let (log_op, kind, id) = match op {
+            TxnOp::AddSchema(schema) => (
+                LogOp::Add,
+                ObjectKind::Schema,
+                schema.schema_id().into_inner().into_inner(),
+            ),
+            TxnOp::RemoveSchema(id) => (
+                LogOp::Remove,
+                ObjectKind::Schema,
+                id.into_inner().into_inner(),
+            ),
+            TxnOp::AddGenesis(genesis) => (
+                LogOp::Add,
+                ObjectKind::Genesis,
+                genesis.contract_id().into_inner().into_inner(),
+            ),
+            TxnOp::RemoveGenesis(id) => (
+                LogOp::Remove,
+                ObjectKind::Genesis,
+                id.into_inner().into_inner(),
+            ),
+            TxnOp::AddAnchor(anchor) => (
+                LogOp::Add,
+                ObjectKind::Anchor,
+                anchor.anchor_id().into_inner().into_inner(),
+            ),
+            TxnOp::RemoveAnchor(id) => (
+                LogOp::Remove,
+                ObjectKind::Anchor,
+                id.into_inner().into_inner(),
+            ),
+            TxnOp::AddTransition(transition) => (
+                LogOp::Add,
+                ObjectKind::Transition,
+                transition.node_id().into_inner().into_inner(),
+            ),
+            TxnOp::RemoveTransition(id) => (
+                LogOp::Remove,
+                ObjectKind::Transition,
+                id.into_inner().into_inner(),
+            ),
+            TxnOp::AddExtension(extension) => (
+                LogOp::Add,
+                ObjectKind::Extension,
+                extension.node_id().into_inner().into_inner(),
+            ),
+            TxnOp::RemoveExtension(id) => (
+                LogOp::Remove,
+                ObjectKind::Extension,
+                id.into_inner().into_inner(),
+            ),
+        };
+        self.record(log_op, kind, Some(id))
+    }
+}
+
+impl<S: Store> Store for LoggingStore<S> {
+    type Error = ServiceErrorDomain;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, ServiceErrorDomain::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
+        self.inner.schema_ids().map_err(S::Error::into)
+    }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> {
+        self.inner.schema_count().map_err(S::Error::into)
+    }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        self.inner.schema(id).map_err(S::Error::into)
+    }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.inner.has_schema(id).map_err(S::Error::into)
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        let id = schema.schema_id().into_inner().into_inner();
+        self.record(LogOp::Add, ObjectKind::Schema, Some(id))?;
+        self.inner.add_schema(schema).map_err(S::Error::into)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        let log_id = id.into_inner().into_inner();
+        self.record(LogOp::Remove, ObjectKind::Schema, Some(log_id))?;
+        self.inner.remove_schema(id).map_err(S::Error::into)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.record(LogOp::Clear, ObjectKind::Schema, None)?;
+        self.inner.clear_schemata().map_err(S::Error::into)
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
+        self.inner.contract_ids().map_err(S::Error::into)
+    }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> {
+        self.inner.contract_count().map_err(S::Error::into)
+    }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        self.inner.genesis(id).map_err(S::Error::into)
+    }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.inner.has_genesis(id).map_err(S::Error::into)
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        let id = genesis.contract_id().into_inner().into_inner();
+        self.record(LogOp::Add, ObjectKind::Genesis, Some(id))?;
+        self.inner.add_genesis(genesis).map_err(S::Error::into)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        let log_id = id.into_inner().into_inner();
+        self.record(LogOp::Remove, ObjectKind::Genesis, Some(log_id))?;
+        self.inner.remove_genesis(id).map_err(S::Error::into)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.record(LogOp::Clear, ObjectKind::Genesis, None)?;
+        self.inner.clear_geneses().map_err(S::Error::into)
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        self.inner.anchor_ids().map_err(S::Error::into)
+    }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> {
+        self.inner.anchor_count().map_err(S::Error::into)
+    }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        self.inner.anchor(id).map_err(S::Error::into)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.inner.has_anchor(id).map_err(S::Error::into)
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        let id = anchor.anchor_id().into_inner().into_inner();
+        self.record(LogOp::Add, ObjectKind::Anchor, Some(id))?;
+        self.inner.add_anchor(anchor).map_err(S::Error::into)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        let log_id = id.into_inner().into_inner();
+        self.record(LogOp::Remove, ObjectKind::Anchor, Some(log_id))?;
+        self.inner.remove_anchor(id).map_err(S::Error::into)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.record(LogOp::Clear, ObjectKind::Anchor, None)?;
+        self.inner.clear_anchors().map_err(S::Error::into)
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.inner.transition_ids().map_err(S::Error::into)
+    }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> {
+        self.inner.transition_count().map_err(S::Error::into)
+    }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        self.inner.transition(id).map_err(S::Error::into)
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.inner.has_transition(id).map_err(S::Error::into)
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        let id = transition.node_id().into_inner().into_inner();
+        self.record(LogOp::Add, ObjectKind::Transition, Some(id))?;
+        self.inner
+            .add_transition(transition)
+            .map_err(S::Error::into)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        for transition in transitions {
+            let id = transition.node_id().into_inner().into_inner();
+            self.record(LogOp::Add, ObjectKind::Transition, Some(id))?;
+        }
+        self.inner
+            .add_transitions(transitions)
+            .map_err(S::Error::into)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        let log_id = id.into_inner().into_inner();
+        self.record(LogOp::Remove, ObjectKind::Transition, Some(log_id))?;
+        self.inner.remove_transition(id).map_err(S::Error::into)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.record(LogOp::Clear, ObjectKind::Transition, None)?;
+        self.inner.clear_transitions().map_err(S::Error::into)
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.inner.extension_ids().map_err(S::Error::into)
+    }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> {
+        self.inner.extension_count().map_err(S::Error::into)
+    }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        self.inner.extension(id).map_err(S::Error::into)
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.inner.has_extension(id).map_err(S::Error::into)
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        let id = extension.node_id().into_inner().into_inner();
+        self.record(LogOp::Add, ObjectKind::Extension, Some(id))?;
+        self.inner.add_extension(extension).map_err(S::Error::into)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        let log_id = id.into_inner().into_inner();
+        self.record(LogOp::Remove, ObjectKind::Extension, Some(log_id))?;
+        self.inner.remove_extension(id).map_err(S::Error::into)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.record(LogOp::Clear, ObjectKind::Extension, None)?;
+        self.inner.clear_extensions().map_err(S::Error::into)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> { self.inner.flush().map_err(S::Error::into) }
+
+    /// Records all the operations of the transaction, and applies it to the
+    /// inner store, so the latter may apply it atomically
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> {
+        for op in txn.ops() {
+            self.record_op(op)?;
+        }
+        self.inner.apply_txn(txn).map_err(S::Error::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::stashd::storage::MemoryStorage;
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_logging_replay() {
+        let log_path = TempPath::new("oplog-test");
+
+        let genesis = Genesis::default();
+        let contract_id = genesis.contract_id().into_inner().into_inner();
+        let mut store = LoggingStore::new(MemoryStorage::new(), &*log_path)
+            .ok()
+            .unwrap();
+        assert_eq!(store.add_genesis(&genesis).ok(), Some(false));
+        assert_eq!(
+            store.remove_genesis(&genesis.contract_id()).ok(),
+            Some(true)
+        );
+        drop(store);
+
+        let mut store = LoggingStore::new(MemoryStorage::new(), &*log_path)
+            .ok()
+            .unwrap();
+        assert!(store.clear_transitions().is_ok());
+        let records = read_operation_log(&log_path).ok().unwrap();
+        let ops: Vec<_> = records
+            .iter()
+            .map(|record| (record.op, record.kind, record.id))
+            .collect();
+        assert_eq!(ops, vec![
+            (LogOp::Add, ObjectKind::Genesis, Some(contract_id)),
+            (LogOp::Remove, ObjectKind::Genesis, Some(contract_id)),
+            (LogOp::Clear, ObjectKind::Transition, None),
+        ]);
+        assert_eq!(records[1].prev, records[0].record_hash());
+
+        let mut data = fs::read(&log_path).unwrap();
+        data[4] ^= 0xFF;
+        fs::write(&log_path, data).unwrap();
+        assert!(matches!(
+            read_operation_log(&log_path),
+            Err(ServiceErrorDomain::Storage(_))
+        ));
+    }
+
+    #[test]
+    fn test_logging_txn() {
+        let log_path = TempPath::new("oplog-txn");
+
+        let genesis = Genesis::default();
+        let transition = Transition::default();
+        let mut store = LoggingStore::new(MemoryStorage::new(), &*log_path)
+            .ok()
+            .unwrap();
+        assert!(store
+            .transaction::<_, ServiceErrorDomain>(|txn| {
+                txn.add_genesis(&genesis);
+                txn.remove_transition(&transition.node_id());
+                Ok(())
+            })
+            .is_ok());
+        assert_eq!(store.has_genesis(&genesis.contract_id()).ok(), Some(true));
+
+        let ops: Vec<_> = read_operation_log(&log_path)
+            .ok()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.op, record.kind, record.id))
+            .collect();
+        assert_eq!(ops, vec![
+            (
+                LogOp::Add,
+                ObjectKind::Genesis,
+                Some(genesis.contract_id().into_inner().into_inner())
+            ),
+            (
+                LogOp::Remove,
+                ObjectKind::Transition,
+                Some(transition.node_id().into_inner().into_inner())
+            ),
+        ]);
+    }
+}
@@ -0,0 +1,134 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use amplify::Wrapper;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, sha256t};
+use bp::dbc::AnchorId;
+use rgb::prelude::*;
+
+use super::{ConsignmentId, DiskStorageConfig};
+
+/// Maps ids of the objects kept by [`super::DiskStorage`] to the names of
+/// their files and back, allowing to follow custom file naming conventions.
+///
+/// Names are relative to the directory of the object category and must
+/// consist of a single file name component. Network prefix and sharding (see
+/// [`DiskStorageConfig`]) are applied by the storage on top of the
+/// mapping. Names ending with the checksum sidecar, tombstone, expiry marker
+/// or temporary file extensions are reserved by the storage.
+pub trait PathMapper: Debug + Send + Sync {
+    fn schema_path(&self, id: &SchemaId) -> PathBuf;
+    fn genesis_path(&self, id: &ContractId) -> PathBuf;
+    fn anchor_path(&self, id: &AnchorId) -> PathBuf;
+    fn transition_path(&self, id: &NodeId) -> PathBuf;
+    fn extension_path(&self, id: &NodeId) -> PathBuf;
+
+    /// Parses id from a file name produced by [`PathMapper::schema_path`];
+    /// returns `None` for files which are not schema files
+    fn parse_schema_path(&self, name: &str) -> Option<SchemaId>;
+    fn parse_genesis_path(&self, name: &str) -> Option<ContractId>;
+    fn parse_anchor_path(&self, name: &str) -> Option<AnchorId>;
+    fn parse_transition_path(&self, name: &str) -> Option<NodeId>;
+    fn parse_extension_path(&self, name: &str) -> Option<NodeId>;
+
+    /// Prefix which names of the files of schemata with bech32 ids starting
+    /// with `prefix` start with, allowing [`super::DiskStorage`] to skip the
+    /// other files without parsing their names; `None` (the default) if the
+    /// naming does not allow this
+    fn schema_name_prefix(&self, _prefix: &str) -> Option<String> { None }
+    fn genesis_name_prefix(&self, _prefix: &str) -> Option<String> { None }
+
+    /// Tells, without parsing the id, whether `name` may be a name of an
+    /// object file; lets [`super::DiskStorage`] count the stored objects
+    /// skipping unrelated files. Defaults to `true`.
+    fn is_object_name(&self, _name: &str) -> bool { true }
+
+    /// Name of the consignment file; defaults to hex id with `.rgb`
+    /// extension
+    fn consignment_path(&self, id: &ConsignmentId) -> PathBuf {
+        DefaultPathMapper::name(id.to_hex())
+    }
+
+    fn parse_consignment_path(&self, name: &str) -> Option<ConsignmentId> {
+        sha256::Hash::from_hex(DefaultPathMapper::stem(name)?)
+            .ok()
+            .map(ConsignmentId::from_inner)
+    }
+}
+
+/// Default file naming: bech32 ids for schemata and geneses, hex ids for the
+/// rest of the objects, all with `.rgb` extension
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct DefaultPathMapper;
+
+impl DefaultPathMapper {
+    fn name(id: String) -> PathBuf {
+        PathBuf::from(id).with_extension(DiskStorageConfig::RGB_FILE_EXT)
+    }
+
+    /// Returns stem of the name of a file with `.rgb` extension
+    fn stem(name: &str) -> Option<&str> {
+        let path = Path::new(name);
+        if path.extension()?.to_str()? != DiskStorageConfig::RGB_FILE_EXT {
+            return None;
+        }
+        path.file_stem()?.to_str()
+    }
+
+    fn parse_hex<T: sha256t::Tag>(name: &str) -> Option<sha256t::Hash<T>> {
+        sha256t::Hash::from_hex(Self::stem(name)?).ok()
+    }
+}
+
+impl PathMapper for DefaultPathMapper {
+    fn schema_path(&self, id: &SchemaId) -> PathBuf { Self::name(id.to_bech32().to_string()) }
+
+    fn genesis_path(&self, id: &ContractId) -> PathBuf { Self::name(id.to_bech32().to_string()) }
+
+    fn anchor_path(&self, id: &AnchorId) -> PathBuf { Self::name(id.to_hex()) }
+
+    fn transition_path(&self, id: &NodeId) -> PathBuf { Self::name(id.to_hex()) }
+
+    fn extension_path(&self, id: &NodeId) -> PathBuf { Self::name(id.to_hex()) }
+
+    fn parse_schema_path(&self, name: &str) -> Option<SchemaId> {
+        SchemaId::from_bech32_str(Self::stem(name)?).ok()
+    }
+
+    fn parse_genesis_path(&self, name: &str) -> Option<ContractId> {
+        ContractId::from_bech32_str(Self::stem(name)?).ok()
+    }
+
+    fn parse_anchor_path(&self, name: &str) -> Option<AnchorId> {
+        Self::parse_hex(name).map(AnchorId::from_inner)
+    }
+
+    fn parse_transition_path(&self, name: &str) -> Option<NodeId> {
+        Self::parse_hex(name).map(NodeId::from_inner)
+    }
+
+    fn parse_extension_path(&self, name: &str) -> Option<NodeId> {
+        Self::parse_hex(name).map(NodeId::from_inner)
+    }
+
+    fn is_object_name(&self, name: &str) -> bool { Self::stem(name).is_some() }
+
+    fn schema_name_prefix(&self, prefix: &str) -> Option<String> { Some(prefix.to_owned()) }
+
+    fn genesis_name_prefix(&self, prefix: &str) -> Option<String> { Some(prefix.to_owned()) }
+}
@@ -0,0 +1,317 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::HashMap;
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+
+use super::{ConsignmentId, ConsignmentStore, Store};
+use crate::error::ServiceErrorDomain;
+
+#[derive(Debug, Display, Error, From)]
+#[display(Debug)]
+pub enum MemoryStorageError {
+    NotFound,
+
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+impl From<MemoryStorageError> for ServiceErrorDomain {
+    fn from(err: MemoryStorageError) -> Self {
+        match err {
+            MemoryStorageError::NotFound => ServiceErrorDomain::NotFound,
+            err => ServiceErrorDomain::Storage(err.to_string()),
+        }
+    }
+}
+
+/// Keeps all RGB contract data in memory; useful for tests and ephemeral
+/// nodes which do not need to persist their stash
+#[derive(Clone, Debug, Default, Display)]
+#[display(Debug)]
+pub struct MemoryStorage {
+    schemata: HashMap<SchemaId, Schema>,
+    geneses: HashMap<ContractId, Genesis>,
+    anchors: HashMap<AnchorId, Anchor<MerkleBlock>>,
+    transitions: HashMap<NodeId, Transition>,
+    extensions: HashMap<NodeId, Extension>,
+    consignments: HashMap<ConsignmentId, Consignment>,
+}
+
+impl MemoryStorage {
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    /// Borrows the schema kept in the storage, avoiding the clone made by
+    /// [`Store::schema`]. Returns `None` for unknown schemata.
+    #[inline]
+    pub fn schema_ref(&self, id: &SchemaId) -> Result<Option<&Schema>, MemoryStorageError> {
+        Ok(self.schemata.get(id))
+    }
+
+    #[inline]
+    pub fn genesis_ref(&self, id: &ContractId) -> Result<Option<&Genesis>, MemoryStorageError> {
+        Ok(self.geneses.get(id))
+    }
+
+    #[inline]
+    pub fn anchor_ref(
+        &self,
+        id: &AnchorId,
+    ) -> Result<Option<&Anchor<MerkleBlock>>, MemoryStorageError> {
+        Ok(self.anchors.get(id))
+    }
+
+    #[inline]
+    pub fn transition_ref(&self, id: &NodeId) -> Result<Option<&Transition>, MemoryStorageError> {
+        Ok(self.transitions.get(id))
+    }
+
+    #[inline]
+    pub fn extension_ref(&self, id: &NodeId) -> Result<Option<&Extension>, MemoryStorageError> {
+        Ok(self.extensions.get(id))
+    }
+
+    #[inline]
+    pub fn consignment_ref(
+        &self,
+        id: &ConsignmentId,
+    ) -> Result<Option<&Consignment>, MemoryStorageError> {
+        Ok(self.consignments.get(id))
+    }
+}
+
+impl Store for MemoryStorage {
+    type Error = MemoryStorageError;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, MemoryStorageError::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
+        Ok(self.schemata.keys().copied().collect())
+    }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> { Ok(self.schemata.len()) }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        self.schemata
+            .get(id)
+            .cloned()
+            .ok_or(MemoryStorageError::NotFound)
+    }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        Ok(self.schemata.contains_key(id))
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        Ok(self
+            .schemata
+            .insert(schema.schema_id(), schema.clone())
+            .is_some())
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        Ok(self.schemata.remove(id).is_some())
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.schemata.clear();
+        Ok(())
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
+        Ok(self.geneses.keys().copied().collect())
+    }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> { Ok(self.geneses.len()) }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        self.geneses
+            .get(id)
+            .cloned()
+            .ok_or(MemoryStorageError::NotFound)
+    }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        Ok(self.geneses.contains_key(id))
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        Ok(self
+            .geneses
+            .insert(genesis.contract_id(), genesis.clone())
+            .is_some())
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        Ok(self.geneses.remove(id).is_some())
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.geneses.clear();
+        Ok(())
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        Ok(self.anchors.keys().copied().collect())
+    }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> { Ok(self.anchors.len()) }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        self.anchors
+            .get(id)
+            .cloned()
+            .ok_or(MemoryStorageError::NotFound)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        Ok(self.anchors.contains_key(id))
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        Ok(self
+            .anchors
+            .insert(anchor.anchor_id(), anchor.clone())
+            .is_some())
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        Ok(self.anchors.remove(id).is_some())
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.anchors.clear();
+        Ok(())
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        Ok(self.transitions.keys().copied().collect())
+    }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> { Ok(self.transitions.len()) }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        self.transitions
+            .get(id)
+            .cloned()
+            .ok_or(MemoryStorageError::NotFound)
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        Ok(self.transitions.contains_key(id))
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        Ok(self
+            .transitions
+            .insert(transition.node_id(), transition.clone())
+            .is_some())
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        Ok(self.transitions.remove(id).is_some())
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.transitions.clear();
+        Ok(())
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        Ok(self.extensions.keys().copied().collect())
+    }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> { Ok(self.extensions.len()) }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        self.extensions
+            .get(id)
+            .cloned()
+            .ok_or(MemoryStorageError::NotFound)
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        Ok(self.extensions.contains_key(id))
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        Ok(self
+            .extensions
+            .insert(extension.node_id(), extension.clone())
+            .is_some())
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        Ok(self.extensions.remove(id).is_some())
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.extensions.clear();
+        Ok(())
+    }
+}
+
+impl ConsignmentStore for MemoryStorage {
+    fn consignment_ids(&self) -> Result<Vec<ConsignmentId>, Self::Error> {
+        Ok(self.consignments.keys().copied().collect())
+    }
+
+    fn consignment(&self, id: &ConsignmentId) -> Result<Consignment, Self::Error> {
+        self.consignments
+            .get(id)
+            .cloned()
+            .ok_or(MemoryStorageError::NotFound)
+    }
+
+    fn has_consignment(&self, id: &ConsignmentId) -> Result<bool, Self::Error> {
+        Ok(self.consignments.contains_key(id))
+    }
+
+    fn add_consignment(&mut self, consignment: &Consignment) -> Result<bool, Self::Error> {
+        Ok(self
+            .consignments
+            .insert(
+                ConsignmentId::from_consignment(consignment),
+                consignment.clone(),
+            )
+            .is_some())
+    }
+
+    fn remove_consignment(&mut self, id: &ConsignmentId) -> Result<bool, Self::Error> {
+        Ok(self.consignments.remove(id).is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stashd::storage::assert_store_conformance;
+
+    #[test]
+    fn test_memory_conformance() { assert_store_conformance(MemoryStorage::new); }
+
+    #[test]
+    fn test_memory_insert_with_other_id() {
+        let mut storage = MemoryStorage::new();
+        let res = storage
+            .transition_or_insert_with::<ServiceErrorDomain, _>(&NodeId::default(), || {
+                Ok(Transition::default())
+            });
+        assert!(matches!(res, Err(ServiceErrorDomain::Storage(_))));
+        assert_eq!(storage.transition_count().ok(), Some(0));
+    }
+}
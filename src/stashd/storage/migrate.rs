@@ -0,0 +1,237 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+
+use strict_encoding::StrictEncode;
+
+use super::{ObjectKind, Store};
+use crate::error::ServiceErrorDomain;
+
+/// Object which [`migrate`] has failed to copy
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct MigrationFailure {
+    pub kind: ObjectKind,
+    pub id: String,
+    pub error: String,
+}
+
+/// Statistics returned by [`migrate`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct MigrationStats {
+    pub schemata: usize,
+    pub geneses: usize,
+    pub anchors: usize,
+    pub transitions: usize,
+    pub extensions: usize,
+    pub failed: Vec<MigrationFailure>,
+}
+
+/// Copies all the data from `src` to `dst` store, returning number of the
+/// copied objects of each kind and the list of objects which failed to
+/// transfer. Failure of a single object does not stop the migration; failure
+/// to list the objects of `src` does.
+pub fn migrate<S: Store, D: Store>(
+    src: &S,
+    dst: &mut D,
+) -> Result<MigrationStats, ServiceErrorDomain> {
+    let mut failed = vec![];
+    let schemata = copy_all(
+        ObjectKind::Schema,
+        src.schema_ids().map_err(S::Error::into)?,
+        |id| src.schema(id),
+        |schema| dst.add_schema(schema),
+        &mut failed,
+    );
+    let geneses = copy_all(
+        ObjectKind::Genesis,
+        src.contract_ids().map_err(S::Error::into)?,
+        |id| src.genesis(id),
+        |genesis| dst.add_genesis(genesis),
+        &mut failed,
+    );
+    let anchors = copy_all(
+        ObjectKind::Anchor,
+        src.anchor_ids().map_err(S::Error::into)?,
+        |id| src.anchor(id),
+        |anchor| dst.add_anchor(anchor),
+        &mut failed,
+    );
+    let transitions = copy_all(
+        ObjectKind::Transition,
+        src.transition_ids().map_err(S::Error::into)?,
+        |id| src.transition(id),
+        |transition| dst.add_transition(transition),
+        &mut failed,
+    );
+    let extensions = copy_all(
+        ObjectKind::Extension,
+        src.extension_ids().map_err(S::Error::into)?,
+        |id| src.extension(id),
+        |extension| dst.add_extension(extension),
+        &mut failed,
+    );
+    Ok(MigrationStats {
+        schemata,
+        geneses,
+        anchors,
+        transitions,
+        extensions,
+        failed,
+    })
+}
+
+fn copy_all<I, T, E1, E2>(
+    kind: ObjectKind,
+    ids: Vec<I>,
+    load: impl Fn(&I) -> Result<T, E1>,
+    mut store: impl FnMut(&T) -> Result<bool, E2>,
+    failed: &mut Vec<MigrationFailure>,
+) -> usize
+where
+    I: Display,
+    E1: Display,
+    E2: Display,
+{
+    let mut copied = 0;
+    for id in ids {
+        let res = match load(&id) {
+            Ok(object) => store(&object).map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        match res {
+            Ok(_) => copied += 1,
+            Err(error) => failed.push(MigrationFailure {
+                kind,
+                id: id.to_string(),
+                error,
+            }),
+        }
+    }
+    copied
+}
+
+/// Differences between the objects of a single kind found by
+/// [`stores_equal`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct KindDiff {
+    /// Ids of the objects present in the first store only
+    pub missing: Vec<String>,
+    /// Ids of the objects present in the second store only
+    pub extra: Vec<String>,
+    /// Ids of the objects present in both stores, but encoded differently
+    pub differing: Vec<String>,
+}
+
+impl KindDiff {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.differing.is_empty()
+    }
+}
+
+/// Report returned by [`stores_equal`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct StoreDiff {
+    pub schemata: KindDiff,
+    pub geneses: KindDiff,
+    pub anchors: KindDiff,
+    pub transitions: KindDiff,
+    pub extensions: KindDiff,
+}
+
+impl StoreDiff {
+    /// Tells whether both stores keep exactly the same data
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.schemata.is_empty()
+            && self.geneses.is_empty()
+            && self.anchors.is_empty()
+            && self.transitions.is_empty()
+            && self.extensions.is_empty()
+    }
+}
+
+/// Compares data kept by two stores, possibly of different backends: the
+/// sets of ids of each kind, and strict encodings of the objects present in
+/// both stores. Useful to ensure that [`migrate`] or a replication was
+/// lossless.
+pub fn stores_equal<A: Store, B: Store>(a: &A, b: &B) -> Result<StoreDiff, ServiceErrorDomain> {
+    Ok(StoreDiff {
+        schemata: diff_kind(
+            a.schema_ids().map_err(A::Error::into)?,
+            b.schema_ids().map_err(B::Error::into)?,
+            |id| a.schema(id).map_err(A::Error::into),
+            |id| b.schema(id).map_err(B::Error::into),
+        )?,
+        geneses: diff_kind(
+            a.contract_ids().map_err(A::Error::into)?,
+            b.contract_ids().map_err(B::Error::into)?,
+            |id| a.genesis(id).map_err(A::Error::into),
+            |id| b.genesis(id).map_err(B::Error::into),
+        )?,
+        anchors: diff_kind(
+            a.anchor_ids().map_err(A::Error::into)?,
+            b.anchor_ids().map_err(B::Error::into)?,
+            |id| a.anchor(id).map_err(A::Error::into),
+            |id| b.anchor(id).map_err(B::Error::into),
+        )?,
+        transitions: diff_kind(
+            a.transition_ids().map_err(A::Error::into)?,
+            b.transition_ids().map_err(B::Error::into)?,
+            |id| a.transition(id).map_err(A::Error::into),
+            |id| b.transition(id).map_err(B::Error::into),
+        )?,
+        extensions: diff_kind(
+            a.extension_ids().map_err(A::Error::into)?,
+            b.extension_ids().map_err(B::Error::into)?,
+            |id| a.extension(id).map_err(A::Error::into),
+            |id| b.extension(id).map_err(B::Error::into),
+        )?,
+    })
+}
+
+fn diff_kind<I, T>(
+    a_ids: Vec<I>,
+    b_ids: Vec<I>,
+    load_a: impl Fn(&I) -> Result<T, ServiceErrorDomain>,
+    load_b: impl Fn(&I) -> Result<T, ServiceErrorDomain>,
+) -> Result<KindDiff, ServiceErrorDomain>
+where
+    I: Ord + Display,
+    T: StrictEncode,
+{
+    let encode = |object: T| {
+        object
+            .strict_serialize()
+            .map_err(|err| ServiceErrorDomain::Internal(err.to_string()))
+    };
+    let a_ids: BTreeSet<I> = a_ids.into_iter().collect();
+    let b_ids: BTreeSet<I> = b_ids.into_iter().collect();
+    let mut diff = KindDiff {
+        missing: a_ids.difference(&b_ids).map(I::to_string).collect(),
+        extra: b_ids.difference(&a_ids).map(I::to_string).collect(),
+        differing: vec![],
+    };
+    for id in a_ids.intersection(&b_ids) {
+        if encode(load_a(id)?)? != encode(load_b(id)?)? {
+            diff.differing.push(id.to_string());
+        }
+    }
+    Ok(diff)
+}
@@ -11,13 +11,54 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+#[cfg(feature = "tokio")]
+mod async_store;
+mod cache;
+#[cfg(any(test, feature = "test-util"))]
+mod conformance;
 mod disk;
+mod dyn_store;
+mod filesystem;
+mod logging;
 #[cfg(feature = "hammersbald")]
 mod hammersbald;
+mod mapper;
+mod memory;
+mod migrate;
+mod shareable;
+#[cfg(feature = "sled")]
+mod sled;
 mod store;
+mod tiered;
+mod transaction;
 
-pub use disk::{DiskStorage, DiskStorageConfig, DiskStorageError};
-pub use store::Store;
+#[cfg(feature = "tokio")]
+pub use async_store::{AsyncStore, BlockingStore};
+pub use cache::CachedStore;
+#[cfg(any(test, feature = "test-util"))]
+pub use conformance::{assert_store_conformance, sample_anchor};
+#[cfg(feature = "compression")]
+pub use disk::CompressionLevel;
+pub use disk::{
+    CategoryUsage, CompactReport, DataFormat, DiskStorage, DiskStorageConfig, DiskStorageError,
+    ImportCount, ImportFailure, ImportStats, LockHolder, StorageHealth, StorageObserver,
+    StorageStats, StoredObjectRef, VerifyError,
+};
+pub use dyn_store::{DynStore, ErasedStore};
+pub use filesystem::{DirEntry, FileSystem, IoOp, LockGuard, Metadata, RetryPolicy, StdFileSystem};
+pub use logging::{read_operation_log, LogOp, LogRecord, LoggingStore};
+pub use mapper::{DefaultPathMapper, PathMapper};
+pub use memory::{MemoryStorage, MemoryStorageError};
+pub use migrate::{migrate, stores_equal, KindDiff, MigrationFailure, MigrationStats, StoreDiff};
+pub use shareable::ShareableStore;
+pub use store::{
+    AddOutcome, ConsignmentId, ConsignmentStore, IdMismatch, ObjectKind, SnapshotStore, Store,
+    StoreMeta,
+};
+pub use tiered::TieredStore;
+pub use transaction::{Txn, TxnOp};
 
 #[cfg(feature = "hammersbald")]
 pub use self::hammersbald::HammersbaldStorage;
+#[cfg(feature = "sled")]
+pub use self::sled::{SledStorage, SledStorageConfig, SledStorageError};
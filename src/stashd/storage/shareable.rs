@@ -0,0 +1,257 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+
+use super::{Store, Txn};
+
+/// Wraps a [`Store`] into a read-write lock, allowing it to be shared between
+/// threads (for instance with an `Arc`) without a global mutex: reads proceed
+/// concurrently, while each write takes the exclusive lock.
+///
+/// [`Store`] is implemented for `&ShareableStore`, so the shared reference
+/// can be used everywhere a store is expected, including mutating methods.
+///
+/// Each of the store methods is atomic with respect to the others; sequences
+/// of calls are not. If a sequence of operations must not interleave with
+/// other writers (like check-then-add), take the lock for the whole sequence
+/// with [`ShareableStore::write`].
+#[derive(Debug, Default)]
+pub struct ShareableStore<S: Store> {
+    inner: RwLock<S>,
+}
+
+impl<S: Store> ShareableStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner: RwLock::new(inner),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Locks the store for reading
+    pub fn read(&self) -> RwLockReadGuard<'_, S> {
+        // A panic in the inner store leaves it in a state no worse than an
+        // I/O error would, so poisoning is ignored
+        self.inner.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Locks the store for writing
+    pub fn write(&self) -> RwLockWriteGuard<'_, S> {
+        self.inner.write().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<S: Store> Store for &ShareableStore<S> {
+    type Error = S::Error;
+
+    fn is_not_found(err: &Self::Error) -> bool { S::is_not_found(err) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> { self.read().schema_ids() }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> { self.read().schema_count() }
+
+    fn schema_ids_with_prefix(&self, prefix: &str) -> Result<Vec<SchemaId>, Self::Error> {
+        self.read().schema_ids_with_prefix(prefix)
+    }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> { self.read().schema(id) }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> { self.read().has_schema(id) }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        self.write().add_schema(schema)
+    }
+
+    fn replace_schema(&mut self, old: &Schema, new: &Schema) -> Result<bool, Self::Error> {
+        self.write().replace_schema(old, new)
+    }
+
+    fn add_schema_if_absent(&mut self, schema: &Schema) -> Result<Option<Schema>, Self::Error> {
+        self.write().add_schema_if_absent(schema)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.write().remove_schema(id)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> { self.write().clear_schemata() }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> { self.read().contract_ids() }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> { self.read().contract_count() }
+
+    fn contract_ids_with_prefix(&self, prefix: &str) -> Result<Vec<ContractId>, Self::Error> {
+        self.read().contract_ids_with_prefix(prefix)
+    }
+
+    fn contract_ids_by_schema(&self, schema_id: &SchemaId) -> Result<Vec<ContractId>, Self::Error> {
+        self.read().contract_ids_by_schema(schema_id)
+    }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> { self.read().genesis(id) }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.read().has_genesis(id)
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        self.write().add_genesis(genesis)
+    }
+
+    fn replace_genesis(&mut self, old: &Genesis, new: &Genesis) -> Result<bool, Self::Error> {
+        self.write().replace_genesis(old, new)
+    }
+
+    fn add_genesis_if_absent(&mut self, genesis: &Genesis) -> Result<Option<Genesis>, Self::Error> {
+        self.write().add_genesis_if_absent(genesis)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.write().remove_genesis(id)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> { self.write().clear_geneses() }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> { self.read().anchor_ids() }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> { self.read().anchor_count() }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        self.read().anchor(id)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> { self.read().has_anchor(id) }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        self.write().add_anchor(anchor)
+    }
+
+    fn replace_anchor(
+        &mut self,
+        old: &Anchor<MerkleBlock>,
+        new: &Anchor<MerkleBlock>,
+    ) -> Result<bool, Self::Error> {
+        self.write().replace_anchor(old, new)
+    }
+
+    fn add_anchor_if_absent(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<Option<Anchor<MerkleBlock>>, Self::Error> {
+        self.write().add_anchor_if_absent(anchor)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.write().remove_anchor(id)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> { self.write().clear_anchors() }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> { self.read().transition_ids() }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> { self.read().transition_count() }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        self.read().transition(id)
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.read().has_transition(id)
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        self.write().add_transition(transition)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        self.write().add_transitions(transitions)
+    }
+
+    fn transitions_many(
+        &self,
+        ids: &[NodeId],
+    ) -> Result<BTreeMap<NodeId, Transition>, Self::Error> {
+        self.read().transitions_many(ids)
+    }
+
+    fn has_transitions(&self, ids: &[NodeId]) -> Result<BTreeSet<NodeId>, Self::Error> {
+        self.read().has_transitions(ids)
+    }
+
+    fn replace_transition(
+        &mut self,
+        old: &Transition,
+        new: &Transition,
+    ) -> Result<bool, Self::Error> {
+        self.write().replace_transition(old, new)
+    }
+
+    fn add_transition_if_absent(
+        &mut self,
+        transition: &Transition,
+    ) -> Result<Option<Transition>, Self::Error> {
+        self.write().add_transition_if_absent(transition)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.write().remove_transition(id)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> { self.write().clear_transitions() }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> { self.read().extension_ids() }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> { self.read().extension_count() }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> { self.read().extension(id) }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.read().has_extension(id)
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        self.write().add_extension(extension)
+    }
+
+    fn replace_extension(&mut self, old: &Extension, new: &Extension) -> Result<bool, Self::Error> {
+        self.write().replace_extension(old, new)
+    }
+
+    fn add_extension_if_absent(
+        &mut self,
+        extension: &Extension,
+    ) -> Result<Option<Extension>, Self::Error> {
+        self.write().add_extension_if_absent(extension)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.write().remove_extension(id)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> { self.write().clear_extensions() }
+
+    fn flush(&mut self) -> Result<(), Self::Error> { self.write().flush() }
+
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> { self.write().apply_txn(txn) }
+}
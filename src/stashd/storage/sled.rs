@@ -0,0 +1,346 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::store::{SnapshotStore, Store};
+use crate::error::{BootstrapError, ServiceErrorDomain};
+
+#[derive(Debug, Display, Error, From)]
+#[display(Debug)]
+pub enum SledStorageError {
+    #[from]
+    Sled(sled::Error),
+
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    NotFound,
+}
+
+impl From<SledStorageError> for ServiceErrorDomain {
+    fn from(err: SledStorageError) -> Self {
+        match err {
+            SledStorageError::NotFound => ServiceErrorDomain::NotFound,
+            err => ServiceErrorDomain::Storage(err.to_string()),
+        }
+    }
+}
+
+impl From<SledStorageError> for BootstrapError {
+    fn from(_: SledStorageError) -> Self { BootstrapError::StorageError }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct SledStorageConfig {
+    pub path: PathBuf,
+}
+
+/// Keeps all RGB contract data in a sled database, using a separate tree for
+/// each type of the objects. Keys are raw id bytes, values are strict-encoded
+/// objects.
+#[derive(Debug, Display)]
+#[display(Debug)]
+pub struct SledStorage {
+    db: sled::Db,
+    schemata: sled::Tree,
+    geneses: sled::Tree,
+    anchors: sled::Tree,
+    transitions: sled::Tree,
+    extensions: sled::Tree,
+}
+
+impl SledStorage {
+    pub fn new(config: SledStorageConfig) -> Result<Self, SledStorageError> {
+        debug!("Instantiating RGB storage (sled database) ...");
+
+        let db = sled::open(&config.path)?;
+        Ok(Self {
+            schemata: db.open_tree("schemata")?,
+            geneses: db.open_tree("geneses")?,
+            anchors: db.open_tree("anchors")?,
+            transitions: db.open_tree("transitions")?,
+            extensions: db.open_tree("extensions")?,
+            db,
+        })
+    }
+
+    /// Flushes all pending writes to the disk
+    pub fn flush(&self) -> Result<(), SledStorageError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn ids<I: StrictDecode>(tree: &sled::Tree) -> Result<Vec<I>, SledStorageError> {
+        tree.iter()
+            .keys()
+            .map(|key| Ok(I::strict_deserialize(key?)?))
+            .collect()
+    }
+
+    fn get<T: StrictDecode>(
+        tree: &sled::Tree,
+        id: &impl StrictEncode,
+    ) -> Result<T, SledStorageError> {
+        let value = tree
+            .get(id.strict_serialize()?)?
+            .ok_or(SledStorageError::NotFound)?;
+        Ok(T::strict_deserialize(value)?)
+    }
+
+    fn has(tree: &sled::Tree, id: &impl StrictEncode) -> Result<bool, SledStorageError> {
+        Ok(tree.contains_key(id.strict_serialize()?)?)
+    }
+
+    fn insert(
+        tree: &sled::Tree,
+        id: &impl StrictEncode,
+        object: &impl StrictEncode,
+    ) -> Result<bool, SledStorageError> {
+        Ok(tree
+            .insert(id.strict_serialize()?, object.strict_serialize()?)?
+            .is_some())
+    }
+
+    fn remove(tree: &sled::Tree, id: &impl StrictEncode) -> Result<bool, SledStorageError> {
+        Ok(tree.remove(id.strict_serialize()?)?.is_some())
+    }
+}
+
+impl Store for SledStorage {
+    type Error = SledStorageError;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, SledStorageError::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> { Self::ids(&self.schemata) }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> { Ok(self.schemata.len()) }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> { Self::get(&self.schemata, id) }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        Self::has(&self.schemata, id)
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        Self::insert(&self.schemata, &schema.schema_id(), schema)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        Self::remove(&self.schemata, id)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.schemata.clear()?;
+        Ok(())
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> { Self::ids(&self.geneses) }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> { Ok(self.geneses.len()) }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        Self::get(&self.geneses, id)
+    }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        Self::has(&self.geneses, id)
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        Self::insert(&self.geneses, &genesis.contract_id(), genesis)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        Self::remove(&self.geneses, id)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.geneses.clear()?;
+        Ok(())
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> { Self::ids(&self.anchors) }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> { Ok(self.anchors.len()) }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        Self::get(&self.anchors, id)
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        Self::has(&self.anchors, id)
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        Self::insert(&self.anchors, &anchor.anchor_id(), anchor)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        Self::remove(&self.anchors, id)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.anchors.clear()?;
+        Ok(())
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> { Self::ids(&self.transitions) }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> { Ok(self.transitions.len()) }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        Self::get(&self.transitions, id)
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        Self::has(&self.transitions, id)
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        Self::insert(&self.transitions, &transition.node_id(), transition)
+    }
+
+    /// Adds all transitions with a single atomic batch
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        let mut batch = sled::Batch::default();
+        let mut created = 0;
+        for transition in transitions {
+            let key = transition.node_id().strict_serialize()?;
+            if !self.transitions.contains_key(&key)? {
+                created += 1;
+            }
+            batch.insert(key, transition.strict_serialize()?);
+        }
+        self.transitions.apply_batch(batch)?;
+        Ok(created)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        Self::remove(&self.transitions, id)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.transitions.clear()?;
+        Ok(())
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> { Self::ids(&self.extensions) }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> { Ok(self.extensions.len()) }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        Self::get(&self.extensions, id)
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        Self::has(&self.extensions, id)
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        Self::insert(&self.extensions, &extension.node_id(), extension)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        Self::remove(&self.extensions, id)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.extensions.clear()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+impl SnapshotStore for SledStorage {
+    /// Copies all the trees of the database into a new database at `dest`,
+    /// which is flushed before returning. The storage can't be written while
+    /// borrowed for the snapshot, so the copy is consistent; sled can't
+    /// checkpoint the database files themselves.
+    fn snapshot(&self, dest: &Path) -> Result<(), Self::Error> {
+        if dest.exists() {
+            return Err(sled::Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "snapshot destination already exists",
+            ))
+            .into());
+        }
+        let db = sled::open(dest)?;
+        for name in self.db.tree_names() {
+            let tree = db.open_tree(&name)?;
+            for item in self.db.open_tree(&name)?.iter() {
+                let (key, value) = item?;
+                tree.insert(key, value)?;
+            }
+        }
+        db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stashd::storage::assert_store_conformance;
+    use crate::util::TempPath;
+
+    #[test]
+    fn test_sled_conformance() {
+        let data_dir = TempPath::new("sled-conformance");
+
+        let mut n = 0;
+        assert_store_conformance(|| {
+            n += 1;
+            SledStorage::new(SledStorageConfig {
+                path: data_dir.join(n.to_string()),
+            })
+            .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_sled_snapshot() {
+        let data_dir = TempPath::new("sled-snapshot");
+
+        let genesis = Genesis::default();
+        let mut storage = SledStorage::new(SledStorageConfig {
+            path: data_dir.join("db"),
+        })
+        .unwrap();
+        storage.add_genesis(&genesis).unwrap();
+        storage.snapshot(&data_dir.join("snapshot")).unwrap();
+        storage.add_transition(&Transition::default()).unwrap();
+        drop(storage);
+
+        let snapshot = SledStorage::new(SledStorageConfig {
+            path: data_dir.join("snapshot"),
+        })
+        .unwrap();
+        assert_eq!(
+            snapshot.contract_ids().unwrap(),
+            vec![genesis.contract_id()]
+        );
+        assert!(snapshot.transition_ids().unwrap().is_empty());
+    }
+}
@@ -11,39 +11,706 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use amplify::Wrapper;
+use bitcoin::hashes::{sha256, Hash};
 use bp::dbc::{Anchor, AnchorId};
 use commit_verify::lnpbp4::MerkleBlock;
 use rgb::prelude::*;
+use strict_encoding::StrictEncode;
 
+use super::Txn;
 use crate::error::ServiceErrorDomain;
+use crate::stashd::index::Index;
+
+/// Kinds of objects kept by a [`Store`]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum ObjectKind {
+    #[display("schema")]
+    Schema,
+
+    #[display("genesis")]
+    Genesis,
+
+    #[display("anchor")]
+    Anchor,
+
+    #[display("transition")]
+    Transition,
+
+    #[display("extension")]
+    Extension,
+}
+
+impl ObjectKind {
+    /// All kinds of the objects, in the order they depend on each other
+    pub const ALL: [ObjectKind; 5] = [
+        ObjectKind::Schema,
+        ObjectKind::Genesis,
+        ObjectKind::Anchor,
+        ObjectKind::Transition,
+        ObjectKind::Extension,
+    ];
+}
+
+/// Outcome of adding an object with one of `Store::add_*_sized` methods
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(Debug)]
+pub struct AddOutcome {
+    /// Whether an object with the same id was already present in the store
+    pub existed: bool,
+    /// Length of the strict encoding of the added object
+    pub bytes: usize,
+}
+
+/// Transition computed by [`Store::transition_or_insert_with`] has an id
+/// different from the requested one
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display("computed transition {found} is stored under a different id {expected}")]
+pub struct IdMismatch {
+    /// Id of the transition requested by the caller
+    pub expected: NodeId,
+    /// Id of the transition computed by the caller
+    pub found: NodeId,
+}
+
+impl From<IdMismatch> for ServiceErrorDomain {
+    fn from(err: IdMismatch) -> Self { ServiceErrorDomain::Storage(err.to_string()) }
+}
+
+/// Returns length of the strict encoding of the object; fails only for the
+/// objects which can't be encoded, since writing into a sink can't fail
+fn encoded_len(object: &impl StrictEncode) -> Result<usize, strict_encoding::Error> {
+    object.strict_encode(io::sink())
+}
+
+/// Tells whether both objects have the same strict encoding; objects which
+/// can't be encoded are never equal
+fn same_encoding(a: &impl StrictEncode, b: &impl StrictEncode) -> bool {
+    match (a.strict_serialize(), b.strict_serialize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
 
 pub trait Store {
-    type Error: ::std::error::Error + Into<ServiceErrorDomain>;
+    type Error: ::std::error::Error + Into<ServiceErrorDomain> + From<strict_encoding::Error>;
+
+    /// Tells whether the error reports that the requested object is absent
+    /// from the store
+    fn is_not_found(_err: &Self::Error) -> bool
+    where Self: Sized {
+        false
+    }
 
     fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error>;
+    fn schema_count(&self) -> Result<usize, Self::Error> { Ok(self.schema_ids()?.len()) }
+    /// Returns ids of the stored schemata which bech32 representation (the
+    /// one shown to the users) starts with `prefix`, allowing to look the
+    /// schemata up by the first characters of their ids. The default
+    /// implementation filters all [`Store::schema_ids`].
+    fn schema_ids_with_prefix(&self, prefix: &str) -> Result<Vec<SchemaId>, Self::Error> {
+        Ok(self
+            .schema_ids()?
+            .into_iter()
+            .filter(|id| id.to_bech32().to_string().starts_with(prefix))
+            .collect())
+    }
     fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error>;
     fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error>;
     fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error>;
+    /// Same as [`Store::add_schema`], additionally reporting encoded length of
+    /// the schema
+    fn add_schema_sized(&mut self, schema: &Schema) -> Result<AddOutcome, Self::Error> {
+        let bytes = encoded_len(schema)?;
+        let existed = self.add_schema(schema)?;
+        Ok(AddOutcome { existed, bytes })
+    }
+    /// Replaces the `old` schema with the `new` one, but only if the store
+    /// keeps exactly the `old` one (compared by the strict encoding).
+    /// Returns whether the replacement was made; nothing is written
+    /// otherwise. If the `new` object has a different id, the `old` one is
+    /// removed.
+    ///
+    /// Ids of the stored objects commit to their data, so the comparison
+    /// may only tell apart copies of the same object with different parts
+    /// revealed (like seals of a state transition). A schema id commits to
+    /// the whole schema, so for schemata the check is a mere presence check.
+    ///
+    /// The write is applied with [`Store::apply_txn`], so it is atomic on
+    /// backends applying transactions atomically, but the check is done
+    /// before it with separate reads. Thus this is a compare-and-swap only
+    /// as far as all writers go through the same exclusive borrow of the
+    /// store, like the single write lock of [`super::ShareableStore`]; the
+    /// default implementation gives no guarantees against other processes
+    /// writing into the same data.
+    fn replace_schema(&mut self, old: &Schema, new: &Schema) -> Result<bool, Self::Error> {
+        let id = old.schema_id();
+        if !self.has_schema(&id)? || !same_encoding(&self.schema(&id)?, old) {
+            return Ok(false);
+        }
+        let mut txn = Txn::new();
+        txn.add_schema(new);
+        if new.schema_id() != id {
+            txn.remove_schema(&id);
+        }
+        self.apply_txn(txn)?;
+        Ok(true)
+    }
+    /// Adds the schema only if the store has no schema with the same id,
+    /// returning `None` in this case. Otherwise nothing is written and the
+    /// stored schema is returned, so objects received repeatedly are not
+    /// rewritten, while the caller can still compare the stored object with
+    /// the received one.
+    fn add_schema_if_absent(&mut self, schema: &Schema) -> Result<Option<Schema>, Self::Error> {
+        let id = schema.schema_id();
+        if self.has_schema(&id)? {
+            return Ok(Some(self.schema(&id)?));
+        }
+        self.add_schema(schema)?;
+        Ok(None)
+    }
     fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error>;
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        for id in self.schema_ids()? {
+            self.remove_schema(&id)?;
+        }
+        Ok(())
+    }
 
     fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error>;
+    fn contract_count(&self) -> Result<usize, Self::Error> { Ok(self.contract_ids()?.len()) }
+    /// Returns ids of all stored contracts as a set, allowing to compute the
+    /// difference with the contracts known to a peer without querying
+    /// [`Store::has_genesis`] per id
+    fn contract_id_set(&self) -> Result<BTreeSet<ContractId>, Self::Error> {
+        Ok(self.contract_ids()?.into_iter().collect())
+    }
+    /// Returns ids of the stored contracts which bech32 representation starts
+    /// with `prefix`; see [`Store::schema_ids_with_prefix`]
+    fn contract_ids_with_prefix(&self, prefix: &str) -> Result<Vec<ContractId>, Self::Error> {
+        Ok(self
+            .contract_ids()?
+            .into_iter()
+            .filter(|id| id.to_bech32().to_string().starts_with(prefix))
+            .collect())
+    }
+    /// Returns ids of the contracts which use the schema with the provided
+    /// id. The store does not index contracts by schema, so the default
+    /// implementation loads all geneses one by one and filters them; geneses
+    /// removed while the scan goes are skipped.
+    fn contract_ids_by_schema(&self, schema_id: &SchemaId) -> Result<Vec<ContractId>, Self::Error>
+    where Self: Sized {
+        let mut ids = vec![];
+        for id in self.contract_ids()? {
+            match self.genesis(&id) {
+                Ok(genesis) if genesis.schema_id() == *schema_id => ids.push(id),
+                Ok(_) => {}
+                Err(err) if Self::is_not_found(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(ids)
+    }
     fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error>;
+    /// Loads genesis of the contract together with the schema it references.
+    /// A schema which is absent while the genesis is present means the store
+    /// is inconsistent; this is reported as [`ServiceErrorDomain::Schema`],
+    /// distinct from [`ServiceErrorDomain::NotFound`] returned for an unknown
+    /// contract.
+    fn schema_for_genesis(
+        &self,
+        contract_id: &ContractId,
+    ) -> Result<(Genesis, Schema), ServiceErrorDomain>
+    where
+        Self: Sized,
+    {
+        let genesis = self.genesis(contract_id).map_err(Self::Error::into)?;
+        let schema_id = genesis.schema_id();
+        match self.schema(&schema_id) {
+            Ok(schema) => Ok((genesis, schema)),
+            Err(err) if Self::is_not_found(&err) => Err(ServiceErrorDomain::Schema(format!(
+                "schema {} referenced by the genesis of contract {} is absent from the store",
+                schema_id, contract_id
+            ))),
+            Err(err) => Err(err.into()),
+        }
+    }
     fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error>;
     fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error>;
+    /// Same as [`Store::add_genesis`], additionally reporting encoded length of
+    /// the genesis
+    fn add_genesis_sized(&mut self, genesis: &Genesis) -> Result<AddOutcome, Self::Error> {
+        let bytes = encoded_len(genesis)?;
+        let existed = self.add_genesis(genesis)?;
+        Ok(AddOutcome { existed, bytes })
+    }
+    /// Replaces the `old` genesis with the `new` one if the store keeps
+    /// exactly the `old` one; see [`Store::replace_schema`]
+    fn replace_genesis(&mut self, old: &Genesis, new: &Genesis) -> Result<bool, Self::Error> {
+        let id = old.contract_id();
+        if !self.has_genesis(&id)? || !same_encoding(&self.genesis(&id)?, old) {
+            return Ok(false);
+        }
+        let mut txn = Txn::new();
+        txn.add_genesis(new);
+        if new.contract_id() != id {
+            txn.remove_genesis(&id);
+        }
+        self.apply_txn(txn)?;
+        Ok(true)
+    }
+    /// Adds the genesis only if the store has none with the same id, or
+    /// returns the stored one; see [`Store::add_schema_if_absent`]
+    fn add_genesis_if_absent(&mut self, genesis: &Genesis) -> Result<Option<Genesis>, Self::Error> {
+        let id = genesis.contract_id();
+        if self.has_genesis(&id)? {
+            return Ok(Some(self.genesis(&id)?));
+        }
+        self.add_genesis(genesis)?;
+        Ok(None)
+    }
     fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error>;
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        for id in self.contract_ids()? {
+            self.remove_genesis(&id)?;
+        }
+        Ok(())
+    }
 
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error>;
+    fn anchor_count(&self) -> Result<usize, Self::Error> { Ok(self.anchor_ids()?.len()) }
+    /// Returns ids of all stored anchors as a set; see [`Store::contract_id_set`]
+    fn anchor_id_set(&self) -> Result<BTreeSet<AnchorId>, Self::Error> {
+        Ok(self.anchor_ids()?.into_iter().collect())
+    }
     fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error>;
+    /// Loads all anchors one by one, passing each of them to `f`, so only a
+    /// single anchor is kept in memory at a time. Stops on the first error
+    /// returned by `f` or by the store; anchors removed while the iteration
+    /// goes are skipped.
+    fn for_each_anchor<E, F>(&self, mut f: F) -> Result<(), E>
+    where
+        Self: Sized,
+        E: From<Self::Error>,
+        F: FnMut(AnchorId, Anchor<MerkleBlock>) -> Result<(), E>,
+    {
+        for id in self.anchor_ids()? {
+            match self.anchor(&id) {
+                Ok(anchor) => f(id, anchor)?,
+                Err(err) if Self::is_not_found(&err) => {}
+                Err(err) => return Err(E::from(err)),
+            }
+        }
+        Ok(())
+    }
     fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error>;
     fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error>;
+    /// Same as [`Store::add_anchor`], additionally reporting encoded length of
+    /// the anchor
+    fn add_anchor_sized(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<AddOutcome, Self::Error> {
+        let bytes = encoded_len(anchor)?;
+        let existed = self.add_anchor(anchor)?;
+        Ok(AddOutcome { existed, bytes })
+    }
+    /// Replaces the `old` anchor with the `new` one if the store keeps
+    /// exactly the `old` one; see [`Store::replace_schema`]
+    fn replace_anchor(
+        &mut self,
+        old: &Anchor<MerkleBlock>,
+        new: &Anchor<MerkleBlock>,
+    ) -> Result<bool, Self::Error> {
+        let id = old.anchor_id();
+        if !self.has_anchor(&id)? || !same_encoding(&self.anchor(&id)?, old) {
+            return Ok(false);
+        }
+        let mut txn = Txn::new();
+        txn.add_anchor(new);
+        if new.anchor_id() != id {
+            txn.remove_anchor(&id);
+        }
+        self.apply_txn(txn)?;
+        Ok(true)
+    }
+    /// Adds the anchor only if the store has none with the same id, or
+    /// returns the stored one; see [`Store::add_schema_if_absent`]
+    fn add_anchor_if_absent(
+        &mut self,
+        anchor: &Anchor<MerkleBlock>,
+    ) -> Result<Option<Anchor<MerkleBlock>>, Self::Error> {
+        let id = anchor.anchor_id();
+        if self.has_anchor(&id)? {
+            return Ok(Some(self.anchor(&id)?));
+        }
+        self.add_anchor(anchor)?;
+        Ok(None)
+    }
     fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error>;
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        for id in self.anchor_ids()? {
+            self.remove_anchor(&id)?;
+        }
+        Ok(())
+    }
 
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error>;
+    fn transition_count(&self) -> Result<usize, Self::Error> { Ok(self.transition_ids()?.len()) }
+    /// Returns ids of all stored transitions as a set; see [`Store::contract_id_set`]
+    fn transition_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        Ok(self.transition_ids()?.into_iter().collect())
+    }
     fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error>;
+    /// Same as [`Store::for_each_anchor`], but for state transitions
+    fn for_each_transition<E, F>(&self, mut f: F) -> Result<(), E>
+    where
+        Self: Sized,
+        E: From<Self::Error>,
+        F: FnMut(NodeId, Transition) -> Result<(), E>,
+    {
+        for id in self.transition_ids()? {
+            match self.transition(&id) {
+                Ok(transition) => f(id, transition)?,
+                Err(err) if Self::is_not_found(&err) => {}
+                Err(err) => return Err(E::from(err)),
+            }
+        }
+        Ok(())
+    }
     fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error>;
     fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error>;
+    /// Same as [`Store::add_transition`], additionally reporting encoded
+    /// length of the state transition
+    fn add_transition_sized(&mut self, transition: &Transition) -> Result<AddOutcome, Self::Error> {
+        let bytes = encoded_len(transition)?;
+        let existed = self.add_transition(transition)?;
+        Ok(AddOutcome { existed, bytes })
+    }
+    /// Replaces the `old` state transition with the `new` one if the store keeps
+    /// exactly the `old` one; see [`Store::replace_schema`]
+    fn replace_transition(
+        &mut self,
+        old: &Transition,
+        new: &Transition,
+    ) -> Result<bool, Self::Error> {
+        let id = old.node_id();
+        if !self.has_transition(&id)? || !same_encoding(&self.transition(&id)?, old) {
+            return Ok(false);
+        }
+        let mut txn = Txn::new();
+        txn.add_transition(new);
+        if new.node_id() != id {
+            txn.remove_transition(&id);
+        }
+        self.apply_txn(txn)?;
+        Ok(true)
+    }
+    /// Adds a batch of transitions, returning how many of them were not
+    /// present in the store before
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        let mut created = 0;
+        for transition in transitions {
+            if !self.add_transition(transition)? {
+                created += 1;
+            }
+        }
+        Ok(created)
+    }
+    /// Loads those of the transitions with the provided ids which are present
+    /// in the store; missing ids are skipped
+    fn transitions_many(
+        &self,
+        ids: &[NodeId],
+    ) -> Result<BTreeMap<NodeId, Transition>, Self::Error> {
+        let mut transitions = BTreeMap::new();
+        for id in ids {
+            if self.has_transition(id)? {
+                transitions.insert(*id, self.transition(id)?);
+            }
+        }
+        Ok(transitions)
+    }
+    /// Returns subset of the provided transition ids which are present in the
+    /// store
+    fn has_transitions(&self, ids: &[NodeId]) -> Result<BTreeSet<NodeId>, Self::Error> {
+        let mut present = BTreeSet::new();
+        for id in ids {
+            if self.has_transition(id)? {
+                present.insert(*id);
+            }
+        }
+        Ok(present)
+    }
+    /// Loads all state transitions of the contract. Their ids are taken from
+    /// [`Index::transition_ids_by_contract`] if the index tracks contracts,
+    /// in which case transitions absent from the store are skipped.
+    /// Otherwise all stored transitions are scanned, selecting those which
+    /// descend from the contract genesis, either directly or through other
+    /// transitions and state extensions of the same contract.
+    fn transitions_by_contract<I: Index>(
+        &self,
+        contract_id: &ContractId,
+        index: &I,
+    ) -> Result<Vec<Transition>, ServiceErrorDomain>
+    where
+        Self: Sized,
+    {
+        if let Some(ids) = index
+            .transition_ids_by_contract(*contract_id)
+            .map_err(I::Error::into)?
+        {
+            return Ok(self
+                .transitions_many(&ids)
+                .map_err(Self::Error::into)?
+                .into_values()
+                .collect());
+        }
+
+        let mut known = BTreeSet::new();
+        known.insert(NodeId::from_inner(contract_id.into_inner()));
+        for id in self.extension_ids().map_err(Self::Error::into)? {
+            let extension = self.extension(&id).map_err(Self::Error::into)?;
+            if extension.contract_id() == Some(*contract_id) {
+                known.insert(id);
+            }
+        }
+
+        let mut pending = vec![];
+        for id in self.transition_ids().map_err(Self::Error::into)? {
+            pending.push(self.transition(&id).map_err(Self::Error::into)?);
+        }
+        // Each pass picks transitions spending outputs of the nodes already
+        // known to belong to the contract, until no more can be found
+        let mut transitions = vec![];
+        loop {
+            let (found, rest): (Vec<_>, Vec<_>) = pending.into_iter().partition(|transition| {
+                transition
+                    .parent_owned_rights()
+                    .iter()
+                    .map(|(id, _)| id)
+                    .chain(transition.parent_public_rights().iter().map(|(id, _)| id))
+                    .any(|id| known.contains(id))
+            });
+            pending = rest;
+            if found.is_empty() {
+                break;
+            }
+            known.extend(found.iter().map(Transition::node_id));
+            transitions.extend(found);
+        }
+        Ok(transitions)
+    }
+    /// Adds the state transition only if the store has none with the same id, or
+    /// returns the stored one; see [`Store::add_schema_if_absent`]
+    fn add_transition_if_absent(
+        &mut self,
+        transition: &Transition,
+    ) -> Result<Option<Transition>, Self::Error> {
+        let id = transition.node_id();
+        if self.has_transition(&id)? {
+            return Ok(Some(self.transition(&id)?));
+        }
+        self.add_transition(transition)?;
+        Ok(None)
+    }
+    /// Returns the stored state transition, or computes it with `f` if it is
+    /// absent, storing and returning the computed one. The store is queried
+    /// once, without [`Store::has_transition`]. Errors of the store are
+    /// converted into the error type of `f`.
+    ///
+    /// `f` must compute the transition with the requested id; otherwise
+    /// nothing is stored and [`IdMismatch`] is returned.
+    fn transition_or_insert_with<E, F>(&mut self, id: &NodeId, f: F) -> Result<Transition, E>
+    where
+        Self: Sized,
+        E: From<Self::Error> + From<IdMismatch>,
+        F: FnOnce() -> Result<Transition, E>,
+    {
+        match self.transition(id) {
+            Ok(transition) => return Ok(transition),
+            Err(err) if Self::is_not_found(&err) => {}
+            Err(err) => return Err(E::from(err)),
+        }
+        let transition = f()?;
+        let found = transition.node_id();
+        if found != *id {
+            return Err(E::from(IdMismatch {
+                expected: *id,
+                found,
+            }));
+        }
+        self.add_transition(&transition)?;
+        Ok(transition)
+    }
     fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error>;
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        for id in self.transition_ids()? {
+            self.remove_transition(&id)?;
+        }
+        Ok(())
+    }
 
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error>;
+    fn extension_count(&self) -> Result<usize, Self::Error> { Ok(self.extension_ids()?.len()) }
+    /// Returns ids of all stored extensions as a set; see [`Store::contract_id_set`]
+    fn extension_id_set(&self) -> Result<BTreeSet<NodeId>, Self::Error> {
+        Ok(self.extension_ids()?.into_iter().collect())
+    }
     fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error>;
+    /// Same as [`Store::for_each_anchor`], but for state extensions
+    fn for_each_extension<E, F>(&self, mut f: F) -> Result<(), E>
+    where
+        Self: Sized,
+        E: From<Self::Error>,
+        F: FnMut(NodeId, Extension) -> Result<(), E>,
+    {
+        for id in self.extension_ids()? {
+            match self.extension(&id) {
+                Ok(extension) => f(id, extension)?,
+                Err(err) if Self::is_not_found(&err) => {}
+                Err(err) => return Err(E::from(err)),
+            }
+        }
+        Ok(())
+    }
     fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error>;
     fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error>;
+    /// Same as [`Store::add_extension`], additionally reporting encoded
+    /// length of the state extension
+    fn add_extension_sized(&mut self, extension: &Extension) -> Result<AddOutcome, Self::Error> {
+        let bytes = encoded_len(extension)?;
+        let existed = self.add_extension(extension)?;
+        Ok(AddOutcome { existed, bytes })
+    }
+    /// Replaces the `old` state extension with the `new` one if the store keeps
+    /// exactly the `old` one; see [`Store::replace_schema`]
+    fn replace_extension(&mut self, old: &Extension, new: &Extension) -> Result<bool, Self::Error> {
+        let id = old.node_id();
+        if !self.has_extension(&id)? || !same_encoding(&self.extension(&id)?, old) {
+            return Ok(false);
+        }
+        let mut txn = Txn::new();
+        txn.add_extension(new);
+        if new.node_id() != id {
+            txn.remove_extension(&id);
+        }
+        self.apply_txn(txn)?;
+        Ok(true)
+    }
+    /// Adds the state extension only if the store has none with the same id, or
+    /// returns the stored one; see [`Store::add_schema_if_absent`]
+    fn add_extension_if_absent(
+        &mut self,
+        extension: &Extension,
+    ) -> Result<Option<Extension>, Self::Error> {
+        let id = extension.node_id();
+        if self.has_extension(&id)? {
+            return Ok(Some(self.extension(&id)?));
+        }
+        self.add_extension(extension)?;
+        Ok(None)
+    }
     fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error>;
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        for id in self.extension_ids()? {
+            self.remove_extension(&id)?;
+        }
+        Ok(())
+    }
+
+    /// Removes all the data from the store, keeping the store itself (like
+    /// data directories) in place
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.clear_schemata()?;
+        self.clear_geneses()?;
+        self.clear_anchors()?;
+        self.clear_transitions()?;
+        self.clear_extensions()
+    }
+
+    /// Makes all the data written to the store so far durable, so that it
+    /// survives a crash or a power loss. Consumers should call it before
+    /// shutting down instead of relying on [`Drop`] of the store.
+    ///
+    /// The default implementation does nothing, suiting stores which persist
+    /// each write before reporting success.
+    fn flush(&mut self) -> Result<(), Self::Error> { Ok(()) }
+
+    /// Runs the closure staging mutations of the store in the provided
+    /// [`Txn`], and applies them with [`Store::apply_txn`] only if the
+    /// closure succeeds; mutations staged by a failed closure are discarded.
+    /// Errors of the store are converted into the error type of the closure.
+    fn transaction<T, E>(&mut self, f: impl FnOnce(&mut Txn) -> Result<T, E>) -> Result<T, E>
+    where
+        Self: Sized,
+        E: From<Self::Error>,
+    {
+        let mut txn = Txn::new();
+        let res = f(&mut txn)?;
+        self.apply_txn(txn)?;
+        Ok(res)
+    }
+
+    /// Applies mutations staged in the transaction, in the order they were
+    /// staged.
+    ///
+    /// The default implementation applies them one by one, so a failure (or
+    /// a crash) in the middle leaves the mutations preceding it applied;
+    /// backends able to apply a batch atomically, like
+    /// [`super::DiskStorage`] with its journal undoing both additions and
+    /// removals, override it.
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> { txn.apply_to(self) }
+}
+
+/// Stores able to report when the objects were last written; used for
+/// incremental replication of the stash data
+pub trait StoreMeta: Store {
+    fn schema_modified(&self, id: &SchemaId) -> Result<SystemTime, Self::Error>;
+    fn genesis_modified(&self, id: &ContractId) -> Result<SystemTime, Self::Error>;
+    fn anchor_modified(&self, id: &AnchorId) -> Result<SystemTime, Self::Error>;
+    fn transition_modified(&self, id: &NodeId) -> Result<SystemTime, Self::Error>;
+    fn extension_modified(&self, id: &NodeId) -> Result<SystemTime, Self::Error>;
+}
+
+/// Stores able to copy all of their data into another location while they
+/// are in use, allowing backups without stopping the node
+pub trait SnapshotStore: Store {
+    /// Writes a consistent point-in-time copy of the store data into the
+    /// `dest` directory, which must not exist yet. The copy can be opened as
+    /// a store of the same type.
+    fn snapshot(&self, dest: &Path) -> Result<(), Self::Error>;
+}
+
+/// Identifier of a consignment kept by a [`ConsignmentStore`], holding the
+/// same hash as the id returned by [`Consignment::id`]. Unlike the latter it
+/// is displayed and parsed as hex.
+#[derive(Wrapper, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, From)]
+#[wrapper(FromStr, LowerHex)]
+#[display(inner)]
+pub struct ConsignmentId(sha256::Hash);
+
+impl ConsignmentId {
+    /// Computes id of the consignment
+    pub fn from_consignment(consignment: &Consignment) -> Self {
+        ConsignmentId(
+            sha256::Hash::from_slice(&consignment.id()[..])
+                .expect("consignment id is a 32-byte hash"),
+        )
+    }
+}
+
+/// Stores able to keep full consignments verbatim, in addition to their
+/// decomposed parts, so they can be re-forwarded without being reassembled
+pub trait ConsignmentStore: Store {
+    fn consignment_ids(&self) -> Result<Vec<ConsignmentId>, Self::Error>;
+    fn consignment(&self, id: &ConsignmentId) -> Result<Consignment, Self::Error>;
+    fn has_consignment(&self, id: &ConsignmentId) -> Result<bool, Self::Error>;
+    fn add_consignment(&mut self, consignment: &Consignment) -> Result<bool, Self::Error>;
+    fn remove_consignment(&mut self, id: &ConsignmentId) -> Result<bool, Self::Error>;
 }
@@ -0,0 +1,364 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+
+use super::{Store, Txn};
+use crate::error::ServiceErrorDomain;
+
+/// Combines a local [`Store`] with a remote fallback one, populating the
+/// local store from the remote on read misses.
+///
+/// Reads of an object absent from the local store (as reported by
+/// [`Store::is_not_found`]) are served by the remote store, and the fetched
+/// object is written into the local one; objects missing from both stores
+/// are reported with [`ServiceErrorDomain::NotFound`]. A failure to write the
+/// fetched object locally is logged and does not fail the read. Presence
+/// checks fall back to the remote store as well, but do not populate the
+/// local one.
+///
+/// All writes, removals, enumerations and counts concern the local store
+/// only: the remote store is never modified, and objects removed locally
+/// can still be read from it.
+///
+/// Errors of both stores are converted into [`ServiceErrorDomain`], which
+/// must map their not-found errors into [`ServiceErrorDomain::NotFound`], as
+/// it does for all the stores of this crate.
+#[derive(Debug)]
+pub struct TieredStore<L: Store, R: Store> {
+    local: RwLock<L>,
+    remote: R,
+}
+
+impl<L: Store, R: Store> TieredStore<L, R> {
+    pub fn new(local: L, remote: R) -> Self {
+        Self {
+            local: RwLock::new(local),
+            remote,
+        }
+    }
+
+    #[inline]
+    pub fn remote(&self) -> &R { &self.remote }
+
+    pub fn into_inner(self) -> (L, R) {
+        let local = self
+            .local
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+        (local, self.remote)
+    }
+
+    /// Locks the local store for reading
+    pub fn local(&self) -> RwLockReadGuard<'_, L> {
+        // A panic in the local store leaves it in a state no worse than an
+        // I/O error would, so poisoning is ignored
+        self.local.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn local_mut(&self) -> RwLockWriteGuard<'_, L> {
+        self.local.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Reads object from the local store, falling back to the remote one and
+    /// keeping the object fetched from there with `keep`
+    fn get<T>(
+        &self,
+        local: impl FnOnce(&L) -> Result<T, L::Error>,
+        remote: impl FnOnce(&R) -> Result<T, R::Error>,
+        keep: impl FnOnce(&mut L, &T) -> Result<bool, L::Error>,
+    ) -> Result<T, ServiceErrorDomain> {
+        match local(&self.local()) {
+            Ok(object) => return Ok(object),
+            Err(err) if L::is_not_found(&err) => {}
+            Err(err) => return Err(err.into()),
+        }
+        let object = remote(&self.remote).map_err(R::Error::into)?;
+        if let Err(err) = keep(&mut self.local_mut(), &object) {
+            warn!(
+                "Unable to keep object fetched from the remote store locally: {}",
+                err
+            );
+        }
+        Ok(object)
+    }
+
+    /// Checks presence of the object in the local store, falling back to the
+    /// remote one
+    fn has(
+        &self,
+        local: impl FnOnce(&L) -> Result<bool, L::Error>,
+        remote: impl FnOnce(&R) -> Result<bool, R::Error>,
+    ) -> Result<bool, ServiceErrorDomain> {
+        if local(&self.local()).map_err(L::Error::into)? {
+            return Ok(true);
+        }
+        remote(&self.remote).map_err(R::Error::into)
+    }
+}
+
+impl<L: Store, R: Store> Store for TieredStore<L, R> {
+    type Error = ServiceErrorDomain;
+
+    fn is_not_found(err: &Self::Error) -> bool { matches!(err, ServiceErrorDomain::NotFound) }
+
+    fn schema_ids(&self) -> Result<Vec<SchemaId>, Self::Error> {
+        self.local().schema_ids().map_err(L::Error::into)
+    }
+
+    fn schema_count(&self) -> Result<usize, Self::Error> {
+        self.local().schema_count().map_err(L::Error::into)
+    }
+
+    fn schema(&self, id: &SchemaId) -> Result<Schema, Self::Error> {
+        self.get(
+            |local| local.schema(id),
+            |remote| remote.schema(id),
+            |local, schema| local.add_schema(schema),
+        )
+    }
+
+    fn has_schema(&self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.has(|local| local.has_schema(id), |remote| remote.has_schema(id))
+    }
+
+    fn add_schema(&mut self, schema: &Schema) -> Result<bool, Self::Error> {
+        self.local_mut().add_schema(schema).map_err(L::Error::into)
+    }
+
+    fn remove_schema(&mut self, id: &SchemaId) -> Result<bool, Self::Error> {
+        self.local_mut().remove_schema(id).map_err(L::Error::into)
+    }
+
+    fn clear_schemata(&mut self) -> Result<(), Self::Error> {
+        self.local_mut().clear_schemata().map_err(L::Error::into)
+    }
+
+    fn contract_ids(&self) -> Result<Vec<ContractId>, Self::Error> {
+        self.local().contract_ids().map_err(L::Error::into)
+    }
+
+    fn contract_count(&self) -> Result<usize, Self::Error> {
+        self.local().contract_count().map_err(L::Error::into)
+    }
+
+    fn genesis(&self, id: &ContractId) -> Result<Genesis, Self::Error> {
+        self.get(
+            |local| local.genesis(id),
+            |remote| remote.genesis(id),
+            |local, genesis| local.add_genesis(genesis),
+        )
+    }
+
+    fn has_genesis(&self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.has(
+            |local| local.has_genesis(id),
+            |remote| remote.has_genesis(id),
+        )
+    }
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<bool, Self::Error> {
+        self.local_mut()
+            .add_genesis(genesis)
+            .map_err(L::Error::into)
+    }
+
+    fn remove_genesis(&mut self, id: &ContractId) -> Result<bool, Self::Error> {
+        self.local_mut().remove_genesis(id).map_err(L::Error::into)
+    }
+
+    fn clear_geneses(&mut self) -> Result<(), Self::Error> {
+        self.local_mut().clear_geneses().map_err(L::Error::into)
+    }
+
+    fn anchor_ids(&self) -> Result<Vec<AnchorId>, Self::Error> {
+        self.local().anchor_ids().map_err(L::Error::into)
+    }
+
+    fn anchor_count(&self) -> Result<usize, Self::Error> {
+        self.local().anchor_count().map_err(L::Error::into)
+    }
+
+    fn anchor(&self, id: &AnchorId) -> Result<Anchor<MerkleBlock>, Self::Error> {
+        self.get(
+            |local| local.anchor(id),
+            |remote| remote.anchor(id),
+            |local, anchor| local.add_anchor(anchor),
+        )
+    }
+
+    fn has_anchor(&self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.has(|local| local.has_anchor(id), |remote| remote.has_anchor(id))
+    }
+
+    fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) -> Result<bool, Self::Error> {
+        self.local_mut().add_anchor(anchor).map_err(L::Error::into)
+    }
+
+    fn remove_anchor(&mut self, id: &AnchorId) -> Result<bool, Self::Error> {
+        self.local_mut().remove_anchor(id).map_err(L::Error::into)
+    }
+
+    fn clear_anchors(&mut self) -> Result<(), Self::Error> {
+        self.local_mut().clear_anchors().map_err(L::Error::into)
+    }
+
+    fn transition_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.local().transition_ids().map_err(L::Error::into)
+    }
+
+    fn transition_count(&self) -> Result<usize, Self::Error> {
+        self.local().transition_count().map_err(L::Error::into)
+    }
+
+    fn transition(&self, id: &NodeId) -> Result<Transition, Self::Error> {
+        self.get(
+            |local| local.transition(id),
+            |remote| remote.transition(id),
+            |local, transition| local.add_transition(transition),
+        )
+    }
+
+    fn has_transition(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.has(
+            |local| local.has_transition(id),
+            |remote| remote.has_transition(id),
+        )
+    }
+
+    fn add_transition(&mut self, transition: &Transition) -> Result<bool, Self::Error> {
+        self.local_mut()
+            .add_transition(transition)
+            .map_err(L::Error::into)
+    }
+
+    fn add_transitions(&mut self, transitions: &[Transition]) -> Result<usize, Self::Error> {
+        self.local_mut()
+            .add_transitions(transitions)
+            .map_err(L::Error::into)
+    }
+
+    fn remove_transition(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.local_mut()
+            .remove_transition(id)
+            .map_err(L::Error::into)
+    }
+
+    fn clear_transitions(&mut self) -> Result<(), Self::Error> {
+        self.local_mut().clear_transitions().map_err(L::Error::into)
+    }
+
+    fn extension_ids(&self) -> Result<Vec<NodeId>, Self::Error> {
+        self.local().extension_ids().map_err(L::Error::into)
+    }
+
+    fn extension_count(&self) -> Result<usize, Self::Error> {
+        self.local().extension_count().map_err(L::Error::into)
+    }
+
+    fn extension(&self, id: &NodeId) -> Result<Extension, Self::Error> {
+        self.get(
+            |local| local.extension(id),
+            |remote| remote.extension(id),
+            |local, extension| local.add_extension(extension),
+        )
+    }
+
+    fn has_extension(&self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.has(
+            |local| local.has_extension(id),
+            |remote| remote.has_extension(id),
+        )
+    }
+
+    fn add_extension(&mut self, extension: &Extension) -> Result<bool, Self::Error> {
+        self.local_mut()
+            .add_extension(extension)
+            .map_err(L::Error::into)
+    }
+
+    fn remove_extension(&mut self, id: &NodeId) -> Result<bool, Self::Error> {
+        self.local_mut()
+            .remove_extension(id)
+            .map_err(L::Error::into)
+    }
+
+    fn clear_extensions(&mut self) -> Result<(), Self::Error> {
+        self.local_mut().clear_extensions().map_err(L::Error::into)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.local_mut().flush().map_err(L::Error::into)
+    }
+
+    fn apply_txn(&mut self, txn: Txn) -> Result<(), Self::Error> {
+        self.local_mut().apply_txn(txn).map_err(L::Error::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stashd::storage::MemoryStorage;
+
+    #[test]
+    fn test_read_through() {
+        let genesis = Genesis::default();
+        let contract_id = genesis.contract_id();
+        let mut remote = MemoryStorage::new();
+        remote.add_genesis(&genesis).unwrap();
+        let mut store = TieredStore::new(MemoryStorage::new(), remote);
+
+        assert_eq!(store.contract_ids().ok(), Some(vec![]));
+        assert_eq!(store.has_genesis(&contract_id).ok(), Some(true));
+        assert_eq!(store.genesis(&contract_id).ok(), Some(genesis.clone()));
+        assert_eq!(store.contract_ids().ok(), Some(vec![contract_id]));
+        assert!(store.local().has_genesis(&contract_id).unwrap());
+
+        let transition = Transition::default();
+        assert_eq!(store.add_transition(&transition).ok(), Some(false));
+        assert!(!store
+            .remote()
+            .has_transition(&transition.node_id())
+            .unwrap());
+        assert!(matches!(
+            store.extension(&Extension::default().node_id()),
+            Err(ServiceErrorDomain::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_txn_local_only() {
+        let genesis = Genesis::default();
+        let contract_id = genesis.contract_id();
+        let mut remote = MemoryStorage::new();
+        remote.add_genesis(&genesis).unwrap();
+        let mut store = TieredStore::new(MemoryStorage::new(), remote);
+
+        let extension = Extension::default();
+        assert!(store
+            .transaction::<_, ServiceErrorDomain>(|txn| {
+                txn.add_extension(&extension);
+                txn.remove_genesis(&contract_id);
+                Ok(())
+            })
+            .is_ok());
+        assert!(store.local().has_extension(&extension.node_id()).unwrap());
+        assert!(!store.remote().has_extension(&extension.node_id()).unwrap());
+        assert!(store.remote().has_genesis(&contract_id).unwrap());
+    }
+}
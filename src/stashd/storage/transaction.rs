@@ -0,0 +1,127 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use bp::dbc::{Anchor, AnchorId};
+use commit_verify::lnpbp4::MerkleBlock;
+use rgb::prelude::*;
+
+use super::Store;
+
+/// Mutation of the store staged by a [`Txn`]
+#[derive(Clone, PartialEq, Debug)]
+pub enum TxnOp {
+    AddSchema(Schema),
+    RemoveSchema(SchemaId),
+    AddGenesis(Genesis),
+    RemoveGenesis(ContractId),
+    AddAnchor(Anchor<MerkleBlock>),
+    RemoveAnchor(AnchorId),
+    AddTransition(Transition),
+    RemoveTransition(NodeId),
+    AddExtension(Extension),
+    RemoveExtension(NodeId),
+}
+
+/// Mutations of the store staged by the closure passed to
+/// [`Store::transaction`], which are applied with [`Store::apply_txn`] in
+/// the order they were staged once the closure succeeds.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Txn {
+    ops: Vec<TxnOp>,
+}
+
+impl Txn {
+    #[inline]
+    pub fn new() -> Self { Txn::default() }
+
+    #[inline]
+    pub fn len(&self) -> usize { self.ops.len() }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.ops.is_empty() }
+
+    #[inline]
+    pub fn ops(&self) -> &[TxnOp] { &self.ops }
+
+    #[inline]
+    pub fn into_ops(self) -> Vec<TxnOp> { self.ops }
+
+    /// Stages addition of the schema; unlike [`Store::add_schema`], it can't
+    /// report whether the schema is already stored
+    #[inline]
+    pub fn add_schema(&mut self, schema: &Schema) {
+        self.ops.push(TxnOp::AddSchema(schema.clone()))
+    }
+
+    #[inline]
+    pub fn remove_schema(&mut self, id: &SchemaId) { self.ops.push(TxnOp::RemoveSchema(*id)) }
+
+    #[inline]
+    pub fn add_genesis(&mut self, genesis: &Genesis) {
+        self.ops.push(TxnOp::AddGenesis(genesis.clone()))
+    }
+
+    #[inline]
+    pub fn remove_genesis(&mut self, id: &ContractId) { self.ops.push(TxnOp::RemoveGenesis(*id)) }
+
+    #[inline]
+    pub fn add_anchor(&mut self, anchor: &Anchor<MerkleBlock>) {
+        self.ops.push(TxnOp::AddAnchor(anchor.clone()))
+    }
+
+    #[inline]
+    pub fn remove_anchor(&mut self, id: &AnchorId) { self.ops.push(TxnOp::RemoveAnchor(*id)) }
+
+    #[inline]
+    pub fn add_transition(&mut self, transition: &Transition) {
+        self.ops.push(TxnOp::AddTransition(transition.clone()))
+    }
+
+    #[inline]
+    pub fn add_transitions(&mut self, transitions: &[Transition]) {
+        self.ops
+            .extend(transitions.iter().cloned().map(TxnOp::AddTransition))
+    }
+
+    #[inline]
+    pub fn remove_transition(&mut self, id: &NodeId) { self.ops.push(TxnOp::RemoveTransition(*id)) }
+
+    #[inline]
+    pub fn add_extension(&mut self, extension: &Extension) {
+        self.ops.push(TxnOp::AddExtension(extension.clone()))
+    }
+
+    #[inline]
+    pub fn remove_extension(&mut self, id: &NodeId) { self.ops.push(TxnOp::RemoveExtension(*id)) }
+
+    /// Applies the staged mutations to the store one by one, stopping at the
+    /// first failure; used by the default implementation of
+    /// [`Store::apply_txn`]
+    pub fn apply_to<S: Store + ?Sized>(self, store: &mut S) -> Result<(), S::Error> {
+        for op in self.ops {
+            match op {
+                TxnOp::AddSchema(schema) => store.add_schema(&schema),
+                TxnOp::RemoveSchema(id) => store.remove_schema(&id),
+                TxnOp::AddGenesis(genesis) => store.add_genesis(&genesis),
+                TxnOp::RemoveGenesis(id) => store.remove_genesis(&id),
+                TxnOp::AddAnchor(anchor) => store.add_anchor(&anchor),
+                TxnOp::RemoveAnchor(id) => store.remove_anchor(&id),
+                TxnOp::AddTransition(transition) => store.add_transition(&transition),
+                TxnOp::RemoveTransition(id) => store.remove_transition(&id),
+                TxnOp::AddExtension(extension) => store.add_extension(&extension),
+                TxnOp::RemoveExtension(id) => store.remove_extension(&id),
+            }?;
+        }
+        Ok(())
+    }
+}
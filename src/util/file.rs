@@ -1,7 +1,8 @@
 use core::convert::TryFrom;
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs, io, process};
 
 use bp::dbc::Anchor;
 use commit_verify::lnpbp4::MerkleBlock;
@@ -27,6 +28,57 @@ pub fn file(filename: impl AsRef<Path>, mode: FileMode) -> Result<fs::File, io::
     }
 }
 
+/// Extension used by temporary files created with [`temp_filename`]
+pub const TEMP_FILE_EXT: &str = "tmp";
+
+/// Constructs name for a temporary sibling of the provided file, which can be
+/// used to write data before atomically renaming it into the final location.
+///
+/// The name has the form `<filename>.<pid>-<seq>.tmp`, where `seq` is a
+/// process-wide counter, so concurrent writers from the same or different
+/// processes never share the same temporary file.
+pub fn temp_filename(filename: impl AsRef<Path>) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let filename = filename.as_ref();
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut name = filename
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    name.push(format!(".{}-{}.{}", process::id(), seq, TEMP_FILE_EXT));
+    filename.with_file_name(name)
+}
+
+/// Checks whether the file name has the form produced by [`temp_filename`]
+pub fn is_temp_filename(name: &str) -> bool {
+    let stem = match name.strip_suffix(TEMP_FILE_EXT) {
+        Some(stem) => stem,
+        None => return false,
+    };
+    let suffix = match stem
+        .strip_suffix('.')
+        .and_then(|stem| stem.rsplit('.').next())
+    {
+        Some(suffix) => suffix,
+        None => return false,
+    };
+    let mut parts = suffix.splitn(2, '-');
+    let is_number = |part: Option<&str>| matches!(part, Some(part) if !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    // The original name must not be empty and precedes the suffix
+    stem.len() > suffix.len() + 2 && is_number(parts.next()) && is_number(parts.next())
+}
+
+/// Flushes directory entries (like newly created or renamed files) to the
+/// disk. On non-unix systems directories can't be opened and synced, so this
+/// is a no-op there.
+pub fn sync_dir(dir: impl AsRef<Path>) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
 pub fn read_file(filename: PathBuf) -> Result<(u32, Vec<u8>), io::Error> {
     let mut data = vec![];
     let mut file = file(filename, FileMode::Read)?;
@@ -41,34 +93,147 @@ pub fn read_dir_filenames(
     dir: PathBuf,
     filter_extensions: Option<&str>,
 ) -> Result<Vec<String>, io::Error> {
-    let mut list = vec![];
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    iter_dir_filenames(dir, filter_extensions)?.collect()
+}
+
+/// Same as [`read_dir_filenames`], but additionally returns paths of the
+/// files which names are not valid unicode, which are skipped by the former
+pub fn read_dir_filenames_with_ignored(
+    dir: PathBuf,
+    filter_extensions: Option<&str>,
+) -> Result<(Vec<String>, Vec<PathBuf>), io::Error> {
+    let mut names = vec![];
+    let mut ignored = vec![];
+    for entry in iter_dir_entries(dir, filter_extensions)? {
+        match entry? {
+            Ok(name) => names.push(name),
+            Err(path) => ignored.push(path),
+        }
+    }
+    Ok((names, ignored))
+}
+
+/// Lazy version of [`read_dir_filenames`], which reads directory entries only
+/// as the returned iterator advances
+pub fn iter_dir_filenames<'a>(
+    dir: PathBuf,
+    filter_extensions: Option<&'a str>,
+) -> Result<impl Iterator<Item = Result<String, io::Error>> + 'a, io::Error> {
+    Ok(
+        iter_dir_entries(dir, filter_extensions)?.filter_map(|entry| match entry {
+            Ok(Ok(name)) => Some(Ok(name)),
+            // Names which are not valid unicode can't be our files
+            Ok(Err(path)) => {
+                debug!("Skipping file with non-unicode name {:?}", path);
+                None
+            }
+            Err(err) => Some(Err(err)),
+        }),
+    )
+}
+
+/// Lists names of the files inside `dir` having the extension, returning
+/// paths of the files which names are not valid unicode as `Err`
+fn iter_dir_entries<'a>(
+    dir: PathBuf,
+    filter_extensions: Option<&'a str>,
+) -> Result<impl Iterator<Item = Result<Result<String, PathBuf>, io::Error>> + 'a, io::Error> {
+    Ok(fs::read_dir(dir)?.filter_map(move |entry| {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => return Some(Err(err)),
+        };
         if let Some(ext) = filter_extensions {
-            if ext != path.extension().map(|s| s.to_str().unwrap()).unwrap_or("") {
-                continue;
+            if Some(ext) != path.extension().and_then(|s| s.to_str()) {
+                return None;
             }
         }
-        if !path.is_dir() {
-            if let Some(name) = path.file_name().map(|s| s.to_str().unwrap().to_string()) {
-                list.push(name);
-            }
+        if path.is_dir() {
+            return None;
         }
-    }
-    Ok(list)
+        let name = path.file_name()?;
+        Some(Ok(name.to_str().map(str::to_owned).ok_or(path.clone())))
+    }))
 }
 
+/// First bytes of a zstd frame, used to detect compressed files
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
 pub trait ReadWrite
 where Self: Sized
 {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error>;
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error>;
+    /// Decodes the object incrementally from the reader, so the memory used
+    /// is bounded by the size of the decoded object rather than the size of
+    /// the whole input
+    fn read_stream(reader: impl Read) -> Result<Self, Error>;
+
+    /// Encodes the object directly into the writer, without an intermediary
+    /// buffer keeping the whole encoding
+    fn write_stream(&self, writer: impl Write) -> Result<usize, Error>;
+
+    /// Reads the object from a file, transparently decompressing it if the
+    /// file was written with [`ReadWrite::write_file_compressed`]
+    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read_buffered(io::BufReader::new(file(filename, FileMode::Read)?))
+    }
+
+    /// Same as [`ReadWrite::read_file`], but decodes the object directly
+    /// from the memory-mapped file instead of copying its data into buffers
+    #[cfg(feature = "mmap")]
+    fn read_mapped(filename: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = file(filename, FileMode::Read)?;
+        // Safety: files are replaced by renaming new files into their place
+        // and never modified in place, so the mapped data can't change while
+        // being decoded
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::read_buffered(&map[..])
+    }
+
+    /// Reads the object from the file data, detecting compression as
+    /// [`ReadWrite::read_file`] does
+    fn read_buffered(mut reader: impl BufRead) -> Result<Self, Error> {
+        if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+            #[cfg(feature = "compression")]
+            return Self::read_stream(zstd::Decoder::with_buffer(reader)?);
+            #[cfg(not(feature = "compression"))]
+            return Err(Error::DataIntegrityError(
+                "File is compressed, but compression support is not compiled in".to_string(),
+            ));
+        }
+        Self::read_stream(reader)
+    }
+
+    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
+        let mut writer = io::BufWriter::new(file(filename, FileMode::Create)?);
+        let len = self.write_stream(&mut writer)?;
+        writer.flush()?;
+        Ok(len)
+    }
+
+    /// Writes the object into a zstd-compressed file with the given
+    /// compression `level`
+    #[cfg(feature = "compression")]
+    fn write_file_compressed(
+        &self,
+        filename: impl AsRef<Path>,
+        level: i32,
+    ) -> Result<usize, Error> {
+        self.write_compressed(file(filename, FileMode::Create)?, level)
+    }
+
+    /// Same as [`ReadWrite::write_file_compressed`], but writes compressed
+    /// data into the writer
+    #[cfg(feature = "compression")]
+    fn write_compressed(&self, writer: impl Write, level: i32) -> Result<usize, Error> {
+        let mut encoder = zstd::Encoder::new(writer, level)?;
+        let len = self.write_stream(&mut encoder)?;
+        encoder.finish()?.flush()?;
+        Ok(len)
+    }
 }
 
 impl ReadWrite for Schema {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -87,16 +252,14 @@ impl ReadWrite for Schema {
         Schema::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Schema.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Schema.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
 
 impl ReadWrite for Genesis {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -115,16 +278,14 @@ impl ReadWrite for Genesis {
         Genesis::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Genesis.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Genesis.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
 
 impl ReadWrite for Anchor<MerkleBlock> {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -143,16 +304,14 @@ impl ReadWrite for Anchor<MerkleBlock> {
         Anchor::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Anchor.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Anchor.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
 
 impl ReadWrite for Transition {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -171,16 +330,14 @@ impl ReadWrite for Transition {
         Transition::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Transition.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Transition.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
 
 impl ReadWrite for Extension {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -199,16 +356,14 @@ impl ReadWrite for Extension {
         Extension::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Extension.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Extension.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
 
 impl ReadWrite for Consignment {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -227,16 +382,14 @@ impl ReadWrite for Consignment {
         Consignment::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Consignment.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Consignment.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
 
 impl ReadWrite for Disclosure {
-    fn read_file(filename: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = file(filename, FileMode::Read)?;
+    fn read_stream(mut file: impl Read) -> Result<Self, Error> {
         let mut magic_buf = [0u8; 4];
         file.read_exact(&mut magic_buf)?;
         let magic = u32::from_be_bytes(magic_buf);
@@ -255,9 +408,8 @@ impl ReadWrite for Disclosure {
         Disclosure::strict_decode(file)
     }
 
-    fn write_file(&self, filename: impl AsRef<Path>) -> Result<usize, Error> {
-        let mut file = file(filename, FileMode::Create)?;
-        file.write(&MagicNumber::Disclosure.to_u32().to_be_bytes())?;
+    fn write_stream(&self, mut file: impl Write) -> Result<usize, Error> {
+        file.write_all(&MagicNumber::Disclosure.to_u32().to_be_bytes())?;
         self.strict_encode(file)
     }
 }
@@ -58,6 +58,14 @@ pub enum MagicNumber {
     /// Equals to first 4 bytes of SHA256("rgb:stash")
     /// = cd22a2cb85720d51f1616752cb85059a02f3d35f7dda30a4ca981b59b0924354
     Stash = 0xcd22a2cb,
+
+    /// Equals to first 4 bytes of SHA256("rgb:index")
+    /// = 3e74c0644d3c5978439a5696ac68f5b917a0938d11fa0a81393ff8eb36a34176
+    Index = 0x3e74c064,
+
+    /// Equals to first 4 bytes of SHA256("rgb:oplog")
+    /// = 2f101767881582566f11a3afc0d86afae809d928f64863a295b283592b6bb71b
+    OperationLog = 0x2f101767,
 }
 
 impl MagicNumber {
@@ -78,10 +86,13 @@ impl TryFrom<u32> for MagicNumber {
             n if n == Self::Schema.to_u32() => Self::Schema,
             n if n == Self::Genesis.to_u32() => Self::Genesis,
             n if n == Self::Transition.to_u32() => Self::Transition,
+            n if n == Self::Extension.to_u32() => Self::Extension,
             n if n == Self::Anchor.to_u32() => Self::Anchor,
             n if n == Self::Consignment.to_u32() => Self::Consignment,
             n if n == Self::Disclosure.to_u32() => Self::Disclosure,
             n if n == Self::Stash.to_u32() => Self::Stash,
+            n if n == Self::Index.to_u32() => Self::Index,
+            n if n == Self::OperationLog.to_u32() => Self::OperationLog,
             invalid => Err(invalid)?,
         })
     }
@@ -16,9 +16,13 @@ mod macros;
 mod bech32data;
 pub mod file;
 mod magic_numbers;
+#[cfg(test)]
+mod temp_path;
 // TODO: Consider deleting mod
 // mod seal_spec;
 
 pub use bech32data::{FromBech32Data, ToBech32Data};
 pub use magic_numbers::MagicNumber;
+#[cfg(test)]
+pub(crate) use temp_path::TempPath;
 // pub use seal_spec::SealSpec;
@@ -0,0 +1,56 @@
+// RGB standard library
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+/// Path inside [`env::temp_dir`] unique for the test and the test process,
+/// removed together with all its contents when dropped, so the tests clean up
+/// after themselves even when failing
+#[derive(Debug)]
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Takes `rgb-node-<name>-<pid>` path, removing whatever is left there by
+    /// an earlier run. Nothing is created at the path.
+    pub fn new(name: &str) -> TempPath {
+        let path = env::temp_dir().join(format!("rgb-node-{}-{}", name, process::id()));
+        remove(&path);
+        TempPath(path)
+    }
+}
+
+fn remove(path: &Path) {
+    if path.is_dir() {
+        let _ = fs::remove_dir_all(path);
+    } else {
+        let _ = fs::remove_file(path);
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    #[inline]
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl AsRef<Path> for TempPath {
+    #[inline]
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) { remove(&self.0); }
+}